@@ -0,0 +1,14 @@
+prints the alphabet on 2000 lines one character at a time
+
+>>>>>++++++++++<<<<<
+++++++++++
+[
+  >++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+  [
+    >++++++++++++++++++++++++++>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++<
+    [>.+<-]
+    >[-]>>.<<<
+    <-
+  ]
+  <-
+]
@@ -0,0 +1,18 @@
+nested counting loops with 120 * 120 * 120 iterations of a body made of
+a multiply loop and a move loop and a clear
+
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+[
+  >++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+  [
+    >++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+    [
+      >+++[>+++++<-]>[<+>-]<
+      [-]
+      <-
+    ]
+    <-
+  ]
+  <-
+]
+>>>>>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.----.>++++++++++.
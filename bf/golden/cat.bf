@@ -0,0 +1,3 @@
+# eof: zero
+copies its input to its output until end of file
+,[.,]
@@ -0,0 +1,4 @@
+# cell_width: 16
+counts a cell up to 256 and prints Y if it did not wrap to 0 (it would with 8 bit cells)
+++++++++++++++++[>++++++++++++++++<-]>
+[[-]>++++++++[<++++++++++>-]<+++++++++.[-]]
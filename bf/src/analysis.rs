@@ -0,0 +1,299 @@
+use crate::ops::{Program, OPS};
+use std::fmt;
+use std::ops::Range;
+
+/// Where the pointer can be, as offsets from some starting cell. `None` means
+/// there is no bound on that side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: Option<isize>,
+    pub max: Option<isize>,
+}
+
+impl Bounds {
+    pub fn exactly(offset: isize) -> Self {
+        Bounds {
+            min: Some(offset),
+            max: Some(offset),
+        }
+    }
+
+    /// The offset, if there is only one.
+    pub fn exact(&self) -> Option<isize> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => Some(min),
+            _ => None,
+        }
+    }
+
+    fn shift(self, distance: isize) -> Self {
+        self.plus(Bounds::exactly(distance))
+    }
+
+    /// Every sum of an offset in `self` and one in `other`.
+    fn plus(self, other: Bounds) -> Self {
+        Bounds {
+            min: self.min.zip(other.min).map(|(a, b)| a + b),
+            max: self.max.zip(other.max).map(|(a, b)| a + b),
+        }
+    }
+
+    fn join(self, other: Bounds) -> Self {
+        Bounds {
+            min: self.min.zip(other.min).map(|(a, b)| a.min(b)),
+            max: self.max.zip(other.max).map(|(a, b)| a.max(b)),
+        }
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min {
+            Some(min) => write!(f, "{:+}", min)?,
+            None => write!(f, "-inf")?,
+        }
+        match self.max {
+            Some(max) => write!(f, "..{:+}", max),
+            None => write!(f, "..+inf"),
+        }
+    }
+}
+
+/// What one loop does to the pointer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopInfo {
+    /// From the `[` to the `]` in the source.
+    pub span: Range<usize>,
+    /// How far one pass through the body moves the pointer, when that is
+    /// always the same.
+    pub balance: Option<isize>,
+    /// Where one pass through the body can take the pointer, relative to
+    /// where the pass started.
+    pub range: Bounds,
+}
+
+impl LoopInfo {
+    /// The body leaves the pointer where it found it, so every pass starts
+    /// on the same cell.
+    pub fn is_balanced(&self) -> bool {
+        self.balance == Some(0)
+    }
+}
+
+/// A `<` that can take the pointer left of the first cell, where it wraps
+/// around to the last one. Only counts when the analysis knows how far left
+/// the pointer can be: after a loop like `[<]` it doesn't, and every `<` would
+/// look like one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Underflow {
+    /// The byte the `<` is at in the source.
+    pub position: usize,
+    /// Whether it always does, whenever it runs.
+    pub certain: bool,
+}
+
+impl fmt::Display for Underflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the pointer {} left of the first cell at byte {} and wraps around to the end of the tape",
+            if self.certain { "moves" } else { "can move" },
+            self.position
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Analysis {
+    /// Every loop, in the order they start in the source.
+    pub loops: Vec<LoopInfo>,
+    /// Every cell the pointer can reach, relative to the first cell.
+    pub reach: Bounds,
+    /// The first `<` that can underflow the tape, if any is known to.
+    pub underflow: Option<Underflow>,
+}
+
+impl Analysis {
+    /// Whether the pointer provably stays on a tape of `tape_size` cells
+    /// without wrapping, so nothing needs to wrap it.
+    pub fn stays_within(&self, tape_size: usize) -> bool {
+        matches!(
+            (self.reach.min, self.reach.max),
+            (Some(min), Some(max)) if min >= 0 && (max as usize) < tape_size
+        )
+    }
+}
+
+struct Analyser<'a> {
+    program: &'a Program,
+    /// How far a pass through each loop's body moves the pointer and where
+    /// it goes on the way, by the index of the loop's `[`.
+    effects: Vec<Option<(Bounds, Bounds)>>,
+    loops: Vec<LoopInfo>,
+    underflow: Option<Underflow>,
+}
+
+impl Analyser<'_> {
+    /// Walks the ops from `start` to `end` with the pointer somewhere in
+    /// `position`, and returns where it can end up and everywhere it can go
+    /// on the way. With `record`, also notes the loops and the first
+    /// underflow, which only make sense for real positions.
+    fn walk(
+        &mut self,
+        start: usize,
+        end: usize,
+        mut position: Bounds,
+        record: bool,
+    ) -> (Bounds, Bounds) {
+        let mut reach = position;
+        let mut i = start;
+        while i < end {
+            match self.program.ops[i] {
+                OPS::ShiftRight => position = position.shift(1),
+                OPS::ShiftLeft => {
+                    position = position.shift(-1);
+                    if record && self.underflow.is_none() && position.min.is_some_and(|min| min < 0)
+                    {
+                        self.underflow = Some(Underflow {
+                            position: self.program.spans[i].start,
+                            certain: position.max.is_some_and(|max| max < 0),
+                        });
+                    }
+                }
+                OPS::LoopStart => {
+                    let close = self.program.jumps[i];
+                    let (displacement, range) = self.effect(i);
+                    // Passes start where the last one left off, so a body
+                    // that only ever moves right keeps the leftmost bound,
+                    // and one that only moves left keeps the rightmost.
+                    let passes = Bounds {
+                        min: position
+                            .min
+                            .filter(|_| displacement.min.is_some_and(|min| min >= 0)),
+                        max: position
+                            .max
+                            .filter(|_| displacement.max.is_some_and(|max| max <= 0)),
+                    };
+                    if record {
+                        self.loops.push(LoopInfo {
+                            span: self.program.spans[i].start..self.program.spans[close].end,
+                            balance: displacement.exact(),
+                            range,
+                        });
+                        self.walk(i + 1, close, passes, true);
+                    }
+                    reach = reach.join(passes.plus(range));
+                    position = passes;
+                    i = close;
+                }
+                _ => {}
+            }
+            reach = reach.join(position);
+            i += 1;
+        }
+        (position, reach)
+    }
+
+    /// What one pass through the body of the loop starting at `start` does,
+    /// relative to where the pass started.
+    fn effect(&mut self, start: usize) -> (Bounds, Bounds) {
+        if let Some(effect) = self.effects[start] {
+            return effect;
+        }
+        let effect = self.walk(
+            start + 1,
+            self.program.jumps[start],
+            Bounds::exactly(0),
+            false,
+        );
+        self.effects[start] = Some(effect);
+        effect
+    }
+}
+
+/// Works out where the pointer can go without running the program, starting
+/// from the first cell. Loops can run any number of times, including none.
+pub fn analyse(program: &Program) -> Analysis {
+    let mut analyser = Analyser {
+        program,
+        effects: vec![None; program.len()],
+        loops: Vec::new(),
+        underflow: None,
+    };
+    let (_, reach) = analyser.walk(0, program.len(), Bounds::exactly(0), true);
+    Analysis {
+        loops: analyser.loops,
+        reach,
+        underflow: analyser.underflow,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyse, Analysis, Bounds, Underflow};
+    use crate::ops::{Dialect, Program};
+
+    fn analysis(source: &str) -> Analysis {
+        analyse(&Program::parse(source, Dialect::Brainfuck).unwrap())
+    }
+
+    fn bounds(min: Option<isize>, max: Option<isize>) -> Bounds {
+        Bounds { min, max }
+    }
+
+    #[test]
+    fn balanced_loops_keep_the_pointer_in_reach() {
+        let analysis = analysis(">>[->+<]<");
+        assert_eq!(analysis.loops.len(), 1);
+        assert!(analysis.loops[0].is_balanced());
+        assert_eq!(analysis.loops[0].range, bounds(Some(0), Some(1)));
+        assert_eq!(analysis.reach, bounds(Some(0), Some(3)));
+        assert_eq!(analysis.underflow, None);
+        assert!(analysis.stays_within(4));
+    }
+
+    #[test]
+    fn scans_have_no_bound() {
+        let right = analysis(">[>]");
+        assert_eq!(right.loops[0].balance, Some(1));
+        assert!(!right.loops[0].is_balanced());
+        assert_eq!(right.reach, bounds(Some(0), None));
+        assert!(!right.stays_within(30000));
+
+        let left = analysis("+[<]");
+        assert_eq!(left.reach, bounds(None, Some(0)));
+        assert!(!left.stays_within(30000));
+        // Nothing is known about how far left the scan goes, so it isn't
+        // reported as an underflow.
+        assert_eq!(left.underflow, None);
+    }
+
+    #[test]
+    fn finds_underflows() {
+        assert_eq!(
+            analysis("+<").underflow,
+            Some(Underflow {
+                position: 1,
+                certain: true
+            })
+        );
+        assert_eq!(
+            analysis(",[>]<<").underflow,
+            Some(Underflow {
+                position: 4,
+                certain: false
+            })
+        );
+        assert_eq!(analysis("><").underflow, None);
+        assert!(!analysis("<>").stays_within(30000));
+    }
+
+    #[test]
+    fn reach_at_the_edge_of_the_tape() {
+        let edge = analysis(">>>[-]<<<");
+        assert_eq!(edge.reach, bounds(Some(0), Some(3)));
+        assert!(edge.stays_within(4));
+        assert!(!edge.stays_within(3));
+        assert!(analysis("").stays_within(1));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::config::Config;
+use crate::golden::{diff, Failure, GoldenTest};
+use crate::interpreter::{Interpreter, Termination};
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+use crate::jit::{Jit, JitIo};
+use crate::ops::Program;
+use crate::vm::Vm;
+use std::time::{Duration, Instant};
+
+/// How long one benchmark took on each engine, the fastest of `repeat` runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timing {
+    pub name: String,
+    pub interpreter: Duration,
+    pub vm: Duration,
+    /// `None` in builds without the `jit` feature.
+    pub jit: Option<Duration>,
+}
+
+impl Timing {
+    /// How many times faster than the interpreter the fastest engine was.
+    pub fn speedup(&self) -> f64 {
+        let fastest = self.jit.map_or(self.vm, |jit| jit.min(self.vm));
+        self.interpreter.as_secs_f64() / fastest.as_secs_f64().max(1e-9)
+    }
+}
+
+fn best_of<F: FnMut() -> Result<Vec<u8>, Failure>>(
+    repeat: usize,
+    mut run: F,
+) -> Result<(Duration, Vec<u8>), Failure> {
+    let mut best = Duration::MAX;
+    let mut output = Vec::new();
+    for _ in 0..repeat.max(1) {
+        let started = Instant::now();
+        output = run()?;
+        best = best.min(started.elapsed());
+    }
+    Ok((best, output))
+}
+
+/// Runs `test` on the interpreter and on the VM, checking that both produce
+/// the expected output.
+pub fn measure(test: &GoldenTest, repeat: usize) -> Result<Timing, Failure> {
+    let (program, headers, input) = test.load()?;
+    let finished = |termination| match termination {
+        Termination::Finished => Ok(()),
+        stopped => Err(Failure::Stopped(stopped)),
+    };
+    let (interpreter_time, interpreter_output) = best_of(repeat, || {
+        let mut interpreter = Interpreter::with_config(headers.config);
+        interpreter.load(program.clone());
+        interpreter.push_input(&input);
+        interpreter.close_input();
+        finished(interpreter.run())?;
+        Ok(interpreter.result_text.chars().map(|c| c as u8).collect())
+    })?;
+    test.check(&interpreter_output)?;
+    let (vm_time, vm_output) = best_of(repeat, || {
+        let mut vm = Vm::new(&program, headers.config);
+        vm.unchecked = true;
+        vm.push_input(&input);
+        vm.close_input();
+        finished(vm.run())?;
+        Ok(vm.output)
+    })?;
+    test.check(&vm_output)?;
+    if vm_output != interpreter_output {
+        return Err(Failure::Output(diff(&interpreter_output, &vm_output)));
+    }
+    let jit = match measure_jit(&program, headers.config, &input, repeat)? {
+        Some((jit_time, jit_output)) => {
+            if jit_output != interpreter_output {
+                return Err(Failure::Output(diff(&interpreter_output, &jit_output)));
+            }
+            Some(jit_time)
+        }
+        None => None,
+    };
+    Ok(Timing {
+        name: test.name.clone(),
+        interpreter: interpreter_time,
+        vm: vm_time,
+        jit,
+    })
+}
+
+/// Feeds the JIT from a buffer and collects what it prints.
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+pub(crate) struct BufferIo<'a> {
+    pub(crate) input: std::slice::Iter<'a, u8>,
+    pub(crate) output: Vec<u8>,
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+impl JitIo for BufferIo<'_> {
+    fn read(&mut self) -> Option<u8> {
+        self.input.next().copied()
+    }
+
+    fn write(&mut self, byte: u8) -> Option<Termination> {
+        self.output.push(byte);
+        None
+    }
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+fn measure_jit(
+    program: &Program,
+    config: Config,
+    input: &[u8],
+    repeat: usize,
+) -> Result<Option<(Duration, Vec<u8>)>, Failure> {
+    best_of(repeat, || {
+        let bytecode = crate::vm::compile(&crate::ir::optimise(program), config.tape_size);
+        let jit = Jit::compile(&bytecode, config)
+            .ok_or_else(|| Failure::Setup("the JIT can't compile this program".to_owned()))?;
+        let mut io = BufferIo {
+            input: input.iter(),
+            output: Vec::new(),
+        };
+        let (termination, _) = jit.run(&mut vec![0; config.tape_size], 0, &mut io);
+        match termination {
+            Termination::Finished => Ok(io.output),
+            stopped => Err(Failure::Stopped(stopped)),
+        }
+    })
+    .map(Some)
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64", unix)))]
+fn measure_jit(
+    _: &Program,
+    _: Config,
+    _: &[u8],
+    _: usize,
+) -> Result<Option<(Duration, Vec<u8>)>, Failure> {
+    Ok(None)
+}
@@ -0,0 +1,39 @@
+use super::{no_flags, one_path, parse, Loaded, Settings};
+use bf::analysis::analyse;
+use bf::codegen::Source;
+use std::error::Error;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let path = one_path(parse(args, Some(&mut settings), 1, no_flags)?)?;
+    let Loaded {
+        text,
+        program,
+        config,
+        ..
+    } = settings.read_program(&path)?;
+    let source = Source::new(&path, &text);
+    let analysis = analyse(&program);
+    for info in &analysis.loops {
+        let balance = match info.balance {
+            Some(0) => "balanced".to_owned(),
+            Some(balance) => format!("moves {:+} a pass", balance),
+            None => "moves an unknown distance a pass".to_owned(),
+        };
+        println!(
+            "{}:{}: loop {}, a pass reaches {}",
+            path,
+            source.line(info.span.start),
+            balance,
+            info.range
+        );
+    }
+    println!("pointer reaches {}", analysis.reach);
+    if analysis.stays_within(config.tape_size) {
+        println!("it never wraps around the {}-cell tape", config.tape_size);
+    }
+    if let Some(underflow) = analysis.underflow {
+        println!("{}", underflow);
+    }
+    Ok(0)
+}
@@ -0,0 +1,53 @@
+use super::{parse, value};
+use bf::bench::measure;
+use bf::golden::discover;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+const BENCH_REPEAT: usize = 3;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut repeat = BENCH_REPEAT;
+    let dir = parse(args, None, 1, |arg, args| {
+        match arg {
+            "--repeat" => repeat = value(args, arg)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?
+    .pop();
+    let dir = dir.unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/benches").to_owned());
+    let tests = discover(Path::new(&dir)).map_err(|e| format!("{}: {}", dir, e))?;
+    println!(
+        "{:<24} {:>14} {:>14} {:>14} {:>9}",
+        "program", "interpreter", "vm", "jit", "speedup"
+    );
+    let mut failed = 0;
+    let mut speedups = Vec::new();
+    for test in &tests {
+        match measure(test, repeat) {
+            Ok(timing) => {
+                let milliseconds = |time: Duration| format!("{:.3}ms", time.as_secs_f64() * 1000.0);
+                println!(
+                    "{:<24} {:>14} {:>14} {:>14} {:>8.1}x",
+                    timing.name,
+                    milliseconds(timing.interpreter),
+                    milliseconds(timing.vm),
+                    timing.jit.map_or("-".to_owned(), milliseconds),
+                    timing.speedup()
+                );
+                speedups.push(timing.speedup());
+            }
+            Err(failure) => {
+                failed += 1;
+                println!("{:<24} FAIL: {}", test.name, failure);
+            }
+        }
+    }
+    if !speedups.is_empty() {
+        let mean = speedups.iter().map(|s| s.ln()).sum::<f64>() / speedups.len() as f64;
+        println!("geometric mean speedup: {:.1}x", mean.exp());
+    }
+    Ok(if failed == 0 { 0 } else { 1 })
+}
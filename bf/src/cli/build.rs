@@ -0,0 +1,41 @@
+use super::{one_path, parse, value, Loaded, Settings};
+use bf::codegen::elf::executable;
+use bf::ir::optimise;
+use bf::vm::compile;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut output: Option<String> = None;
+    let path = one_path(parse(args, Some(&mut settings), 1, |arg, args| {
+        match arg {
+            "-o" => output = Some(value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let Loaded {
+        program, config, ..
+    } = settings.read_program(&path)?;
+    let bytecode = compile(&optimise(&program), config.tape_size);
+    let binary = executable(&bytecode, config).ok_or("the tape is too big for an executable")?;
+    let output =
+        output.unwrap_or_else(|| Path::new(&path).with_extension("").display().to_string());
+    if output == path {
+        return Err(format!(
+            "{} would overwrite the program, pick another name with -o",
+            output
+        )
+        .into());
+    }
+    fs::write(&output, binary).map_err(|e| format!("{}: {}", output, e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("{}: {}", output, e))?;
+    }
+    Ok(0)
+}
@@ -0,0 +1,49 @@
+use super::{one_path, parse, value, Loaded, Settings};
+use bf::codegen::{generate, Source, Target};
+use bf::ir::optimise;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut target = Target::C;
+    let mut output: Option<String> = None;
+    let path = one_path(parse(args, Some(&mut settings), 1, |arg, args| {
+        match arg {
+            "--target" => target = value(args, arg)?,
+            "-o" => output = Some(value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let Loaded {
+        text,
+        program,
+        config,
+        ..
+    } = settings.read_program(&path)?;
+    let name = Path::new(&path)
+        .file_name()
+        .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+    let code = generate(
+        target,
+        &optimise(&program),
+        config,
+        &Source::new(&name, &text),
+    )
+    .ok_or("the tape is too big for this target")?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(&path)
+            .with_extension(target.extension())
+            .display()
+            .to_string()
+    });
+    if output == "-" {
+        io::stdout().write_all(&code)?;
+    } else {
+        fs::write(&output, code).map_err(|e| format!("{}: {}", output, e))?;
+    }
+    Ok(0)
+}
@@ -0,0 +1,25 @@
+use super::{no_flags, one_path, parse, Loaded, Settings};
+use bf::codegen::Source;
+use bf::decompile::decompile;
+use bf::ir::optimise;
+use std::error::Error;
+use std::path::Path;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let path = one_path(parse(args, Some(&mut settings), 1, no_flags)?)?;
+    let Loaded {
+        text,
+        program,
+        config,
+        ..
+    } = settings.read_program(&path)?;
+    let name = Path::new(&path)
+        .file_name()
+        .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+    print!(
+        "{}",
+        decompile(&optimise(&program), config, &Source::new(&name, &text))
+    );
+    Ok(0)
+}
@@ -0,0 +1,90 @@
+use super::{parse, value, Loaded, Settings, USAGE};
+use bf::equiv::{compare, reads_input, Inputs};
+use bf::interpreter::Limits;
+use std::error::Error;
+
+const EQUIV_MAX_STEPS: u64 = 1_000_000;
+const EQUIV_MAX_OUTPUT: usize = 1_000_000;
+/// How much of each output a difference shows.
+const EQUIV_SHOWN: usize = 64;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut inputs = Inputs::default();
+    let paths = parse(args, Some(&mut settings), 2, |arg, args| {
+        match arg {
+            "--exhaustive" => inputs.exhaustive = value(args, arg)?,
+            "--random" => inputs.random = value(args, arg)?,
+            "--seed" => inputs.seed = value(args, arg)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let [path_a, path_b] = <[String; 2]>::try_from(paths).map_err(|_| USAGE)?;
+    let Loaded {
+        program: a,
+        config,
+        limits,
+        ..
+    } = settings.read_program(&path_a)?;
+    let Loaded {
+        program: b,
+        config: config_b,
+        ..
+    } = settings.read_program(&path_b)?;
+    if config != config_b {
+        return Err(format!(
+            "{} and {} ask for different machines in their headers; \
+             pick one with --cell-width, --eof and --tape-size",
+            path_a, path_b
+        )
+        .into());
+    }
+    let limits = limits.or(Limits {
+        max_steps: Some(EQUIV_MAX_STEPS),
+        max_output_bytes: Some(EQUIV_MAX_OUTPUT),
+        ..Limits::default()
+    });
+    let comparison = compare(&a, &b, config, limits, inputs);
+    match comparison.difference {
+        None if !reads_input(&a) && !reads_input(&b) => {
+            println!("{} and {} agree (neither reads any input)", path_a, path_b);
+            Ok(0)
+        }
+        None => {
+            println!(
+                "{} and {} agree on all {} inputs tried",
+                path_a, path_b, comparison.tried
+            );
+            Ok(0)
+        }
+        Some(difference) => {
+            println!(
+                "{} and {} differ on input \"{}\" (after {} inputs):",
+                path_a,
+                path_b,
+                difference.input.escape_ascii(),
+                comparison.tried
+            );
+            for (path, run) in [(&path_a, &difference.a), (&path_b, &difference.b)] {
+                let shown = &run.output[..run.output.len().min(EQUIV_SHOWN)];
+                let more = if shown.len() < run.output.len() {
+                    format!("... ({} bytes)", run.output.len())
+                } else {
+                    String::new()
+                };
+                println!(
+                    "  {} printed \"{}\"{} and {}",
+                    path,
+                    shown.escape_ascii(),
+                    more,
+                    run.termination
+                );
+            }
+            if let Some(at) = difference.output_differs_at() {
+                println!("  the outputs first differ at byte {}", at);
+            }
+            Ok(1)
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use super::{parse, value};
+use bf::fuzz::{self, Verdict};
+use bf::rng::Rng;
+use std::error::Error;
+
+const FUZZ_COUNT: usize = 1000;
+const FUZZ_LENGTH: usize = 40;
+const FUZZ_MAX_STEPS: u64 = 100_000;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut count = FUZZ_COUNT;
+    let mut seed = None;
+    let mut length = FUZZ_LENGTH;
+    let mut max_steps = FUZZ_MAX_STEPS;
+    parse(args, None, 0, |arg, args| {
+        match arg {
+            "--count" => count = value(args, arg)?,
+            "--seed" => seed = Some(value(args, arg)?),
+            "--length" => length = value(args, arg)?,
+            "--max-steps" => max_steps = value(args, arg)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let seed = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+    });
+    let engines = fuzz::engines();
+    let names: Vec<String> = engines.iter().map(|engine| engine.to_string()).collect();
+    println!(
+        "seed {}, checking {} against the interpreter",
+        seed,
+        names.join(", ")
+    );
+    let mut rng = Rng::new(seed);
+    let (mut skipped, mut disagreements) = (0, 0);
+    for _ in 0..count {
+        match fuzz::check(&fuzz::case(&mut rng, length), &engines, max_steps) {
+            Verdict::Agreed => {}
+            Verdict::Skipped => skipped += 1,
+            Verdict::Disagreed(disagreement) => {
+                disagreements += 1;
+                let disagreement = fuzz::minimise(*disagreement, max_steps);
+                println!(
+                    "{} disagrees with the interpreter on {}",
+                    disagreement.engine, disagreement.case
+                );
+                println!(
+                    "  interpreter printed \"{}\"",
+                    disagreement.expected.escape_ascii()
+                );
+                match &disagreement.actual {
+                    Ok(output) => println!(
+                        "  {} printed \"{}\"",
+                        disagreement.engine,
+                        output.escape_ascii()
+                    ),
+                    Err(e) => println!("  {} failed: {}", disagreement.engine, e),
+                }
+            }
+        }
+    }
+    println!(
+        "{} programs, {} disagreements, {} skipped because the interpreter didn't finish them",
+        count, disagreements, skipped
+    );
+    Ok(if disagreements == 0 { 0 } else { 1 })
+}
@@ -0,0 +1,334 @@
+mod analyse;
+mod bench;
+mod build;
+mod compile;
+mod decompile;
+mod equiv;
+mod fuzz;
+mod reduce;
+mod replay;
+mod run;
+mod solve;
+mod test;
+
+use bf::codegen::Source;
+use bf::config::{parse_headers, CellWidth, Config, EofPolicy};
+use bf::interpreter::{Limits, Termination};
+use bf::ops::{Dialect, Program};
+use bf::sourcemap::SourceMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+const USAGE: &str = "usage:
+    bf                  start the GUI
+    bf run <program> [settings] [--load-state FILE] [--save-state FILE]
+                     [--trace FILE [--trace-every N | --trace-loops]] [--record FILE]
+                     [--engine auto|interpreter|vm|jit] [--break BYTE]...
+    bf replay <program> <transcript> [settings]
+    bf compile <program> [--target c|js|llvm|py|rust|wasm|wasi] [-o FILE] [settings]
+                        translate the program, to <program>.<target> by default
+                        (-o - writes to stdout)
+    bf decompile <program> [settings]
+                        print the program as structured pseudo-code
+    bf analyse <program> [settings]
+                        show how each loop moves the pointer and how far the whole
+                        program can move it, without running it
+    bf solve <program> (--reach BYTE | --output TEXT) [--max-input N] [--max-passes N]
+             [settings]
+                        look for an input that runs the op at byte BYTE of the file, or
+                        makes the program print exactly TEXT, by running it with the
+                        input as unknowns (inputs up to 16 bytes and loops going round
+                        up to 1000 times by default)
+    bf equiv <a> <b> [--exhaustive N] [--random N] [--seed N] [settings]
+                        run both programs on every input up to N bytes (1 by default)
+                        and on random longer ones (1000 by default), and show the first
+                        input where their output or the way they stop differs
+    bf fuzz [--count N] [--seed N] [--length N] [--max-steps N]
+                        run N random programs (1000 by default) of about --length ops
+                        on random machines and inputs through the interpreter and every
+                        other engine in this build, and show each disagreement shrunk
+                        down as far as it goes
+    bf reduce <program> (--differs ENGINE | --panics | --fails TEXT | --prints TEXT
+                         | --command CMD) [--engine ENGINE] [--input FILE] [-o FILE]
+                         [settings]
+                        cut the program down to as few ops as still do the thing: an
+                        engine (vm|jit|wasm|wasi|native) prints something else than the
+                        interpreter, running it on --engine (the interpreter by default)
+                        panics, stops with a message containing TEXT or prints TEXT, or
+                        `CMD file` exits with 0. prints the result, or writes it to -o
+    bf build <program> [-o FILE] [settings]
+                        make a static Linux x86-64 executable, <program> without its
+                        extension by default
+    bf test <dir> [limits] [--wasm]
+                        run every .bf/.bf2 under dir on its .in, compare with its .out;
+                        --wasm also compiles each one to WebAssembly, runs it in an
+                        embedded runtime and compares with the interpreter (needs the
+                        wasm-runtime feature)
+    bf bench [dir] [--repeat N]
+                        time the interpreter against the VM (and the JIT) on every program
+                        under dir (the bundled benches/ by default)
+
+settings:
+    --fnord             read the program as BrainFNORD2 (the default for .bf2 files)
+    --max-steps N, --max-output N, --max-cells N, --timeout-ms N
+                        stop the program when it runs into one of these limits
+    --cell-width 8|16|32, --eof unchanged|zero|max, --tape-size N
+                        override the program's `# key: value` headers
+    --source-map FILE   the map bf2bf wrote when it converted the program: errors and
+                        where it stopped are shown in the file it was converted from
+                        too, and --break BYTE is a byte of that file
+    (bf test uses the limits as defaults for tests whose headers don't set them)
+
+engines:
+    auto picks the JIT (in builds with the jit feature) or else the bytecode VM,
+    unless the run needs the interpreter: step or cell limits, --trace, --record,
+    --break, --load-state or --save-state. the JIT can't time out, so --timeout-ms uses the VM";
+
+pub fn main(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "run" => run::command(&args[1..]),
+        "replay" => replay::command(&args[1..]),
+        "test" => test::command(&args[1..]),
+        "bench" => bench::command(&args[1..]),
+        "compile" => compile::command(&args[1..]),
+        "decompile" => decompile::command(&args[1..]),
+        "analyse" => analyse::command(&args[1..]),
+        "solve" => solve::command(&args[1..]),
+        "equiv" => equiv::command(&args[1..]),
+        "fuzz" => fuzz::command(&args[1..]),
+        "reduce" => reduce::command(&args[1..]),
+        "build" => build::command(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        _ => Err(USAGE.into()),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("bf: {}", e);
+            1
+        }
+    }
+}
+
+type Args<'a> = std::slice::Iter<'a, String>;
+
+fn value<T>(args: &mut Args, flag: &str) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    let text = args
+        .next()
+        .ok_or_else(|| format!("{} needs a value", flag))?;
+    text.parse()
+        .map_err(|e| format!("bad value {:?} for {}: {}", text, flag, e).into())
+}
+
+/// Goes through a command's arguments, taking the shared settings if it has
+/// any and up to `max_paths` paths. Its own flags go to `flag`, which says
+/// whether it took the one it was given.
+fn parse(
+    args: &[String],
+    mut settings: Option<&mut Settings>,
+    max_paths: usize,
+    mut flag: impl FnMut(&str, &mut Args) -> Result<bool, Box<dyn Error>>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(settings) = settings.as_deref_mut() {
+            if settings.flag(arg, &mut args)? {
+                continue;
+            }
+        }
+        if flag(arg, &mut args)? {
+            continue;
+        }
+        if paths.len() < max_paths && !arg.starts_with("--") {
+            paths.push(arg.clone());
+            continue;
+        }
+        return Err(format!("unexpected argument {:?}\n{}", arg, USAGE).into());
+    }
+    Ok(paths)
+}
+
+/// For commands with no flags of their own.
+fn no_flags(_: &str, _: &mut Args) -> Result<bool, Box<dyn Error>> {
+    Ok(false)
+}
+
+/// The one path a command needs.
+fn one_path(mut paths: Vec<String>) -> Result<String, Box<dyn Error>> {
+    paths.pop().ok_or_else(|| USAGE.into())
+}
+
+fn dialect_for(path: &Path) -> Dialect {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("bf2") => Dialect::BrainFnord2,
+        _ => Dialect::Brainfuck,
+    }
+}
+
+/// The flags shared by every command that runs a program. Settings given
+/// here win over the program's `# key: value` headers.
+#[derive(Default)]
+struct Settings {
+    fnord: bool,
+    limits: Limits,
+    cell_width: Option<CellWidth>,
+    eof: Option<EofPolicy>,
+    tape_size: Option<usize>,
+    source_map: Option<String>,
+}
+
+/// A program file, read and parsed.
+struct Loaded {
+    text: String,
+    program: Program,
+    config: Config,
+    limits: Limits,
+}
+
+impl Settings {
+    /// Takes `arg` (and its value) if it is one of the shared flags.
+    fn flag(&mut self, arg: &str, args: &mut Args) -> Result<bool, Box<dyn Error>> {
+        match arg {
+            "--fnord" => self.fnord = true,
+            "--max-steps" => self.limits.max_steps = Some(value(args, arg)?),
+            "--max-output" => self.limits.max_output_bytes = Some(value(args, arg)?),
+            "--max-cells" => self.limits.max_cells_touched = Some(value(args, arg)?),
+            "--timeout-ms" => self.limits.timeout = Some(Duration::from_millis(value(args, arg)?)),
+            "--cell-width" => self.cell_width = Some(value(args, arg)?),
+            "--eof" => self.eof = Some(value(args, arg)?),
+            "--tape-size" => self.tape_size = Some(value(args, arg)?),
+            "--source-map" => self.source_map = Some(value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn dialect(&self, path: &str) -> Dialect {
+        if self.fnord {
+            Dialect::BrainFnord2
+        } else {
+            dialect_for(Path::new(path))
+        }
+    }
+
+    fn read_program(&self, path: &str) -> Result<Loaded, Box<dyn Error>> {
+        let dialect = self.dialect(path);
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let headers = parse_headers(&text).map_err(|e| format!("{}: {}", path, e))?;
+        let program = match Program::parse(&headers.source, dialect) {
+            Ok(program) => program,
+            Err(e) if self.source_map.is_some() => {
+                let place = self.places(path, &text)?.describe(e.position());
+                return Err(format!("{}: {} ({})", path, e, place).into());
+            }
+            Err(e) => return Err(format!("{}: {}", path, e).into()),
+        };
+        let mut config = headers.config;
+        config.cell_width = self.cell_width.unwrap_or(config.cell_width);
+        config.eof = self.eof.unwrap_or(config.eof);
+        config.tape_size = self.tape_size.unwrap_or(config.tape_size);
+        if config.tape_size == 0 {
+            return Err("--tape-size must be at least 1".into());
+        }
+        let limits = self.limits.or(headers.limits);
+        Ok(Loaded {
+            text,
+            program,
+            config,
+            limits,
+        })
+    }
+
+    /// Where things are in the program at `path`, which reads `text`, and in
+    /// the file it was converted from if there's a --source-map.
+    fn places(&self, path: &str, text: &str) -> Result<Places, Box<dyn Error>> {
+        let original = match &self.source_map {
+            Some(map_path) => {
+                let map = SourceMap::load(Path::new(map_path))
+                    .map_err(|e| format!("{}: {}", map_path, e))?;
+                if Path::new(&map.destination).file_name() != Path::new(path).file_name() {
+                    return Err(format!(
+                        "{} is a map of {}, not {}",
+                        map_path, map.destination, path
+                    )
+                    .into());
+                }
+                let folder = Path::new(map_path).parent().unwrap_or(Path::new(""));
+                let source_path = folder.join(&map.source).display().to_string();
+                let source_text = fs::read_to_string(&source_path)
+                    .map_err(|e| format!("{}: {}", source_path, e))?;
+                Some((map, source_path, source_text))
+            }
+            None => None,
+        };
+        Ok(Places {
+            path: path.to_owned(),
+            text: text.to_owned(),
+            original,
+        })
+    }
+}
+
+/// Turns byte positions in a program into file and line, going back to the
+/// file it was converted from when there's a source map.
+struct Places {
+    path: String,
+    text: String,
+    /// The map, and the path and text of the file it maps back to.
+    original: Option<(SourceMap, String, String)>,
+}
+
+impl Places {
+    fn describe(&self, position: usize) -> String {
+        let line = Source::new(&self.path, &self.text).line(position);
+        let here = format!("{}:{}", self.path, line);
+        let Some((map, path, text)) = &self.original else {
+            return here;
+        };
+        match map.to_source(position) {
+            Some(span) => format!(
+                "{}:{}, {}",
+                path,
+                Source::new(path, text).line(span.start),
+                here
+            ),
+            None => here,
+        }
+    }
+
+    /// The byte in the program of the op at byte `position`, which is in
+    /// the original file if there's a source map.
+    fn op_at(&self, position: usize) -> Result<usize, Box<dyn Error>> {
+        match &self.original {
+            Some((map, path, _)) => map
+                .to_destination(position)
+                .map(|span| span.start)
+                .ok_or_else(|| {
+                    format!("there's no command at byte {} of {}", position, path).into()
+                }),
+            None => Ok(position),
+        }
+    }
+}
+
+fn exit_code(termination: Termination) -> i32 {
+    match termination {
+        Termination::Finished | Termination::AwaitingInput => 0,
+        Termination::StepLimit { .. } => 2,
+        Termination::OutputLimit { .. } => 3,
+        Termination::TapeLimit { .. } => 4,
+        Termination::Timeout { .. } => 5,
+        Termination::Breakpoint => 6,
+    }
+}
@@ -0,0 +1,93 @@
+use super::{one_path, parse, value, Loaded, Settings};
+use bf::config::parse_headers;
+use bf::fuzz;
+use bf::interpreter::Limits;
+use bf::ops::Program;
+use bf::reduce::{reduce, Interesting, Predicate};
+use std::error::Error;
+use std::fs;
+
+const REDUCE_MAX_STEPS: u64 = 1_000_000;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut interesting = None;
+    let mut engine = None;
+    let mut input = Vec::new();
+    let mut output: Option<String> = None;
+    let path = one_path(parse(args, Some(&mut settings), 1, |arg, args| {
+        match arg {
+            "--differs" => interesting = Some(Interesting::Differs(value(args, arg)?)),
+            "--panics" => interesting = Some(Interesting::Panics),
+            "--fails" => interesting = Some(Interesting::Fails(value(args, arg)?)),
+            "--prints" => {
+                interesting = Some(Interesting::Prints(
+                    args.next()
+                        .ok_or("--prints needs a value")?
+                        .as_bytes()
+                        .to_vec(),
+                ))
+            }
+            "--command" => interesting = Some(Interesting::Command(value(args, arg)?)),
+            "--engine" => {
+                let name: String = value(args, arg)?;
+                engine = match name.as_str() {
+                    "interpreter" => None,
+                    _ => Some(
+                        name.parse::<fuzz::Engine>()
+                            .map_err(|e| format!("bad value {:?} for {}: {}", name, arg, e))?,
+                    ),
+                };
+            }
+            "--input" => {
+                let file: String = value(args, arg)?;
+                input = fs::read(&file).map_err(|e| format!("{}: {}", file, e))?;
+            }
+            "-o" => output = Some(value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let interesting =
+        interesting.ok_or("bf reduce needs --differs, --panics, --fails, --prints or --command")?;
+    let Loaded {
+        text,
+        program,
+        config,
+        limits,
+    } = settings.read_program(&path)?;
+    let dialect = settings.dialect(&path);
+    let predicate = Predicate {
+        interesting,
+        engine,
+        dialect,
+        config,
+        limits: limits.or(Limits {
+            max_steps: Some(REDUCE_MAX_STEPS),
+            ..Limits::default()
+        }),
+        input,
+    };
+    if !predicate.holds(&text) {
+        return Err(format!("{} isn't interesting to begin with", path).into());
+    }
+    let mut runs = 1;
+    // Candidates keep the header lines, so they run on the same machine.
+    let reduced = reduce(&program, &text, dialect, |candidate| {
+        runs += 1;
+        predicate.holds(candidate)
+    });
+    let ops = Program::parse(&parse_headers(&reduced)?.source, dialect)?.len();
+    let reduced = reduced + "\n";
+    match output {
+        Some(output) => fs::write(&output, &reduced).map_err(|e| format!("{}: {}", output, e))?,
+        None => print!("{}", reduced),
+    }
+    eprintln!(
+        "bf: reduced {} ops to {} in {} runs",
+        program.len(),
+        ops,
+        runs
+    );
+    Ok(0)
+}
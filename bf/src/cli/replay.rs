@@ -0,0 +1,49 @@
+use super::{no_flags, parse, Loaded, Settings, USAGE};
+use bf::transcript::Transcript;
+use std::error::Error;
+use std::path::Path;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let paths = parse(args, Some(&mut settings), 2, no_flags)?;
+    if paths.len() != 2 {
+        return Err(USAGE.into());
+    }
+    let Loaded {
+        program, limits, ..
+    } = settings.read_program(&paths[0])?;
+    let transcript =
+        Transcript::load(Path::new(&paths[1])).map_err(|e| format!("{}: {}", paths[1], e))?;
+    // The transcript says which machine it ran on, so these can only agree.
+    let recorded = transcript.config;
+    for (flag, differs) in [
+        (
+            "--cell-width",
+            settings
+                .cell_width
+                .is_some_and(|w| w != recorded.cell_width),
+        ),
+        ("--eof", settings.eof.is_some_and(|e| e != recorded.eof)),
+        (
+            "--tape-size",
+            settings.tape_size.is_some_and(|t| t != recorded.tape_size),
+        ),
+    ] {
+        if differs {
+            return Err(format!("{} was recorded with a different {}", paths[1], flag).into());
+        }
+    }
+    match transcript.replay(program, limits) {
+        Ok(()) => {
+            println!(
+                "replayed {} input bytes, output matches",
+                transcript.inputs.len()
+            );
+            Ok(0)
+        }
+        Err(mismatch) => {
+            println!("replay failed: {}", mismatch);
+            Ok(1)
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use super::{exit_code, one_path, parse, value, Loaded, Settings};
+use bf::analysis::analyse;
+use bf::config::{Config, ConfigError};
+use bf::interpreter::{Interpreter, Limits, Termination};
+use bf::ir::optimise;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+use bf::jit::{Jit, JitIo};
+use bf::snapshot::Snapshot;
+use bf::trace::{TraceMode, Tracer};
+use bf::vm::{compile, Bytecode, Vm};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    Auto,
+    Interpreter,
+    Vm,
+    Jit,
+}
+
+impl FromStr for Engine {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Engine::Auto),
+            "interpreter" => Ok(Engine::Interpreter),
+            "vm" => Ok(Engine::Vm),
+            "jit" => Ok(Engine::Jit),
+            _ => Err(ConfigError::BadValue {
+                key: "engine".to_owned(),
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut load_state: Option<String> = None;
+    let mut save_state: Option<String> = None;
+    let mut trace: Option<String> = None;
+    let mut trace_mode = TraceMode::Every;
+    let mut record: Option<String> = None;
+    let mut engine = Engine::Auto;
+    let mut breaks: Vec<usize> = Vec::new();
+    let path = one_path(parse(args, Some(&mut settings), 1, |arg, args| {
+        match arg {
+            "--load-state" => load_state = Some(value(args, arg)?),
+            "--save-state" => save_state = Some(value(args, arg)?),
+            "--trace" => trace = Some(value(args, arg)?),
+            "--trace-every" => {
+                let every = value(args, arg)?;
+                if every == 0 {
+                    return Err("--trace-every must be at least 1".into());
+                }
+                trace_mode = TraceMode::EveryNth(every);
+            }
+            "--trace-loops" => trace_mode = TraceMode::LoopBoundaries,
+            "--record" => record = Some(value(args, arg)?),
+            "--engine" => engine = value(args, arg)?,
+            "--break" => breaks.push(value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let Loaded {
+        text,
+        program,
+        config,
+        limits,
+    } = settings.read_program(&path)?;
+    let places = settings.places(&path, &text)?;
+    let analysis = analyse(&program);
+    if let Some(underflow) = analysis.underflow {
+        match places.original {
+            Some(_) => eprintln!(
+                "bf: warning: {} ({})",
+                underflow,
+                places.describe(underflow.position)
+            ),
+            None => eprintln!("bf: warning: {}", underflow),
+        }
+    }
+    let mut breakpoints = Vec::new();
+    for position in breaks {
+        let byte = places.op_at(position)?;
+        let instruction = program
+            .spans
+            .iter()
+            .position(|span| span.contains(&byte))
+            .ok_or_else(|| format!("there's no command at byte {} of {}", byte, path))?;
+        breakpoints.push(instruction);
+    }
+    let needs_interpreter = !Vm::supports(&limits)
+        || !breakpoints.is_empty()
+        || trace.is_some()
+        || record.is_some()
+        || load_state.is_some()
+        || save_state.is_some();
+    if needs_interpreter && matches!(engine, Engine::Vm | Engine::Jit) {
+        return Err(
+            "--engine vm and --engine jit can't be used with step or cell limits, \
+                    --break, --trace, --record, --load-state or --save-state"
+                .into(),
+        );
+    }
+    if record.is_some() && load_state.is_some() {
+        return Err(
+            "--record can't be used with --load-state, bf replay starts from the beginning".into(),
+        );
+    }
+    if engine == Engine::Jit && limits.timeout.is_some() {
+        return Err("--engine jit can't be used with --timeout-ms".into());
+    }
+    if !needs_interpreter && engine != Engine::Interpreter {
+        let bytecode = compile(&optimise(&program), config.tape_size);
+        if matches!(engine, Engine::Auto | Engine::Jit) && limits.timeout.is_none() {
+            match run_jit(&bytecode, config, limits) {
+                Some(result) => return result,
+                None if engine == Engine::Jit => {
+                    eprintln!("bf: the JIT isn't available in this build, using the VM")
+                }
+                None => {}
+            }
+        }
+        let mut vm = Vm::with_bytecode(bytecode, config);
+        vm.limits = limits;
+        vm.unchecked = true;
+        vm.bounded = analysis.stays_within(config.tape_size);
+        return run_vm(vm);
+    }
+
+    let mut interpreter = Interpreter::with_config(config);
+    let mut written = 0;
+    interpreter.limits = limits;
+    interpreter.load(program);
+    interpreter.breakpoints = breakpoints;
+    if let Some(load_state) = &load_state {
+        let snapshot =
+            Snapshot::load(Path::new(load_state)).map_err(|e| format!("{}: {}", load_state, e))?;
+        interpreter
+            .restore(snapshot)
+            .map_err(|e| format!("{}: {}", load_state, e))?;
+        written = interpreter.result_text.len();
+    }
+    if let Some(trace) = &trace {
+        let tracer = Tracer::create(Path::new(trace), trace_mode)
+            .map_err(|e| format!("{}: {}", trace, e))?;
+        interpreter.tracer = Some(tracer);
+    }
+    if record.is_some() {
+        interpreter.start_recording();
+    }
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        let termination = interpreter.run();
+        let output: Vec<u8> = interpreter.result_text[written..]
+            .chars()
+            .map(|c| c as u8)
+            .collect();
+        written = interpreter.result_text.len();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        if termination == Termination::AwaitingInput {
+            let mut line = Vec::new();
+            if stdin.lock().read_until(b'\n', &mut line)? == 0 {
+                interpreter.close_input();
+            } else {
+                interpreter.push_input(&line);
+            }
+            continue;
+        }
+        if let Some(tracer) = interpreter.tracer.take() {
+            tracer
+                .finish()
+                .map_err(|e| format!("{}: {}", trace.as_deref().unwrap_or_default(), e))?;
+        }
+        if let (Some(record), Some(transcript)) = (&record, interpreter.transcript()) {
+            transcript
+                .save(Path::new(record))
+                .map_err(|e| format!("{}: {}", record, e))?;
+        }
+        if termination.is_limit() {
+            let instruction = interpreter.current_instruction;
+            match interpreter.program.spans.get(instruction) {
+                Some(span) => eprintln!("bf: {} at {}", termination, places.describe(span.start)),
+                None => eprintln!("bf: {}", termination),
+            }
+            if let Some(save_state) = &save_state {
+                interpreter.snapshot().save(Path::new(save_state))?;
+                eprintln!("bf: state saved to {}", save_state);
+            }
+        }
+        return Ok(exit_code(termination));
+    }
+}
+
+fn run_vm(mut vm: Vm) -> Result<i32, Box<dyn Error>> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut written = 0;
+    loop {
+        let termination = vm.run();
+        stdout.write_all(&vm.output[written..])?;
+        stdout.flush()?;
+        written = vm.output.len();
+        if termination == Termination::AwaitingInput {
+            let mut line = Vec::new();
+            if stdin.lock().read_until(b'\n', &mut line)? == 0 {
+                vm.close_input();
+            } else {
+                vm.push_input(&line);
+            }
+            continue;
+        }
+        if termination.is_limit() {
+            eprintln!("bf: {}", termination);
+        }
+        return Ok(exit_code(termination));
+    }
+}
+
+/// Reads input a line at a time, like the other engines, and stops the
+/// program at the output limit.
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+struct StdIo {
+    line: Vec<u8>,
+    position: usize,
+    closed: bool,
+    written: usize,
+    max_output_bytes: Option<usize>,
+    stdout: io::BufWriter<io::Stdout>,
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+impl JitIo for StdIo {
+    fn read(&mut self) -> Option<u8> {
+        while self.position == self.line.len() && !self.closed {
+            self.stdout.flush().ok()?;
+            self.line.clear();
+            self.position = 0;
+            self.closed = io::stdin().lock().read_until(b'\n', &mut self.line).ok()? == 0;
+        }
+        let byte = self.line.get(self.position).copied();
+        self.position += 1;
+        byte
+    }
+
+    fn write(&mut self, byte: u8) -> Option<Termination> {
+        if let Some(max_output_bytes) = self.max_output_bytes {
+            if self.written >= max_output_bytes {
+                return Some(Termination::OutputLimit {
+                    bytes: max_output_bytes,
+                });
+            }
+        }
+        self.written += 1;
+        self.stdout.write_all(&[byte]).ok();
+        None
+    }
+}
+
+/// Runs the program natively, or returns `None` if the JIT isn't built in or
+/// can't handle it.
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+fn run_jit(
+    bytecode: &Bytecode,
+    config: Config,
+    limits: Limits,
+) -> Option<Result<i32, Box<dyn Error>>> {
+    let jit = Jit::compile(bytecode, config)?;
+    let mut cells = vec![0; config.tape_size];
+    let mut io = StdIo {
+        line: Vec::new(),
+        position: 0,
+        closed: false,
+        written: 0,
+        max_output_bytes: limits.max_output_bytes,
+        stdout: io::BufWriter::new(io::stdout()),
+    };
+    let (termination, _) = jit.run(&mut cells, 0, &mut io);
+    if let Err(e) = io.stdout.flush() {
+        return Some(Err(e.into()));
+    }
+    if termination.is_limit() {
+        eprintln!("bf: {}", termination);
+    }
+    Some(Ok(exit_code(termination)))
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64", unix)))]
+fn run_jit(_: &Bytecode, _: Config, _: Limits) -> Option<Result<i32, Box<dyn Error>>> {
+    None
+}
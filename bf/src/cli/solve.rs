@@ -0,0 +1,53 @@
+use super::{one_path, parse, value, Loaded, Settings};
+use bf::ir::optimise;
+use bf::symbolic::{search, Goal, Search};
+use std::error::Error;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut goal = None;
+    let mut bounds = Search::default();
+    let path = one_path(parse(args, Some(&mut settings), 1, |arg, args| {
+        match arg {
+            "--reach" => goal = Some(Goal::Reach(value(args, arg)?)),
+            "--output" => {
+                goal = Some(Goal::Output(
+                    args.next()
+                        .ok_or("--output needs a value")?
+                        .as_bytes()
+                        .to_vec(),
+                ))
+            }
+            "--max-input" => bounds.max_input = value(args, arg)?,
+            "--max-passes" => bounds.max_passes = value(args, arg)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let goal = goal.ok_or("bf solve needs --reach or --output")?;
+    let Loaded {
+        program, config, ..
+    } = settings.read_program(&path)?;
+    let report = search(&optimise(&program), config, &goal, bounds);
+    match report.found {
+        Some(witness) => {
+            println!("input: \"{}\"", witness.input.escape_ascii());
+            println!("output: \"{}\"", witness.output.escape_ascii());
+            Ok(0)
+        }
+        None => {
+            if report.cut == 0 {
+                println!(
+                    "no input of up to {} bytes does it ({} paths)",
+                    bounds.max_input, report.paths
+                );
+            } else {
+                println!(
+                    "nothing found in {} paths, {} of them given up on at the bounds",
+                    report.paths, report.cut
+                );
+            }
+            Ok(1)
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use super::{one_path, parse, Settings};
+use bf::golden::{discover, Failure, GoldenTest};
+use bf::interpreter::Limits;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+const TEST_MAX_STEPS: u64 = 100_000_000;
+const TEST_TIMEOUT_MS: u64 = 10_000;
+
+pub fn command(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut wasm = false;
+    let dir = one_path(parse(args, Some(&mut settings), 1, |arg, _| {
+        match arg {
+            "--wasm" => wasm = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?)?;
+    let tests = discover(Path::new(&dir)).map_err(|e| format!("{}: {}", dir, e))?;
+    let limits = settings.limits.or(Limits {
+        max_steps: Some(TEST_MAX_STEPS),
+        timeout: Some(Duration::from_millis(TEST_TIMEOUT_MS)),
+        ..Limits::default()
+    });
+    if wasm && !cfg!(feature = "wasm-runtime") {
+        return Err("--wasm needs a build with the wasm-runtime feature".into());
+    }
+    let mut failed = 0;
+    for test in &tests {
+        let result = if wasm {
+            check_wasm(test, limits)
+        } else {
+            test.run(limits)
+        };
+        match result {
+            Ok(()) => println!("PASS {}", test.name),
+            Err(failure) => {
+                failed += 1;
+                println!("FAIL {}: {}", test.name, failure);
+            }
+        }
+    }
+    println!("{} passed, {} failed", tests.len() - failed, failed);
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+#[cfg(feature = "wasm-runtime")]
+fn check_wasm(test: &GoldenTest, limits: Limits) -> Result<(), Failure> {
+    bf::wasm_runtime::check(test, limits)
+}
+
+#[cfg(not(feature = "wasm-runtime"))]
+fn check_wasm(_: &GoldenTest, _: Limits) -> Result<(), Failure> {
+    Err(Failure::Setup(
+        "this build has no WebAssembly runtime".to_owned(),
+    ))
+}
@@ -0,0 +1,139 @@
+use super::{describe, reads_input, wrap_offset, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+struct Writer<'a> {
+    out: String,
+    config: Config,
+    source: &'a Source<'a>,
+    line: usize,
+}
+
+impl Writer<'_> {
+    fn statement(&mut self, depth: usize, node: &Node, text: &str) {
+        let line = self.source.line(node.span.start);
+        if line != self.line {
+            self.line = line;
+            self.out += &format!("{}// {}:{}\n", "    ".repeat(depth), self.source.name, line);
+        }
+        self.out += &format!("{}{}\n", "    ".repeat(depth), text);
+    }
+
+    fn at(&self, offset: isize) -> String {
+        format!("AT({})", wrap_offset(offset, self.config))
+    }
+
+    fn moved(&self, distance: isize) -> String {
+        let distance = wrap_offset(distance, self.config);
+        let sign = if distance < 0 { '-' } else { '+' };
+        format!(
+            "p = (p + TAPE_SIZE {} {}) % TAPE_SIZE;",
+            sign,
+            distance.unsigned_abs()
+        )
+    }
+
+    fn block(&mut self, depth: usize, nodes: &[Node]) {
+        let mask = self.config.cell_width.mask();
+        for node in nodes {
+            let text = match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    let delta = delta & mask;
+                    if delta == 0 {
+                        continue;
+                    }
+                    if delta > mask / 2 {
+                        format!(
+                            "{} -= {};",
+                            self.at(*offset),
+                            (mask - delta).wrapping_add(1)
+                        )
+                    } else {
+                        format!("{} += {};", self.at(*offset), delta)
+                    }
+                }
+                NodeKind::Move(distance) => self.moved(*distance),
+                NodeKind::Clear { offset } => format!("{} = 0;", self.at(*offset)),
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => match factor & mask {
+                    1 => format!("{} += {};", self.at(*offset), self.at(*src)),
+                    factor if factor == mask => {
+                        format!("{} -= {};", self.at(*offset), self.at(*src))
+                    }
+                    factor => format!("{} += {} * {}u;", self.at(*offset), self.at(*src), factor),
+                },
+                NodeKind::Scan(step) => format!("while (AT(0)) {}", self.moved(*step)),
+                NodeKind::Print { offset } => format!("putchar({});", self.at(*offset)),
+                NodeKind::Input { offset } => format!("input(&{});", self.at(*offset)),
+                NodeKind::Loop(body) => {
+                    self.statement(depth, node, "while (AT(0)) {");
+                    self.block(depth + 1, body);
+                    self.out += &format!("{}}}\n", "    ".repeat(depth));
+                    continue;
+                }
+            };
+            self.statement(depth, node, &text);
+        }
+    }
+}
+
+/// A C99 program that does what `nodes` do, using only the standard library.
+pub fn generate(nodes: &[Node], config: Config, source: &Source) -> String {
+    let cell = match config.cell_width.bits() {
+        8 => "uint8_t",
+        16 => "uint16_t",
+        _ => "uint32_t",
+    };
+    let eof = match config.eof {
+        EofPolicy::Unchanged => String::new(),
+        EofPolicy::Zero => "    else\n        *c = 0;\n".to_owned(),
+        EofPolicy::Max => format!("    else\n        *c = {}u;\n", config.cell_width.mask()),
+    };
+    let input = if reads_input(nodes) {
+        format!(
+            "static void input(cell *c) {{\n\
+             \x20   fflush(stdout);\n\
+             \x20   int ch = getchar();\n\
+             \x20   if (ch != EOF)\n\
+             \x20       *c = (cell)ch;\n\
+             {}\
+             }}\n\
+             \n",
+            eof
+        )
+    } else {
+        String::new()
+    };
+    let mut writer = Writer {
+        out: format!(
+            "// Compiled by bf from {}: {}.\n\
+             #include <stdint.h>\n\
+             #include <stdio.h>\n\
+             \n\
+             #define TAPE_SIZE {}\n\
+             #define AT(offset) tape[(p + TAPE_SIZE + (offset)) % TAPE_SIZE]\n\
+             \n\
+             typedef {} cell;\n\
+             \n\
+             static cell tape[TAPE_SIZE];\n\
+             static size_t p;\n\
+             \n\
+             {}\
+             int main(void) {{\n",
+            source.name,
+            describe(config),
+            config.tape_size,
+            cell,
+            input
+        ),
+        config,
+        source,
+        line: 0,
+    };
+    writer.block(1, nodes);
+    writer.out += "    return 0;\n}\n";
+    writer.out
+}
@@ -0,0 +1,96 @@
+use super::x86::{Assembler, Io, OUTPUT_BUFFER_SIZE};
+use crate::config::Config;
+use crate::vm::Bytecode;
+
+const BASE_ADDRESS: u64 = 0x40_0000;
+const PAGE_SIZE: u64 = 0x1000;
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+struct Segment {
+    flags: u32,
+    offset: u64,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+}
+
+/// A statically linked Linux x86-64 executable for `bytecode`, talking to
+/// the kernel directly, with nothing to link against. The code is one
+/// read-only executable segment; the tape and output buffer are a
+/// zero-filled writable one. Returns `None` if the tape is too big for the
+/// 32-bit offsets the code uses.
+pub fn executable(bytecode: &Bytecode, config: Config) -> Option<Vec<u8>> {
+    if config.tape_size > i32::MAX as usize {
+        return None;
+    }
+    let headers = ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+    // The code's length doesn't depend on where the data goes, so assemble
+    // once to measure it and again with the real addresses.
+    let measure = Io::Syscalls {
+        tape: 0,
+        output_buffer: 0,
+    };
+    let code_size = Assembler::new(config, measure).assemble(bytecode).len() as u64;
+    let end_of_code = BASE_ADDRESS + headers + code_size;
+    let tape = end_of_code.div_ceil(PAGE_SIZE) * PAGE_SIZE + PAGE_SIZE;
+    let tape_bytes = config.tape_size as u64 * 4;
+    let output_buffer = tape + tape_bytes;
+    let code = Assembler::new(
+        config,
+        Io::Syscalls {
+            tape,
+            output_buffer,
+        },
+    )
+    .assemble(bytecode);
+
+    let segments = [
+        Segment {
+            flags: PF_R | PF_X,
+            offset: 0,
+            address: BASE_ADDRESS,
+            file_size: headers + code.len() as u64,
+            memory_size: headers + code.len() as u64,
+        },
+        Segment {
+            flags: PF_R | PF_W,
+            offset: 0,
+            address: tape,
+            file_size: 0,
+            memory_size: tape_bytes + OUTPUT_BUFFER_SIZE as u64,
+        },
+    ];
+    let mut file = Vec::with_capacity(headers as usize + code.len());
+    file.extend_from_slice(b"\x7fELF");
+    file.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, version 1, System V
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(&2u16.to_le_bytes()); // executable
+    file.extend_from_slice(&0x3eu16.to_le_bytes()); // x86-64
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&(BASE_ADDRESS + headers).to_le_bytes()); // entry point
+    file.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes()); // program headers
+    file.extend_from_slice(&0u64.to_le_bytes()); // no section headers
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    file.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    file.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+    file.extend_from_slice(&[0; 6]); // section header size, count and names
+    for segment in &segments {
+        file.extend_from_slice(&PT_LOAD.to_le_bytes());
+        file.extend_from_slice(&segment.flags.to_le_bytes());
+        file.extend_from_slice(&segment.offset.to_le_bytes());
+        file.extend_from_slice(&segment.address.to_le_bytes());
+        file.extend_from_slice(&segment.address.to_le_bytes());
+        file.extend_from_slice(&segment.file_size.to_le_bytes());
+        file.extend_from_slice(&segment.memory_size.to_le_bytes());
+        file.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+    }
+    file.extend_from_slice(&code);
+    Some(file)
+}
@@ -0,0 +1,156 @@
+use super::{describe, reads_input, wrap_offset, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+struct Writer<'a> {
+    out: String,
+    config: Config,
+    source: &'a Source<'a>,
+    line: usize,
+}
+
+impl Writer<'_> {
+    fn statement(&mut self, depth: usize, node: &Node, text: &str) {
+        let line = self.source.line(node.span.start);
+        if line != self.line {
+            self.line = line;
+            self.out += &format!("{}// {}:{}\n", "    ".repeat(depth), self.source.name, line);
+        }
+        self.out += &format!("{}{}\n", "    ".repeat(depth), text);
+    }
+
+    fn at(&self, offset: isize) -> String {
+        match wrap_offset(offset, self.config) {
+            0 => "tape[p]".to_owned(),
+            offset if offset < 0 => format!("tape[(p + TAPE_SIZE - {}) % TAPE_SIZE]", -offset),
+            offset => format!("tape[(p + {}) % TAPE_SIZE]", offset),
+        }
+    }
+
+    fn moved(&self, distance: isize) -> String {
+        match wrap_offset(distance, self.config) {
+            distance if distance < 0 => format!("p = (p + TAPE_SIZE - {}) % TAPE_SIZE;", -distance),
+            distance => format!("p = (p + {}) % TAPE_SIZE;", distance),
+        }
+    }
+
+    fn block(&mut self, depth: usize, nodes: &[Node]) {
+        let mask = self.config.cell_width.mask();
+        for node in nodes {
+            let text = match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    let delta = delta & mask;
+                    if delta == 0 {
+                        continue;
+                    }
+                    if delta > mask / 2 {
+                        let delta = (mask - delta).wrapping_add(1);
+                        format!("{} -= {};", self.at(*offset), delta)
+                    } else {
+                        format!("{} += {};", self.at(*offset), delta)
+                    }
+                }
+                NodeKind::Move(distance) => self.moved(*distance),
+                NodeKind::Clear { offset } => format!("{} = 0;", self.at(*offset)),
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => match factor & mask {
+                    1 => format!("{} += {};", self.at(*offset), self.at(*src)),
+                    factor if factor == mask => {
+                        format!("{} -= {};", self.at(*offset), self.at(*src))
+                    }
+                    // A 32-bit product can go past what a double holds exactly.
+                    factor if self.config.cell_width.bits() == 32 => format!(
+                        "{} += Math.imul({}, {});",
+                        self.at(*offset),
+                        self.at(*src),
+                        factor
+                    ),
+                    factor => format!("{} += {} * {};", self.at(*offset), self.at(*src), factor),
+                },
+                NodeKind::Scan(step) => format!("while ({}) {}", self.at(0), self.moved(*step)),
+                NodeKind::Print { offset } => format!("print({});", self.at(*offset)),
+                NodeKind::Input { offset } => {
+                    format!("{} = input({});", self.at(*offset), self.at(*offset))
+                }
+                NodeKind::Loop(body) => {
+                    self.statement(depth, node, "while (tape[p]) {");
+                    self.block(depth + 1, body);
+                    self.out += &format!("{}}}\n", "    ".repeat(depth));
+                    continue;
+                }
+            };
+            self.statement(depth, node, &text);
+        }
+    }
+}
+
+/// A Node.js script that does what `nodes` do on stdin and stdout. The tape
+/// is a typed array of the cell width, so it wraps by itself.
+pub fn generate(nodes: &[Node], config: Config, source: &Source) -> String {
+    let array = match config.cell_width.bits() {
+        8 => "Uint8Array",
+        16 => "Uint16Array",
+        _ => "Uint32Array",
+    };
+    let eof = match config.eof {
+        EofPolicy::Unchanged => "cell".to_owned(),
+        EofPolicy::Zero => "0".to_owned(),
+        EofPolicy::Max => config.cell_width.mask().to_string(),
+    };
+    let input = if reads_input(nodes) {
+        format!(
+            "// The next byte of input, or what the cell becomes at the end of it.\n\
+             function input(cell) {{\n\
+             \x20   flush();\n\
+             \x20   const byte = Buffer.alloc(1);\n\
+             \x20   return fs.readSync(0, byte, 0, 1, null) === 1 ? byte[0] : {};\n\
+             }}\n\
+             \n",
+            eof
+        )
+    } else {
+        String::new()
+    };
+    let mut writer = Writer {
+        out: format!(
+            "// Compiled by bf from {}: {}.\n\
+             \"use strict\";\n\
+             const fs = require(\"fs\");\n\
+             \n\
+             const TAPE_SIZE = {};\n\
+             const tape = new {}(TAPE_SIZE);\n\
+             let p = 0;\n\
+             let output = [];\n\
+             \n\
+             function flush() {{\n\
+             \x20   if (output.length > 0) {{\n\
+             \x20       fs.writeSync(1, Buffer.from(output));\n\
+             \x20       output = [];\n\
+             \x20   }}\n\
+             }}\n\
+             \n\
+             function print(cell) {{\n\
+             \x20   output.push(cell & 0xff);\n\
+             \x20   if (output.length >= 4096) {{\n\
+             \x20       flush();\n\
+             \x20   }}\n\
+             }}\n\
+             \n\
+             {}",
+            source.name,
+            describe(config),
+            config.tape_size,
+            array,
+            input
+        ),
+        config,
+        source,
+        line: 0,
+    };
+    writer.block(0, nodes);
+    writer.out += "flush();\n";
+    writer.out
+}
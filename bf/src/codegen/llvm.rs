@@ -0,0 +1,239 @@
+use super::{describe, reads_input, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+/// `value` as the signed constant of the cell width LLVM expects.
+fn constant(value: u32, config: Config) -> i64 {
+    let bits = config.cell_width.bits();
+    let value = (value & config.cell_width.mask()) as i64;
+    if value >> (bits - 1) == 1 {
+        value - (1 << bits)
+    } else {
+        value
+    }
+}
+
+struct Writer<'a> {
+    out: String,
+    config: Config,
+    source: &'a Source<'a>,
+    line: usize,
+    cell: String,
+    tape: String,
+    values: usize,
+    labels: usize,
+}
+
+impl Writer<'_> {
+    fn comment(&mut self, node: &Node) {
+        let line = self.source.line(node.span.start);
+        if line != self.line {
+            self.line = line;
+            self.out += &format!("  ; {}:{}\n", self.source.name, line);
+        }
+    }
+
+    fn instruction(&mut self, text: &str) {
+        self.out += &format!("  {}\n", text);
+    }
+
+    /// Emits `text` as the value of a fresh register and returns its name.
+    fn value(&mut self, text: &str) -> String {
+        self.values += 1;
+        let name = format!("%v{}", self.values);
+        self.instruction(&format!("{} = {}", name, text));
+        name
+    }
+
+    fn label(&mut self, name: &str) {
+        self.out += &format!("{}:\n", name);
+    }
+
+    /// The tape index `offset` cells from the pointer.
+    fn index(&mut self, offset: isize) -> String {
+        let p = self.value("load i64, i64* %p");
+        let offset = offset.rem_euclid(self.config.tape_size as isize);
+        if offset == 0 {
+            return p;
+        }
+        let sum = self.value(&format!("add i64 {}, {}", p, offset));
+        self.value(&format!("urem i64 {}, {}", sum, self.config.tape_size))
+    }
+
+    fn address(&mut self, offset: isize) -> String {
+        let index = self.index(offset);
+        let tape = self.tape.clone();
+        self.value(&format!(
+            "getelementptr inbounds {}, {}* @tape, i64 0, i64 {}",
+            tape, tape, index
+        ))
+    }
+
+    fn load(&mut self, address: &str) -> String {
+        let cell = self.cell.clone();
+        self.value(&format!("load {}, {}* {}", cell, cell, address))
+    }
+
+    fn store(&mut self, value: &str, address: &str) {
+        let cell = self.cell.clone();
+        self.instruction(&format!("store {} {}, {}* {}", cell, value, cell, address));
+    }
+
+    fn moved(&mut self, distance: isize) {
+        let index = self.index(distance);
+        self.instruction(&format!("store i64 {}, i64* %p", index));
+    }
+
+    /// A `while (cell != 0)` loop around whatever `body` emits.
+    fn while_nonzero(&mut self, body: impl FnOnce(&mut Self)) {
+        self.labels += 1;
+        let label = format!("loop{}", self.labels);
+        self.instruction(&format!("br label %{}.test", label));
+        self.label(&format!("{}.test", label));
+        let address = self.address(0);
+        let cell = self.load(&address);
+        let cell_type = self.cell.clone();
+        let nonzero = self.value(&format!("icmp ne {} {}, 0", cell_type, cell));
+        self.instruction(&format!(
+            "br i1 {}, label %{}.body, label %{}.end",
+            nonzero, label, label
+        ));
+        self.label(&format!("{}.body", label));
+        body(self);
+        self.instruction(&format!("br label %{}.test", label));
+        self.label(&format!("{}.end", label));
+    }
+
+    fn block(&mut self, nodes: &[Node]) {
+        let cell = self.cell.clone();
+        for node in nodes {
+            self.comment(node);
+            match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    let address = self.address(*offset);
+                    let value = self.load(&address);
+                    let delta = constant(*delta, self.config);
+                    let sum = self.value(&format!("add {} {}, {}", cell, value, delta));
+                    self.store(&sum, &address);
+                }
+                NodeKind::Move(distance) => self.moved(*distance),
+                NodeKind::Clear { offset } => {
+                    let address = self.address(*offset);
+                    self.store("0", &address);
+                }
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    let source = self.address(*src);
+                    let multiplier = self.load(&source);
+                    let address = self.address(*offset);
+                    let value = self.load(&address);
+                    let factor = constant(*factor, self.config);
+                    let product = self.value(&format!("mul {} {}, {}", cell, multiplier, factor));
+                    let sum = self.value(&format!("add {} {}, {}", cell, value, product));
+                    self.store(&sum, &address);
+                }
+                NodeKind::Scan(step) => self.while_nonzero(|this| this.moved(*step)),
+                NodeKind::Print { offset } => {
+                    let address = self.address(*offset);
+                    let mut value = self.load(&address);
+                    if self.config.cell_width.bits() < 32 {
+                        value = self.value(&format!("zext {} {} to i32", cell, value));
+                    }
+                    self.value(&format!("call i32 @putchar(i32 {})", value));
+                }
+                NodeKind::Input { offset } => {
+                    let address = self.address(*offset);
+                    self.instruction(&format!("call void @input({}* {})", cell, address));
+                }
+                NodeKind::Loop(body) => self.while_nonzero(|this| this.block(body)),
+            }
+        }
+    }
+}
+
+/// `input(cell)`: flushes the output, then reads a byte into the cell or
+/// applies the end-of-input policy.
+fn input_function(config: Config, cell: &str) -> String {
+    let (truncate, byte) = if config.cell_width.bits() < 32 {
+        (format!("  %byte = trunc i32 %ch to {}\n", cell), "%byte")
+    } else {
+        (String::new(), "%ch")
+    };
+    let eof = match config.eof {
+        EofPolicy::Unchanged => String::new(),
+        EofPolicy::Zero => format!("  store {} 0, {}* %cell\n", cell, cell),
+        EofPolicy::Max => format!(
+            "  store {} {}, {}* %cell\n",
+            cell,
+            constant(config.cell_width.mask(), config),
+            cell
+        ),
+    };
+    format!(
+        "define internal void @input({}* %cell) {{\n\
+         entry:\n\
+         \x20 call i32 @fflush(i8* null)\n\
+         \x20 %ch = call i32 @getchar()\n\
+         \x20 %eof = icmp eq i32 %ch, -1\n\
+         \x20 br i1 %eof, label %end_of_input, label %read\n\
+         read:\n\
+         {}\
+         \x20 store {} {}, {}* %cell\n\
+         \x20 ret void\n\
+         end_of_input:\n\
+         {}\
+         \x20 ret void\n\
+         }}\n\
+         \n",
+        cell, truncate, cell, byte, cell, eof
+    )
+}
+
+/// A textual LLVM IR module with a `main` that does what `nodes` do, calling
+/// `getchar`, `putchar` and `fflush` from libc. Uses typed pointers, which
+/// LLVM 14 needs and later versions still read.
+pub fn generate(nodes: &[Node], config: Config, source: &Source) -> String {
+    let cell = format!("i{}", config.cell_width.bits());
+    let tape = format!("[{} x {}]", config.tape_size, cell);
+    let input = if reads_input(nodes) {
+        input_function(config, &cell)
+    } else {
+        String::new()
+    };
+    let mut writer = Writer {
+        out: format!(
+            "; Compiled by bf from {}: {}.\n\
+             source_filename = {:?}\n\
+             \n\
+             @tape = internal global {} zeroinitializer\n\
+             \n\
+             declare i32 @getchar()\n\
+             declare i32 @putchar(i32)\n\
+             declare i32 @fflush(i8*)\n\
+             \n\
+             {}\
+             define i32 @main() {{\n\
+             entry:\n\
+             \x20 %p = alloca i64\n\
+             \x20 store i64 0, i64* %p\n",
+            source.name,
+            describe(config),
+            source.name,
+            tape,
+            input
+        ),
+        config,
+        source,
+        line: 0,
+        cell,
+        tape,
+        values: 0,
+        labels: 0,
+    };
+    writer.block(nodes);
+    writer.out += "  ret i32 0\n}\n";
+    writer.out
+}
@@ -0,0 +1,142 @@
+pub mod c;
+pub mod elf;
+pub mod js;
+pub mod llvm;
+pub mod py;
+pub mod rust;
+pub mod wasm;
+pub mod x86;
+
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// What `bf compile --target` can produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    C,
+    /// A Node.js script.
+    Js,
+    /// Textual LLVM IR.
+    Llvm,
+    /// A Python 3 script.
+    Py,
+    Rust,
+    /// A module that imports its I/O as `env.read` and `env.write`.
+    Wasm,
+    /// A WASI command module.
+    Wasi,
+}
+
+impl FromStr for Target {
+    type Err = UnknownTarget;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Target::C),
+            "js" => Ok(Target::Js),
+            "llvm" => Ok(Target::Llvm),
+            "py" => Ok(Target::Py),
+            "rust" => Ok(Target::Rust),
+            "wasm" => Ok(Target::Wasm),
+            "wasi" => Ok(Target::Wasi),
+            _ => Err(UnknownTarget(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownTarget(pub String);
+
+impl fmt::Display for UnknownTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown target {:?}", self.0)
+    }
+}
+
+impl Error for UnknownTarget {}
+
+impl Target {
+    /// The usual file extension for the output.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Target::C => "c",
+            Target::Js => "js",
+            Target::Llvm => "ll",
+            Target::Py => "py",
+            Target::Rust => "rs",
+            Target::Wasm | Target::Wasi => "wasm",
+        }
+    }
+}
+
+/// The program being compiled, for the comments that point back into it.
+pub struct Source<'a> {
+    pub name: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(name: &'a str, text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Source { name, line_starts }
+    }
+
+    /// The 1-based line that byte `offset` is on.
+    pub fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+}
+
+/// Generates `target` code for `nodes`, built by `ir::optimise` from the
+/// program in `source`. Returns `None` if the tape is too big for the target.
+pub fn generate(
+    target: Target,
+    nodes: &[Node],
+    config: Config,
+    source: &Source,
+) -> Option<Vec<u8>> {
+    match target {
+        Target::C => Some(c::generate(nodes, config, source).into_bytes()),
+        Target::Js => Some(js::generate(nodes, config, source).into_bytes()),
+        Target::Llvm => Some(llvm::generate(nodes, config, source).into_bytes()),
+        Target::Py => Some(py::generate(nodes, config, source).into_bytes()),
+        Target::Rust => Some(rust::generate(nodes, config, source).into_bytes()),
+        Target::Wasm => wasm::generate(nodes, config, wasm::Flavour::Imports),
+        Target::Wasi => wasm::generate(nodes, config, wasm::Flavour::Wasi),
+    }
+}
+
+/// `offset` reduced to less than a tape length away, so that adding the tape
+/// size once is enough to make any index positive.
+pub fn wrap_offset(offset: isize, config: Config) -> isize {
+    offset % config.tape_size as isize
+}
+
+/// A one-line description of the machine a program was compiled for.
+pub fn describe(config: Config) -> String {
+    let eof = match config.eof {
+        EofPolicy::Unchanged => "leaves the cell unchanged",
+        EofPolicy::Zero => "sets the cell to 0",
+        EofPolicy::Max => "sets the cell to its maximum",
+    };
+    format!(
+        "{}-bit cells, {}-cell wrapping tape, end of input {}",
+        config.cell_width.bits(),
+        config.tape_size,
+        eof
+    )
+}
+
+/// Whether the program ever reads, so backends can leave out input support.
+pub fn reads_input(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match &node.kind {
+        NodeKind::Input { .. } => true,
+        NodeKind::Loop(body) => reads_input(body),
+        _ => false,
+    })
+}
@@ -0,0 +1,142 @@
+use super::{describe, reads_input, wrap_offset, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+struct Writer<'a> {
+    out: String,
+    config: Config,
+    source: &'a Source<'a>,
+    line: usize,
+}
+
+impl Writer<'_> {
+    fn statement(&mut self, depth: usize, node: &Node, text: &str) {
+        let line = self.source.line(node.span.start);
+        if line != self.line {
+            self.line = line;
+            self.out += &format!("{}# {}:{}\n", "    ".repeat(depth), self.source.name, line);
+        }
+        self.out += &format!("{}{}\n", "    ".repeat(depth), text);
+    }
+
+    /// Python's `%` is never negative, so negative offsets need no help.
+    fn index(&self, offset: isize) -> String {
+        match wrap_offset(offset, self.config) {
+            0 => "p".to_owned(),
+            offset if offset < 0 => format!("(p - {}) % TAPE_SIZE", -offset),
+            offset => format!("(p + {}) % TAPE_SIZE", offset),
+        }
+    }
+
+    fn at(&self, offset: isize) -> String {
+        format!("tape[{}]", self.index(offset))
+    }
+
+    fn update(&self, offset: isize, op: char, operand: &str) -> String {
+        let at = self.at(offset);
+        format!("{} = ({} {} {}) & MASK", at, at, op, operand)
+    }
+
+    fn moved(&self, distance: isize) -> String {
+        format!("p = {}", self.index(distance))
+    }
+
+    fn block(&mut self, depth: usize, nodes: &[Node]) {
+        let mask = self.config.cell_width.mask();
+        for node in nodes {
+            let text = match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    let delta = delta & mask;
+                    if delta == 0 {
+                        continue;
+                    }
+                    if delta > mask / 2 {
+                        let delta = (mask - delta).wrapping_add(1);
+                        self.update(*offset, '-', &delta.to_string())
+                    } else {
+                        self.update(*offset, '+', &delta.to_string())
+                    }
+                }
+                NodeKind::Move(distance) => self.moved(*distance),
+                NodeKind::Clear { offset } => format!("{} = 0", self.at(*offset)),
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => match factor & mask {
+                    1 => self.update(*offset, '+', &self.at(*src)),
+                    factor if factor == mask => self.update(*offset, '-', &self.at(*src)),
+                    factor => self.update(*offset, '+', &format!("{} * {}", self.at(*src), factor)),
+                },
+                NodeKind::Scan(step) => format!("while tape[p]: {}", self.moved(*step)),
+                NodeKind::Print { offset } => format!("write({})", self.at(*offset)),
+                NodeKind::Input { offset } => {
+                    format!("{} = read({})", self.at(*offset), self.at(*offset))
+                }
+                NodeKind::Loop(body) => {
+                    self.statement(depth, node, "while tape[p]:");
+                    if body.is_empty() {
+                        self.out += &format!("{}pass\n", "    ".repeat(depth + 1));
+                    }
+                    self.block(depth + 1, body);
+                    continue;
+                }
+            };
+            self.statement(depth, node, &text);
+        }
+    }
+}
+
+/// A Python 3 script that does what `nodes` do on stdin and stdout, masking
+/// every cell update to the cell width.
+pub fn generate(nodes: &[Node], config: Config, source: &Source) -> String {
+    let eof = match config.eof {
+        EofPolicy::Unchanged => "cell",
+        EofPolicy::Zero => "0",
+        EofPolicy::Max => "MASK",
+    };
+    let read = if reads_input(nodes) {
+        format!(
+            "def read(cell):\n\
+             \x20   \"\"\"The next byte of input, or what the cell becomes at the end of it.\"\"\"\n\
+             \x20   sys.stdout.buffer.flush()\n\
+             \x20   byte = sys.stdin.buffer.read(1)\n\
+             \x20   return byte[0] if byte else {}\n\
+             \n\
+             \n",
+            eof
+        )
+    } else {
+        String::new()
+    };
+    let mut writer = Writer {
+        out: format!(
+            "# Compiled by bf from {}: {}.\n\
+             import sys\n\
+             \n\
+             TAPE_SIZE = {}\n\
+             MASK = {:#x}\n\
+             \n\
+             \n\
+             def write(cell):\n\
+             \x20   sys.stdout.buffer.write(bytes((cell & 0xff,)))\n\
+             \n\
+             \n\
+             {}\
+             def main():\n\
+             \x20   tape = [0] * TAPE_SIZE\n\
+             \x20   p = 0\n",
+            source.name,
+            describe(config),
+            config.tape_size,
+            config.cell_width.mask(),
+            read
+        ),
+        config,
+        source,
+        line: 0,
+    };
+    writer.block(1, nodes);
+    writer.out += "\n\nmain()\nsys.stdout.buffer.flush()\n";
+    writer.out
+}
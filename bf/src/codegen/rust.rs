@@ -0,0 +1,197 @@
+use super::{describe, wrap_offset, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+struct Writer<'a> {
+    out: String,
+    config: Config,
+    source: Option<&'a Source<'a>>,
+    line: usize,
+    reads: bool,
+    prints: bool,
+    moves: bool,
+    writes: bool,
+}
+
+impl Writer<'_> {
+    fn statement(&mut self, depth: usize, node: &Node, text: &str) {
+        if let Some(source) = self.source {
+            let line = source.line(node.span.start);
+            if line != self.line {
+                self.line = line;
+                self.out += &format!("{}// {}:{}\n", "    ".repeat(depth), source.name, line);
+            }
+        }
+        self.out += &format!("{}{}\n", "    ".repeat(depth), text);
+    }
+
+    fn at(&self, offset: isize) -> String {
+        format!("tape[{}]", self.index(offset))
+    }
+
+    fn write(&mut self, offset: isize) -> String {
+        self.writes = true;
+        self.at(offset)
+    }
+
+    fn index(&self, offset: isize) -> String {
+        let offset = wrap_offset(offset, self.config);
+        match offset {
+            0 => "p".to_owned(),
+            offset if offset < 0 => format!("(p + TAPE_SIZE - {}) % TAPE_SIZE", -offset),
+            offset => format!("(p + {}) % TAPE_SIZE", offset),
+        }
+    }
+
+    fn moved(&mut self, distance: isize) -> String {
+        self.moves = true;
+        format!("p = {};", self.index(distance))
+    }
+
+    fn block(&mut self, depth: usize, nodes: &[Node]) {
+        let mask = self.config.cell_width.mask();
+        for node in nodes {
+            let text = match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    let delta = delta & mask;
+                    if delta == 0 {
+                        continue;
+                    }
+                    let at = self.write(*offset);
+                    if delta > mask / 2 {
+                        let delta = (mask - delta).wrapping_add(1);
+                        format!("{} = {}.wrapping_sub({});", at, at, delta)
+                    } else {
+                        format!("{} = {}.wrapping_add({});", at, at, delta)
+                    }
+                }
+                NodeKind::Move(distance) => self.moved(*distance),
+                NodeKind::Clear { offset } => format!("{} = 0;", self.write(*offset)),
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    let (at, src) = (self.write(*offset), self.at(*src));
+                    match factor & mask {
+                        1 => format!("{} = {}.wrapping_add({});", at, at, src),
+                        factor if factor == mask => {
+                            format!("{} = {}.wrapping_sub({});", at, at, src)
+                        }
+                        factor => format!(
+                            "{} = {}.wrapping_add({}.wrapping_mul({}));",
+                            at, at, src, factor
+                        ),
+                    }
+                }
+                NodeKind::Scan(step) => format!("while tape[p] != 0 {{ {} }}", self.moved(*step)),
+                NodeKind::Print { offset } => {
+                    self.prints = true;
+                    match self.config.cell_width.bits() {
+                        8 => format!("output.push({});", self.at(*offset)),
+                        _ => format!("output.push({} as u8);", self.at(*offset)),
+                    }
+                }
+                NodeKind::Input { offset } => {
+                    self.reads = true;
+                    let at = self.write(*offset);
+                    let eof = match self.config.eof {
+                        EofPolicy::Unchanged => String::new(),
+                        EofPolicy::Zero => format!(" else {{ {} = 0; }}", at),
+                        EofPolicy::Max => format!(" else {{ {} = {}; }}", at, mask),
+                    };
+                    let byte = match self.config.cell_width.bits() {
+                        8 => "byte",
+                        _ => "byte.into()",
+                    };
+                    format!(
+                        "if let Some(&byte) = input.next() {{ {} = {}; }}{}",
+                        at, byte, eof
+                    )
+                }
+                NodeKind::Loop(body) => {
+                    self.statement(depth, node, "while tape[p] != 0 {");
+                    self.block(depth + 1, body);
+                    self.out += &format!("{}}}\n", "    ".repeat(depth));
+                    continue;
+                }
+            };
+            self.statement(depth, node, &text);
+        }
+    }
+}
+
+/// A self-contained `fn run(input: &[u8]) -> Vec<u8>` that does what `nodes`
+/// do on all of `input` and returns the output. With a `source`, statements
+/// are marked with the line they came from.
+pub fn function(mut nodes: &[Node], config: Config, source: Option<&Source>) -> String {
+    // Where the pointer ends up doesn't matter, and rustc warns about the
+    // assignment.
+    while let Some((last, rest)) = nodes.split_last() {
+        if !matches!(last.kind, NodeKind::Move(_)) {
+            break;
+        }
+        nodes = rest;
+    }
+    if nodes.is_empty() {
+        return "fn run(_input: &[u8]) -> Vec<u8> {\n    Vec::new()\n}\n".to_owned();
+    }
+    let mut writer = Writer {
+        out: String::new(),
+        config,
+        source,
+        line: 0,
+        reads: false,
+        prints: false,
+        moves: false,
+        writes: false,
+    };
+    writer.block(1, nodes);
+    let cell = match config.cell_width.bits() {
+        8 => "u8",
+        16 => "u16",
+        _ => "u32",
+    };
+    let mutable = |used| if used { "mut " } else { "" };
+    format!(
+        "fn run({}input: &[u8]) -> Vec<u8> {{\n\
+         \x20   const TAPE_SIZE: usize = {};\n\
+         \x20   let {}tape = vec![0{}; TAPE_SIZE];\n\
+         \x20   let {}p = 0;\n\
+         {}\
+         \x20   let {}output = Vec::new();\n\
+         {}\
+         \x20   output\n\
+         }}\n",
+        if writer.reads { "" } else { "_" },
+        config.tape_size,
+        mutable(writer.writes),
+        cell,
+        mutable(writer.moves),
+        if writer.reads {
+            "    let mut input = input.iter();\n"
+        } else {
+            ""
+        },
+        mutable(writer.prints),
+        writer.out
+    )
+}
+
+/// A Rust program that runs the program on its whole standard input.
+pub fn generate(nodes: &[Node], config: Config, source: &Source) -> String {
+    format!(
+        "// Compiled by bf from {}: {}.\n\
+         use std::io::{{Read, Write}};\n\
+         \n\
+         {}\n\
+         fn main() {{\n\
+         \x20   let mut input = Vec::new();\n\
+         \x20   std::io::stdin().read_to_end(&mut input).expect(\"can't read stdin\");\n\
+         \x20   std::io::stdout().write_all(&run(&input)).expect(\"can't write stdout\");\n\
+         }}\n",
+        source.name,
+        describe(config),
+        function(nodes, config, Some(source))
+    )
+}
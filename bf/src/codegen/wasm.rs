@@ -0,0 +1,407 @@
+use super::wrap_offset;
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+
+/// Where the module gets its input and sends its output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flavour {
+    /// Imports `env.read: () -> i32` (a byte, or -1 at the end of the input)
+    /// and `env.write: (i32) -> ()`, and exports `run`.
+    Imports,
+    /// Imports `fd_read` and `fd_write` from `wasi_snapshot_preview1` and
+    /// exports `_start`, so WASI runtimes can run it as a command.
+    Wasi,
+}
+
+const I32: u8 = 0x7f;
+
+mod op {
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_IF: u8 = 0x0d;
+    pub const CALL: u8 = 0x10;
+    pub const DROP: u8 = 0x1a;
+    pub const SELECT: u8 = 0x1b;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_LOAD16_U: u8 = 0x2f;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I32_STORE8: u8 = 0x3a;
+    pub const I32_STORE16: u8 = 0x3b;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_NE: u8 = 0x47;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_GE_U: u8 = 0x4f;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_SUB: u8 = 0x6b;
+    pub const I32_MUL: u8 = 0x6c;
+    pub const I32_SHL: u8 = 0x74;
+}
+
+// Locals of the main function.
+const POINTER: u32 = 0;
+const INDEX: u32 = 1;
+const ADDRESS: u32 = 2;
+const VALUE: u32 = 3;
+
+fn unsigned(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(module: &mut Vec<u8>, id: u8, count: usize, contents: &[u8]) {
+    let mut body = Vec::new();
+    unsigned(&mut body, count as u32);
+    body.extend_from_slice(contents);
+    module.push(id);
+    unsigned(module, body.len() as u32);
+    module.extend_from_slice(&body);
+}
+
+/// One function body: its `i32` locals and its code, closed with `end`.
+fn function(locals: u32, code: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    if locals == 0 {
+        body.push(0);
+    } else {
+        body.push(1);
+        unsigned(&mut body, locals);
+        body.push(I32);
+    }
+    body.extend_from_slice(code);
+    body.push(op::END);
+    let mut sized = Vec::new();
+    unsigned(&mut sized, body.len() as u32);
+    sized.extend_from_slice(&body);
+    sized
+}
+
+struct Body {
+    code: Vec<u8>,
+    config: Config,
+    read: u32,
+    write: u32,
+}
+
+impl Body {
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn local(&mut self, op: u8, local: u32) {
+        self.code.push(op);
+        unsigned(&mut self.code, local);
+    }
+
+    fn constant(&mut self, value: i32) {
+        self.code.push(op::I32_CONST);
+        signed(&mut self.code, value);
+    }
+
+    fn load(&mut self) {
+        let (op, align) = match self.config.cell_width.bits() {
+            8 => (op::I32_LOAD8_U, 0),
+            16 => (op::I32_LOAD16_U, 1),
+            _ => (op::I32_LOAD, 2),
+        };
+        self.code.extend_from_slice(&[op, align, 0]);
+    }
+
+    /// Stores truncate to the cell width, so cells never need masking.
+    fn store(&mut self) {
+        let (op, align) = match self.config.cell_width.bits() {
+            8 => (op::I32_STORE8, 0),
+            16 => (op::I32_STORE16, 1),
+            _ => (op::I32_STORE, 2),
+        };
+        self.code.extend_from_slice(&[op, align, 0]);
+    }
+
+    /// Pushes the tape index `offset` cells from the pointer.
+    fn index(&mut self, offset: isize) {
+        let offset = wrap_offset(offset, self.config);
+        let len = self.config.tape_size as i32;
+        self.local(op::LOCAL_GET, POINTER);
+        if offset == 0 {
+            return;
+        }
+        self.constant(offset as i32);
+        self.op(op::I32_ADD);
+        self.local(op::LOCAL_TEE, INDEX);
+        // select(wrapped, unwrapped, needs wrapping)
+        self.constant(len);
+        self.op(if offset > 0 { op::I32_SUB } else { op::I32_ADD });
+        self.local(op::LOCAL_GET, INDEX);
+        self.local(op::LOCAL_GET, INDEX);
+        if offset > 0 {
+            self.constant(len);
+            self.op(op::I32_GE_U);
+        } else {
+            self.constant(0);
+            self.op(op::I32_LT_S);
+        }
+        self.op(op::SELECT);
+    }
+
+    /// Pushes the memory address of the cell `offset` cells from the pointer.
+    fn address(&mut self, offset: isize) {
+        self.index(offset);
+        let shift = match self.config.cell_width.bits() {
+            8 => 0,
+            16 => 1,
+            _ => 2,
+        };
+        if shift != 0 {
+            self.constant(shift);
+            self.op(op::I32_SHL);
+        }
+    }
+
+    /// `block loop (br_if cell == 0) ... br 0 end end`, with `body` in the
+    /// middle.
+    fn while_nonzero(&mut self, body: impl FnOnce(&mut Self)) {
+        self.code
+            .extend_from_slice(&[op::BLOCK, 0x40, op::LOOP, 0x40]);
+        self.address(0);
+        self.load();
+        self.op(op::I32_EQZ);
+        self.code.extend_from_slice(&[op::BR_IF, 1]);
+        body(self);
+        self.code.extend_from_slice(&[op::BR, 0, op::END, op::END]);
+    }
+
+    fn block(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    self.address(*offset);
+                    self.local(op::LOCAL_TEE, ADDRESS);
+                    self.local(op::LOCAL_GET, ADDRESS);
+                    self.load();
+                    self.constant(*delta as i32);
+                    self.op(op::I32_ADD);
+                    self.store();
+                }
+                NodeKind::Move(distance) => {
+                    self.index(*distance);
+                    self.local(op::LOCAL_SET, POINTER);
+                }
+                NodeKind::Clear { offset } => {
+                    self.address(*offset);
+                    self.constant(0);
+                    self.store();
+                }
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    self.address(*offset);
+                    self.local(op::LOCAL_TEE, ADDRESS);
+                    self.local(op::LOCAL_GET, ADDRESS);
+                    self.load();
+                    self.address(*src);
+                    self.load();
+                    self.constant(*factor as i32);
+                    self.op(op::I32_MUL);
+                    self.op(op::I32_ADD);
+                    self.store();
+                }
+                NodeKind::Scan(step) => self.while_nonzero(|body| {
+                    body.index(*step);
+                    body.local(op::LOCAL_SET, POINTER);
+                }),
+                NodeKind::Print { offset } => {
+                    self.address(*offset);
+                    self.load();
+                    self.local(op::CALL, self.write);
+                }
+                NodeKind::Input { offset } => {
+                    self.local(op::CALL, self.read);
+                    self.local(op::LOCAL_SET, VALUE);
+                    self.address(*offset);
+                    self.local(op::LOCAL_SET, ADDRESS);
+                    self.local(op::LOCAL_GET, VALUE);
+                    self.constant(-1);
+                    self.op(op::I32_NE);
+                    self.code.extend_from_slice(&[op::IF, 0x40]);
+                    self.local(op::LOCAL_GET, ADDRESS);
+                    self.local(op::LOCAL_GET, VALUE);
+                    self.store();
+                    let eof = match self.config.eof {
+                        EofPolicy::Unchanged => None,
+                        EofPolicy::Zero => Some(0),
+                        EofPolicy::Max => Some(self.config.cell_width.mask() as i32),
+                    };
+                    if let Some(value) = eof {
+                        self.op(op::ELSE);
+                        self.local(op::LOCAL_GET, ADDRESS);
+                        self.constant(value);
+                        self.store();
+                    }
+                    self.op(op::END);
+                }
+                NodeKind::Loop(body) => self.while_nonzero(|this| this.block(body)),
+            }
+        }
+    }
+}
+
+/// The WASI helpers: `getc` reads one byte (or -1) and `putc` writes one,
+/// using an iovec and a one-byte buffer at `scratch`.
+fn wasi_helpers(scratch: i32) -> (Vec<u8>, Vec<u8>) {
+    let (iovec, count, byte) = (scratch, scratch + 8, scratch + 16);
+    let setup = |code: &mut Vec<u8>| {
+        for (address, value) in [(iovec, byte), (iovec + 4, 1)] {
+            code.push(op::I32_CONST);
+            signed(code, address);
+            code.push(op::I32_CONST);
+            signed(code, value);
+            code.extend_from_slice(&[op::I32_STORE, 2, 0]);
+        }
+    };
+    let call = |code: &mut Vec<u8>, fd: i32, function: u8| {
+        for value in [fd, iovec, 1, count] {
+            code.push(op::I32_CONST);
+            signed(code, value);
+        }
+        code.extend_from_slice(&[op::CALL, function]);
+    };
+
+    let mut getc = Vec::new();
+    setup(&mut getc);
+    call(&mut getc, 0, 0);
+    // errno == 0 && count != 0 ? byte : -1
+    getc.extend_from_slice(&[op::I32_EQZ, op::IF, I32, op::I32_CONST]);
+    signed(&mut getc, count);
+    getc.extend_from_slice(&[op::I32_LOAD, 2, 0, op::I32_EQZ, op::IF, I32]);
+    getc.extend_from_slice(&[op::I32_CONST, 0x7f, op::ELSE, op::I32_CONST]);
+    signed(&mut getc, byte);
+    getc.extend_from_slice(&[op::I32_LOAD8_U, 0, 0, op::END]);
+    getc.extend_from_slice(&[op::ELSE, op::I32_CONST, 0x7f, op::END]);
+
+    let mut putc = Vec::new();
+    putc.push(op::I32_CONST);
+    signed(&mut putc, byte);
+    putc.extend_from_slice(&[op::LOCAL_GET, 0, op::I32_STORE8, 0, 0]);
+    setup(&mut putc);
+    call(&mut putc, 1, 1);
+    putc.push(op::DROP);
+    (function(0, &getc), function(0, &putc))
+}
+
+/// A WebAssembly module for `nodes`. The tape starts at address 0 of the
+/// exported `memory`, one cell per `cell_width` bits. Returns `None` if the
+/// tape doesn't fit in a 32-bit address space.
+pub fn generate(nodes: &[Node], config: Config, flavour: Flavour) -> Option<Vec<u8>> {
+    if config.tape_size > (i32::MAX / 4) as usize {
+        return None;
+    }
+    let cell_bytes = config.cell_width.bits() as usize / 8;
+    let scratch = (config.tape_size * cell_bytes).div_ceil(8) * 8;
+    let pages = (scratch + 32).div_ceil(0x1_0000);
+
+    let mut module = b"\0asm".to_vec();
+    module.extend_from_slice(&1u32.to_le_bytes());
+
+    // () -> (), () -> i32, (i32) -> (), (i32, i32, i32, i32) -> i32
+    let types: [&[u8]; 4] = [
+        &[0x60, 0, 0],
+        &[0x60, 0, 1, I32],
+        &[0x60, 1, I32, 0],
+        &[0x60, 4, I32, I32, I32, I32, 1, I32],
+    ];
+    section(&mut module, 1, types.len(), &types.concat());
+
+    let (imports, read, write, entry, helpers) = match flavour {
+        Flavour::Imports => (
+            vec![("env", "read", 1), ("env", "write", 2)],
+            0,
+            1,
+            "run",
+            None,
+        ),
+        Flavour::Wasi => (
+            vec![
+                ("wasi_snapshot_preview1", "fd_read", 3),
+                ("wasi_snapshot_preview1", "fd_write", 3),
+            ],
+            2,
+            3,
+            "_start",
+            Some(wasi_helpers(scratch as i32)),
+        ),
+    };
+    let mut import_section = Vec::new();
+    for (module_name, field, type_index) in &imports {
+        name(&mut import_section, module_name);
+        name(&mut import_section, field);
+        import_section.extend_from_slice(&[0x00, *type_index]);
+    }
+    section(&mut module, 2, imports.len(), &import_section);
+
+    let mut body = Body {
+        code: Vec::new(),
+        config,
+        read,
+        write,
+    };
+    body.block(nodes);
+    let mut functions = Vec::new();
+    let mut function_types = Vec::new();
+    if let Some((getc, putc)) = helpers {
+        functions.extend([getc, putc]);
+        function_types.extend([1, 2]);
+    }
+    functions.push(function(4, &body.code));
+    function_types.push(0);
+    section(&mut module, 3, function_types.len(), &function_types);
+
+    let mut memory = vec![0x00];
+    unsigned(&mut memory, pages as u32);
+    section(&mut module, 5, 1, &memory);
+
+    let mut exports = Vec::new();
+    name(&mut exports, entry);
+    exports.push(0x00);
+    unsigned(&mut exports, (imports.len() + functions.len() - 1) as u32);
+    name(&mut exports, "memory");
+    exports.extend_from_slice(&[0x02, 0]);
+    section(&mut module, 7, 2, &exports);
+
+    section(&mut module, 10, functions.len(), &functions.concat());
+    Some(module)
+}
@@ -0,0 +1,314 @@
+use crate::config::{Config, EofPolicy};
+use crate::vm::{Bytecode, Insn};
+
+/// Size of the output buffer `Io::Syscalls` code writes through.
+pub const OUTPUT_BUFFER_SIZE: u32 = 4096;
+
+/// How the generated code does `,` and `.`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Io {
+    /// A function called as `fn(context, ...)` with the sysv64 ABI. Code built
+    /// this way is itself a function `fn(tape, pointer, tape_size, context)`:
+    /// `read(context) -> i32` returns a byte or -1 at the end of the input,
+    /// `write(context, byte) -> u32` returns nonzero to stop the program, and
+    /// the final pointer is stored in the first word of the context.
+    Callbacks { read: u64, write: u64 },
+    /// Linux syscalls, with the tape and an `OUTPUT_BUFFER_SIZE` byte output
+    /// buffer at fixed addresses. Code built this way is a program entry
+    /// point and ends with `exit(0)`.
+    Syscalls { tape: u64, output_buffer: u64 },
+}
+
+/// Hand-encodes x86-64 machine code for bytecode. Registers: rbx is the tape
+/// (32-bit cells), r12 the pointer and r13 the tape length. With callbacks r14
+/// holds the context; with syscalls r15 counts the buffered output bytes.
+/// rax, rcx, rdx, rsi, rdi and r11 are scratch.
+pub struct Assembler {
+    code: Vec<u8>,
+    config: Config,
+    io: Io,
+}
+
+impl Assembler {
+    pub fn new(config: Config, io: Io) -> Self {
+        Assembler {
+            code: Vec::new(),
+            config,
+            io,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn imm64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Emits a 32-bit relative jump or call with opcode `opcode` and returns
+    /// where its offset is, for `patch`.
+    fn jump(&mut self, opcode: &[u8]) -> usize {
+        self.bytes(opcode);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let relative = target as i64 - (at as i64 + 4);
+        self.code[at..at + 4].copy_from_slice(&(relative as i32).to_le_bytes());
+    }
+
+    /// rax = (r12 + offset) wrapped onto the tape.
+    fn index(&mut self, offset: isize) {
+        if offset == 0 {
+            self.bytes(&[0x4c, 0x89, 0xe0]); // mov rax, r12
+            return;
+        }
+        self.bytes(&[0x49, 0x8d, 0x84, 0x24]); // lea rax, [r12 + offset]
+        self.imm32(offset as i32 as u32);
+        if offset > 0 {
+            self.bytes(&[0x4c, 0x39, 0xe8]); // cmp rax, r13
+            self.bytes(&[0x72, 0x03]); // jb +3
+            self.bytes(&[0x4c, 0x29, 0xe8]); // sub rax, r13
+        } else {
+            self.bytes(&[0x48, 0x85, 0xc0]); // test rax, rax
+            self.bytes(&[0x79, 0x03]); // jns +3
+            self.bytes(&[0x4c, 0x01, 0xe8]); // add rax, r13
+        }
+    }
+
+    /// Masks the cell at rax to the cell width.
+    fn mask(&mut self) {
+        let mask = self.config.cell_width.mask();
+        if mask != u32::MAX {
+            self.bytes(&[0x81, 0x24, 0x83]); // and dword [rbx + rax*4], mask
+            self.imm32(mask);
+        }
+    }
+
+    fn compare_current_cell(&mut self) {
+        self.bytes(&[0x42, 0x83, 0x3c, 0xa3, 0x00]); // cmp dword [rbx + r12*4], 0
+    }
+
+    fn call_absolute(&mut self, function: u64) {
+        self.bytes(&[0x4c, 0x89, 0xf7]); // mov rdi, r14
+        self.bytes(&[0x48, 0xb8]); // mov rax, function
+        self.imm64(function);
+        self.bytes(&[0xff, 0xd0]); // call rax
+    }
+
+    fn prologue(&mut self) {
+        match self.io {
+            Io::Callbacks { .. } => {
+                // Keep the callee-saved registers and leave the stack 16-byte
+                // aligned for the calls.
+                self.bytes(&[0x55, 0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+                self.bytes(&[0x48, 0x83, 0xec, 0x08]); // sub rsp, 8
+                self.bytes(&[0x48, 0x89, 0xfb]); // mov rbx, rdi
+                self.bytes(&[0x49, 0x89, 0xf4]); // mov r12, rsi
+                self.bytes(&[0x49, 0x89, 0xd5]); // mov r13, rdx
+                self.bytes(&[0x49, 0x89, 0xce]); // mov r14, rcx
+            }
+            Io::Syscalls { tape, .. } => {
+                self.bytes(&[0x48, 0xbb]); // mov rbx, tape
+                self.imm64(tape);
+                self.bytes(&[0x45, 0x31, 0xe4]); // xor r12d, r12d
+                self.bytes(&[0x49, 0xbd]); // mov r13, tape_size
+                self.imm64(self.config.tape_size as u64);
+                self.bytes(&[0x45, 0x31, 0xff]); // xor r15d, r15d
+            }
+        }
+    }
+
+    pub fn assemble(mut self, bytecode: &Bytecode) -> Vec<u8> {
+        self.prologue();
+        let mut starts = Vec::with_capacity(bytecode.code.len());
+        let mut loop_jumps = Vec::new();
+        let mut exits = Vec::new();
+        let mut flush_calls = Vec::new();
+        let mut read_calls = Vec::new();
+        for insn in &bytecode.code {
+            starts.push(self.code.len());
+            match *insn {
+                Insn::Add { offset, delta } => {
+                    self.index(offset);
+                    self.bytes(&[0x81, 0x04, 0x83]); // add dword [rbx + rax*4], delta
+                    self.imm32(delta);
+                    self.mask();
+                }
+                Insn::Move(distance) => {
+                    self.index(distance);
+                    self.bytes(&[0x49, 0x89, 0xc4]); // mov r12, rax
+                }
+                Insn::Clear { offset } => {
+                    self.index(offset);
+                    self.bytes(&[0xc7, 0x04, 0x83]); // mov dword [rbx + rax*4], 0
+                    self.imm32(0);
+                }
+                Insn::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    self.index(src);
+                    self.bytes(&[0x8b, 0x0c, 0x83]); // mov ecx, [rbx + rax*4]
+                    self.bytes(&[0x69, 0xc9]); // imul ecx, ecx, factor
+                    self.imm32(factor);
+                    self.index(offset);
+                    self.bytes(&[0x01, 0x0c, 0x83]); // add [rbx + rax*4], ecx
+                    self.mask();
+                }
+                Insn::Scan(step) => {
+                    let top = self.code.len();
+                    self.compare_current_cell();
+                    let done = self.jump(&[0x0f, 0x84]); // je done
+                    self.index(step);
+                    self.bytes(&[0x49, 0x89, 0xc4]); // mov r12, rax
+                    let back = self.jump(&[0xe9]); // jmp top
+                    self.patch(back, top);
+                    self.patch(done, self.code.len());
+                }
+                Insn::Print { offset } => {
+                    self.index(offset);
+                    self.bytes(&[0x8b, 0x34, 0x83]); // mov esi, [rbx + rax*4]
+                    match self.io {
+                        Io::Callbacks { write, .. } => {
+                            self.call_absolute(write);
+                            self.bytes(&[0x85, 0xc0]); // test eax, eax
+                            exits.push(self.jump(&[0x0f, 0x85])); // jnz exit
+                        }
+                        Io::Syscalls { output_buffer, .. } => {
+                            self.bytes(&[0x48, 0xbf]); // mov rdi, output_buffer
+                            self.imm64(output_buffer);
+                            self.bytes(&[0x42, 0x88, 0x34, 0x3f]); // mov [rdi + r15], sil
+                            self.bytes(&[0x49, 0xff, 0xc7]); // inc r15
+                            self.bytes(&[0x49, 0x81, 0xff]); // cmp r15, OUTPUT_BUFFER_SIZE
+                            self.imm32(OUTPUT_BUFFER_SIZE);
+                            self.bytes(&[0x75, 0x05]); // jne +5
+                            flush_calls.push(self.jump(&[0xe8])); // call flush
+                        }
+                    }
+                }
+                Insn::Input { offset } => {
+                    match self.io {
+                        Io::Callbacks { read, .. } => self.call_absolute(read),
+                        Io::Syscalls { .. } => read_calls.push(self.jump(&[0xe8])), // call read
+                    }
+                    self.bytes(&[0x89, 0xc1]); // mov ecx, eax
+                    self.index(offset);
+                    self.bytes(&[0x83, 0xf9, 0xff]); // cmp ecx, -1
+                    let eof = self.jump(&[0x0f, 0x84]); // je eof
+                    self.bytes(&[0x89, 0x0c, 0x83]); // mov [rbx + rax*4], ecx
+                    let done = self.jump(&[0xe9]); // jmp done
+                    self.patch(eof, self.code.len());
+                    let value = match self.config.eof {
+                        EofPolicy::Unchanged => None,
+                        EofPolicy::Zero => Some(0),
+                        EofPolicy::Max => Some(self.config.cell_width.mask()),
+                    };
+                    if let Some(value) = value {
+                        self.bytes(&[0xc7, 0x04, 0x83]); // mov dword [rbx + rax*4], value
+                        self.imm32(value);
+                    }
+                    self.patch(done, self.code.len());
+                }
+                Insn::JumpIfZero(target) => {
+                    self.compare_current_cell();
+                    loop_jumps.push((self.jump(&[0x0f, 0x84]), target)); // je target
+                }
+                Insn::JumpIfNonZero(target) => {
+                    self.compare_current_cell();
+                    loop_jumps.push((self.jump(&[0x0f, 0x85]), target)); // jne target
+                }
+                Insn::Halt => match self.io {
+                    Io::Callbacks { .. } => self.bytes(&[0x31, 0xc0]), // xor eax, eax
+                    Io::Syscalls { .. } => {
+                        flush_calls.push(self.jump(&[0xe8])); // call flush
+                        self.bytes(&[0xb8, 0x3c, 0x00, 0x00, 0x00]); // mov eax, 60 (exit)
+                        self.bytes(&[0x31, 0xff]); // xor edi, edi
+                        self.bytes(&[0x0f, 0x05]); // syscall
+                    }
+                },
+            }
+        }
+        starts.push(self.code.len());
+        for (at, target) in loop_jumps {
+            self.patch(at, starts[target]);
+        }
+        match self.io {
+            Io::Callbacks { .. } => {
+                let exit = self.code.len();
+                for at in exits {
+                    self.patch(at, exit);
+                }
+                self.bytes(&[0x4d, 0x89, 0x26]); // mov [r14], r12
+                self.bytes(&[0x48, 0x83, 0xc4, 0x08]); // add rsp, 8
+                self.bytes(&[0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b, 0x5d]);
+                self.bytes(&[0xc3]); // ret
+            }
+            Io::Syscalls { output_buffer, .. } => {
+                let flush = self.flush_routine(output_buffer);
+                for at in flush_calls {
+                    self.patch(at, flush);
+                }
+                let read = self.read_routine(flush);
+                for at in read_calls {
+                    self.patch(at, read);
+                }
+            }
+        }
+        self.code
+    }
+
+    /// Writes out the r15 buffered bytes, retrying short writes, and empties
+    /// the buffer. Gives up quietly on errors, like a closed pipe.
+    fn flush_routine(&mut self, output_buffer: u64) -> usize {
+        let start = self.code.len();
+        self.bytes(&[0x48, 0xbe]); // mov rsi, output_buffer
+        self.imm64(output_buffer);
+        let top = self.code.len();
+        self.bytes(&[0x4d, 0x85, 0xff]); // test r15, r15
+        let empty = self.jump(&[0x0f, 0x84]); // je done
+        self.bytes(&[0xb8, 0x01, 0x00, 0x00, 0x00]); // mov eax, 1 (write)
+        self.bytes(&[0xbf, 0x01, 0x00, 0x00, 0x00]); // mov edi, 1 (stdout)
+        self.bytes(&[0x4c, 0x89, 0xfa]); // mov rdx, r15
+        self.bytes(&[0x0f, 0x05]); // syscall
+        self.bytes(&[0x48, 0x85, 0xc0]); // test rax, rax
+        let failed = self.jump(&[0x0f, 0x8e]); // jle done
+        self.bytes(&[0x48, 0x01, 0xc6]); // add rsi, rax
+        self.bytes(&[0x49, 0x29, 0xc7]); // sub r15, rax
+        let back = self.jump(&[0xe9]); // jmp top
+        self.patch(back, top);
+        let done = self.code.len();
+        self.patch(empty, done);
+        self.patch(failed, done);
+        self.bytes(&[0x45, 0x31, 0xff]); // xor r15d, r15d
+        self.bytes(&[0xc3]); // ret
+        start
+    }
+
+    /// Flushes the output, then reads one byte from stdin into eax, or -1 at
+    /// the end of the input.
+    fn read_routine(&mut self, flush: usize) -> usize {
+        let start = self.code.len();
+        let call = self.jump(&[0xe8]); // call flush
+        self.patch(call, flush);
+        self.bytes(&[0x31, 0xc0]); // xor eax, eax (read)
+        self.bytes(&[0x31, 0xff]); // xor edi, edi (stdin)
+        self.bytes(&[0x48, 0x8d, 0x74, 0x24, 0xf8]); // lea rsi, [rsp - 8]
+        self.bytes(&[0xba, 0x01, 0x00, 0x00, 0x00]); // mov edx, 1
+        self.bytes(&[0x0f, 0x05]); // syscall
+        self.bytes(&[0x48, 0x83, 0xf8, 0x01]); // cmp rax, 1
+        self.bytes(&[0x75, 0x06]); // jne +6
+        self.bytes(&[0x0f, 0xb6, 0x44, 0x24, 0xf8]); // movzx eax, byte [rsp - 8]
+        self.bytes(&[0xc3]); // ret
+        self.bytes(&[0xb8, 0xff, 0xff, 0xff, 0xff]); // mov eax, -1
+        self.bytes(&[0xc3]); // ret
+        start
+    }
+}
@@ -0,0 +1,248 @@
+use crate::interpreter::{Limits, CSIZE};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl CellWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+        }
+    }
+
+    pub fn mask(self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 0xff,
+            CellWidth::Bits16 => 0xffff,
+            CellWidth::Bits32 => 0xffff_ffff,
+        }
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellWidth::Bits8),
+            "16" => Ok(CellWidth::Bits16),
+            "32" => Ok(CellWidth::Bits32),
+            _ => Err(ConfigError::BadValue {
+                key: "cell_width".to_owned(),
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// What `,` does once the input is used up and closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EofPolicy {
+    Unchanged,
+    Zero,
+    Max,
+}
+
+impl FromStr for EofPolicy {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchanged" => Ok(EofPolicy::Unchanged),
+            "zero" | "0" => Ok(EofPolicy::Zero),
+            "max" | "-1" => Ok(EofPolicy::Max),
+            _ => Err(ConfigError::BadValue {
+                key: "eof".to_owned(),
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    pub cell_width: CellWidth,
+    pub eof: EofPolicy,
+    pub tape_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cell_width: CellWidth::Bits8,
+            eof: EofPolicy::Unchanged,
+            tape_size: CSIZE,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    BadValue { key: String, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::BadValue { key, value } => {
+                write!(f, "bad value {:?} for {}", value, key)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::BadValue {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+/// Settings read from the `# key: value` lines at the top of a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    pub config: Config,
+    pub limits: Limits,
+    /// How many bytes at the start of the source are header lines.
+    pub header_len: usize,
+    /// The source with the header lines blanked out, so byte positions in it
+    /// still match the original file.
+    pub source: String,
+}
+
+/// Reads the header block: the leading lines that look like `# key: value`
+/// with a key listed below. The first line that doesn't ends the block, and
+/// everything from there on is program.
+///
+/// Keys: `cell_width` (8, 16, 32), `eof` (unchanged, zero, max), `tape_size`,
+/// `max_steps`, `max_output`, `max_cells` and `timeout_ms`.
+pub fn parse_headers(source: &str) -> Result<Headers, ConfigError> {
+    let mut config = Config::default();
+    let mut limits = Limits::default();
+    let mut header_len = 0;
+    for line in source.split_inclusive('\n') {
+        let setting = line
+            .trim()
+            .strip_prefix('#')
+            .and_then(|rest| rest.split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim()));
+        let (key, value) = match setting {
+            Some(setting) => setting,
+            None => break,
+        };
+        match key {
+            "cell_width" => config.cell_width = value.parse()?,
+            "eof" => config.eof = value.parse()?,
+            "tape_size" => config.tape_size = number(key, value)?,
+            "max_steps" => limits.max_steps = Some(number(key, value)?),
+            "max_output" => limits.max_output_bytes = Some(number(key, value)?),
+            "max_cells" => limits.max_cells_touched = Some(number(key, value)?),
+            "timeout_ms" => limits.timeout = Some(Duration::from_millis(number(key, value)?)),
+            _ => break,
+        }
+        header_len += line.len();
+    }
+    if config.tape_size == 0 {
+        return Err(ConfigError::BadValue {
+            key: "tape_size".to_owned(),
+            value: "0".to_owned(),
+        });
+    }
+    let blanked: String = source[..header_len]
+        .bytes()
+        .map(|b| if b == b'\n' { '\n' } else { ' ' })
+        .collect();
+    Ok(Headers {
+        config,
+        limits,
+        header_len,
+        source: blanked + &source[header_len..],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_headers, CellWidth, Config, ConfigError, EofPolicy};
+    use crate::ops::{Dialect, Program};
+    use std::time::Duration;
+
+    #[test]
+    fn reads_the_header_block() {
+        let headers =
+            parse_headers("# cell_width: 16\n#eof:zero\n  # tape_size : 100\n# timeout_ms: 5\n+.")
+                .unwrap();
+        assert_eq!(
+            headers.config,
+            Config {
+                cell_width: CellWidth::Bits16,
+                eof: EofPolicy::Zero,
+                tape_size: 100,
+            }
+        );
+        assert_eq!(headers.limits.timeout, Some(Duration::from_millis(5)));
+        assert_eq!(headers.limits.max_steps, None);
+    }
+
+    #[test]
+    fn stops_at_an_unknown_key() {
+        let text = "# eof: max\n# author: me\n# cell_width: 32\n+.";
+        let headers = parse_headers(text).unwrap();
+        assert_eq!(headers.config.eof, EofPolicy::Max);
+        assert_eq!(headers.config.cell_width, CellWidth::Bits8);
+        assert_eq!(headers.header_len, "# eof: max\n".len());
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let bad = |key: &str, value: &str| {
+            Err(ConfigError::BadValue {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            })
+        };
+        assert_eq!(
+            parse_headers("# cell_width: 12\n+"),
+            bad("cell_width", "12")
+        );
+        assert_eq!(parse_headers("# eof: never\n+"), bad("eof", "never"));
+        assert_eq!(parse_headers("# max_steps: -1\n+"), bad("max_steps", "-1"));
+        assert_eq!(parse_headers("# tape_size: 0\n+"), bad("tape_size", "0"));
+    }
+
+    #[test]
+    fn ignores_headers_after_the_first_command() {
+        let text = "+\n# cell_width: 16\n.";
+        let headers = parse_headers(text).unwrap();
+        assert_eq!(headers.config, Config::default());
+        assert_eq!(headers.header_len, 0);
+        assert_eq!(headers.source, text);
+    }
+
+    #[test]
+    fn blanks_headers_without_moving_the_program() {
+        let text = "# cell_width: 16\r\n# eof: zero\n+[-]";
+        let headers = parse_headers(text).unwrap();
+        assert_eq!(headers.source.len(), text.len());
+        assert_eq!(headers.header_len, text.find('+').unwrap());
+        assert!(headers.source[..headers.header_len].trim().is_empty());
+        assert_eq!(&headers.source[headers.header_len..], "+[-]");
+        let program = Program::parse(&headers.source, Dialect::Brainfuck).unwrap();
+        assert_eq!(
+            program.spans[1],
+            text.find('[').unwrap()..text.find('[').unwrap() + 1
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use crate::codegen::{describe, reads_input, Source};
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How many nodes a loop whose inputs are all known may run while
+/// decompiling before it's written out as a loop after all.
+const EVALUATION_BUDGET: usize = 1_000_000;
+
+/// What the decompiler knows about the machine at some point in the program.
+#[derive(Clone, Default)]
+struct State {
+    /// The absolute cell the pointer is on, while that's known.
+    position: Option<usize>,
+    /// Cells with a known (`Some`) or unknown (`None`) value.
+    cells: BTreeMap<usize, Option<u32>>,
+    /// Whether cells missing from `cells` are known to be zero.
+    zeroed: bool,
+    /// Known cells whose value hasn't been written out yet.
+    dirty: BTreeSet<usize>,
+    /// The values the pseudo-code so far has given cells (missing ones are
+    /// zero if `zeroed`), so cells that end up where they were aren't
+    /// written out again.
+    shown: BTreeMap<usize, Option<u32>>,
+    /// Known output that hasn't been written out yet.
+    pending: Vec<u8>,
+}
+
+struct Decompiler<'a> {
+    out: String,
+    config: Config,
+    source: &'a Source<'a>,
+    line: usize,
+    depth: usize,
+    /// Where in the source the node being decompiled starts.
+    at: usize,
+    state: State,
+}
+
+/// How far `nodes` move the pointer, if that's the same every time.
+fn displacement(nodes: &[Node]) -> Option<isize> {
+    let mut total = 0;
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Move(distance) => total += distance,
+            NodeKind::Scan(_) => return None,
+            NodeKind::Loop(body) if displacement(body) != Some(0) => return None,
+            _ => {}
+        }
+    }
+    Some(total)
+}
+
+/// Whether a loop with this body can only go round once: the body leaves the
+/// pointer where it was and its last write to the loop's cell clears it.
+fn runs_once(body: &[Node]) -> bool {
+    if displacement(body) != Some(0) {
+        return false;
+    }
+    for node in body.iter().rev() {
+        match &node.kind {
+            NodeKind::Clear { offset: 0 } => return true,
+            NodeKind::Add { offset: 0, .. }
+            | NodeKind::MulAdd { offset: 0, .. }
+            | NodeKind::Input { offset: 0 }
+            | NodeKind::Move(_)
+            | NodeKind::Scan(_)
+            | NodeKind::Loop(_) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn signed(value: u32, mask: u32) -> i64 {
+    if value > mask / 2 {
+        value as i64 - mask as i64 - 1
+    } else {
+        value as i64
+    }
+}
+
+impl Decompiler<'_> {
+    fn emit(&mut self, text: &str) {
+        let indent = "    ".repeat(self.depth);
+        let line = self.source.line(self.at);
+        if line != self.line {
+            self.line = line;
+            self.out += &format!("{}// {}:{}\n", indent, self.source.name, line);
+        }
+        self.out += &format!("{}{}\n", indent, text);
+    }
+
+    fn cell(&self, offset: isize) -> Option<usize> {
+        let size = self.config.tape_size as isize;
+        let position = self.state.position? as isize;
+        Some((position + offset % size).rem_euclid(size) as usize)
+    }
+
+    /// `c12` for a cell whose address is known, `p[3]` for one that is only
+    /// known relative to the pointer.
+    fn name(&self, offset: isize) -> String {
+        match self.cell(offset) {
+            Some(cell) => format!("c{}", cell),
+            None => format!("p[{}]", offset),
+        }
+    }
+
+    fn value(&self, offset: isize) -> Option<u32> {
+        let cell = self.cell(offset)?;
+        match self.state.cells.get(&cell) {
+            Some(value) => *value,
+            None => self.state.zeroed.then_some(0),
+        }
+    }
+
+    /// Records a value for a cell at a known address, to be written out later.
+    fn set(&mut self, offset: isize, value: u32) {
+        if let Some(cell) = self.cell(offset) {
+            let value = value & self.config.cell_width.mask();
+            self.state.cells.insert(cell, Some(value));
+            self.state.dirty.insert(cell);
+        }
+    }
+
+    fn forget(&mut self, offset: isize) {
+        if let Some(cell) = self.cell(offset) {
+            self.state.cells.insert(cell, None);
+            self.state.dirty.remove(&cell);
+            self.state.shown.insert(cell, None);
+        }
+    }
+
+    fn show(&mut self, cell: usize) {
+        let value = self.state.cells[&cell];
+        let shown = match self.state.shown.get(&cell) {
+            Some(shown) => *shown,
+            None => self.state.zeroed.then_some(0),
+        };
+        if value != shown {
+            self.emit(&format!("c{} = {}", cell, value.unwrap_or(0)));
+            self.state.shown.insert(cell, value);
+        }
+    }
+
+    fn flush_output(&mut self) {
+        if !self.state.pending.is_empty() {
+            let text: String = self.state.pending.drain(..).map(|b| b as char).collect();
+            self.emit(&format!("print({:?})", text));
+        }
+    }
+
+    /// Writes out the value of the cell at `offset` if it's waiting to be.
+    fn materialise(&mut self, offset: isize) {
+        if let Some(cell) = self.cell(offset) {
+            if self.state.dirty.remove(&cell) {
+                self.show(cell);
+            }
+        }
+    }
+
+    /// Writes out everything that's waiting to be.
+    fn sync(&mut self) {
+        self.flush_output();
+        for cell in std::mem::take(&mut self.state.dirty) {
+            self.show(cell);
+        }
+    }
+
+    /// Runs `nodes` for real while every cell is known, so loops that only
+    /// compute constants turn into their results. Gives up (returning
+    /// `false`) when the budget runs out.
+    fn evaluate(&mut self, nodes: &[Node], budget: &mut usize) -> bool {
+        for node in nodes {
+            if *budget == 0 {
+                return false;
+            }
+            *budget -= 1;
+            let value = |this: &Self, offset| this.value(offset).unwrap_or(0);
+            match &node.kind {
+                NodeKind::Add { offset, delta } => {
+                    self.set(*offset, value(self, *offset).wrapping_add(*delta))
+                }
+                NodeKind::Move(distance) => self.step(*distance),
+                NodeKind::Clear { offset } => self.set(*offset, 0),
+                NodeKind::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    let product = value(self, *src).wrapping_mul(*factor);
+                    self.set(*offset, value(self, *offset).wrapping_add(product));
+                }
+                NodeKind::Scan(step) => {
+                    while value(self, 0) != 0 {
+                        if *budget == 0 {
+                            return false;
+                        }
+                        *budget -= 1;
+                        self.step(*step);
+                    }
+                }
+                NodeKind::Print { offset } => self.state.pending.push(value(self, *offset) as u8),
+                NodeKind::Input { .. } => return false,
+                NodeKind::Loop(body) => {
+                    while value(self, 0) != 0 {
+                        if !self.evaluate(body, budget) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Whether the whole tape and the pointer are known, so the program can
+    /// be run ahead of time from here.
+    fn all_known(&self) -> bool {
+        self.state.position.is_some()
+            && self.state.zeroed
+            && self.state.cells.values().all(Option::is_some)
+    }
+
+    fn step(&mut self, distance: isize) {
+        self.state.position = self.cell(distance);
+    }
+
+    fn add(&mut self, offset: isize, amount: u32) {
+        let mask = self.config.cell_width.mask();
+        match self.value(offset) {
+            Some(value) => self.set(offset, value.wrapping_add(amount)),
+            None => {
+                self.flush_output();
+                let amount = signed(amount & mask, mask);
+                let name = self.name(offset);
+                if amount < 0 {
+                    self.emit(&format!("{} -= {}", name, -amount));
+                } else {
+                    self.emit(&format!("{} += {}", name, amount));
+                }
+                self.forget(offset);
+            }
+        }
+    }
+
+    /// `MulAdd`s from one cell followed by clearing it, which is what a
+    /// multiplication loop turns into.
+    fn transfer(&mut self, src: isize, targets: &[(isize, u32)]) {
+        let mask = self.config.cell_width.mask();
+        if let Some(value) = self.value(src) {
+            for &(offset, factor) in targets {
+                self.add(offset, value.wrapping_mul(factor));
+            }
+            self.set(src, 0);
+            return;
+        }
+        self.flush_output();
+        for &(offset, _) in targets {
+            self.materialise(offset);
+        }
+        let names: Vec<String> = targets
+            .iter()
+            .map(|&(offset, _)| self.name(offset))
+            .collect();
+        let text = if targets.iter().all(|&(_, factor)| factor & mask == 1) {
+            let verb = if targets.len() == 1 { "move" } else { "copy" };
+            format!("{}({} -> {})", verb, self.name(src), names.join(", "))
+        } else {
+            let products: Vec<String> = targets
+                .iter()
+                .zip(&names)
+                .map(|(&(_, factor), name)| format!("{} * {}", name, signed(factor & mask, mask)))
+                .collect();
+            format!("multiply({} -> {})", self.name(src), products.join(", "))
+        };
+        self.emit(&text);
+        for &(offset, _) in targets {
+            self.forget(offset);
+        }
+        if let Some(cell) = self.cell(src) {
+            self.state.cells.insert(cell, Some(0));
+            self.state.dirty.remove(&cell);
+            self.state.shown.insert(cell, Some(0));
+        }
+    }
+
+    fn block(&mut self, nodes: &[Node]) {
+        let mut i = 0;
+        while i < nodes.len() {
+            let node = &nodes[i];
+            self.at = node.span.start;
+            i += 1;
+            match &node.kind {
+                NodeKind::Add { offset, delta } => self.add(*offset, *delta),
+                NodeKind::Move(distance) => {
+                    if self.state.position.is_some() {
+                        self.step(*distance);
+                    } else if *distance < 0 {
+                        self.emit(&format!("p -= {}", -distance));
+                    } else {
+                        self.emit(&format!("p += {}", distance));
+                    }
+                }
+                NodeKind::Clear { offset } => {
+                    if self.state.position.is_some() {
+                        self.set(*offset, 0);
+                    } else {
+                        self.flush_output();
+                        self.emit(&format!("{} = 0", self.name(*offset)));
+                    }
+                }
+                NodeKind::MulAdd { src, .. } => {
+                    let mut targets = Vec::new();
+                    let mut j = i - 1;
+                    while let Some(NodeKind::MulAdd {
+                        src: from,
+                        offset,
+                        factor,
+                    }) = nodes.get(j).map(|node| &node.kind)
+                    {
+                        if from != src || offset == src {
+                            break;
+                        }
+                        targets.push((*offset, *factor));
+                        j += 1;
+                    }
+                    match nodes.get(j).map(|node| &node.kind) {
+                        Some(NodeKind::Clear { offset })
+                            if offset == src && !targets.is_empty() =>
+                        {
+                            self.transfer(*src, &targets);
+                            i = j + 1;
+                        }
+                        _ => self.multiply_add(node),
+                    }
+                }
+                NodeKind::Scan(step) => self.scan(*step),
+                NodeKind::Print { offset } => match self.value(*offset) {
+                    Some(value) => self.state.pending.push(value as u8),
+                    None => {
+                        self.flush_output();
+                        self.emit(&format!("print({})", self.name(*offset)));
+                    }
+                },
+                NodeKind::Input { offset } => {
+                    self.flush_output();
+                    if self.config.eof == EofPolicy::Unchanged {
+                        self.materialise(*offset);
+                    }
+                    self.emit(&format!("{} = input()", self.name(*offset)));
+                    self.forget(*offset);
+                }
+                NodeKind::Loop(body) => self.looped(body),
+            }
+        }
+    }
+
+    fn multiply_add(&mut self, node: &Node) {
+        if let NodeKind::MulAdd {
+            src,
+            offset,
+            factor,
+        } = &node.kind
+        {
+            if let Some(value) = self.value(*src) {
+                self.add(*offset, value.wrapping_mul(*factor));
+                return;
+            }
+            self.flush_output();
+            self.materialise(*offset);
+            let mask = self.config.cell_width.mask();
+            self.emit(&format!(
+                "{} += {} * {}",
+                self.name(*offset),
+                self.name(*src),
+                signed(factor & mask, mask)
+            ));
+            self.forget(*offset);
+        }
+    }
+
+    fn scan(&mut self, step: isize) {
+        if self.all_known() {
+            let saved = self.state.clone();
+            let node = Node {
+                kind: NodeKind::Scan(step),
+                span: self.at..self.at,
+            };
+            let mut budget = EVALUATION_BUDGET;
+            if self.evaluate(std::slice::from_ref(&node), &mut budget) {
+                return;
+            }
+            self.state = saved;
+        }
+        self.sync();
+        self.emit(&format!("p = find_zero({}, step {:+})", self.name(0), step));
+        self.state = State::default();
+    }
+
+    fn looped(&mut self, body: &[Node]) {
+        if self.value(0) == Some(0) {
+            return;
+        }
+        if self.all_known() && !reads_input(body) {
+            let saved = self.state.clone();
+            let mut budget = EVALUATION_BUDGET;
+            let mut finished = true;
+            while self.value(0) != Some(0) {
+                if !self.evaluate(body, &mut budget) {
+                    finished = false;
+                    break;
+                }
+            }
+            if finished {
+                return;
+            }
+            self.state = saved;
+        }
+        self.sync();
+        let balanced = displacement(body) == Some(0);
+        let keyword = if runs_once(body) { "if" } else { "while" };
+        self.emit(&format!("{} {} != 0 {{", keyword, self.name(0)));
+        let position = if balanced { self.state.position } else { None };
+        self.state = State {
+            position,
+            ..State::default()
+        };
+        self.depth += 1;
+        self.block(body);
+        self.sync();
+        self.depth -= 1;
+        self.emit("}");
+        self.state = State {
+            position,
+            ..State::default()
+        };
+        if let Some(cell) = self.cell(0) {
+            self.state.cells.insert(cell, Some(0));
+            self.state.shown.insert(cell, Some(0));
+        }
+    }
+}
+
+/// Structured pseudo-code for `nodes`, built by `ir::optimise` from the
+/// program in `source`: cells are named by address while the pointer's
+/// position is known, straight-line code and loops that only work on known
+/// values are run ahead of time (so constant output becomes string
+/// literals), and moves, copies, multiplications and loops that can only
+/// run once are called out.
+pub fn decompile(mut nodes: &[Node], config: Config, source: &Source) -> String {
+    // Where the pointer ends up doesn't matter.
+    while let [rest @ .., last] = nodes {
+        if !matches!(last.kind, NodeKind::Move(_)) {
+            break;
+        }
+        nodes = rest;
+    }
+    let mut decompiler = Decompiler {
+        out: format!(
+            "// Decompiled by bf from {}: {}.\n",
+            source.name,
+            describe(config)
+        ),
+        config,
+        source,
+        line: 0,
+        depth: 0,
+        at: 0,
+        state: State {
+            position: Some(0),
+            zeroed: true,
+            ..State::default()
+        },
+    };
+    decompiler.block(nodes);
+    decompiler.flush_output();
+    decompiler.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompile;
+    use crate::codegen::Source;
+    use crate::config::Config;
+    use crate::ir::optimise;
+    use crate::ops::{Dialect, Program};
+
+    fn decompiled(text: &str) -> String {
+        let program = Program::parse(text, Dialect::Brainfuck).unwrap();
+        decompile(
+            &optimise(&program),
+            Config::default(),
+            &Source::new("test.bf", text),
+        )
+    }
+
+    const HEADER: &str =
+        "// Decompiled by bf from test.bf: 8-bit cells, 30000-cell wrapping tape, \
+                          end of input leaves the cell unchanged.\n";
+
+    #[test]
+    fn folds_a_clear_loop_into_what_the_cell_becomes() {
+        assert_eq!(
+            decompiled(",[-]+."),
+            format!(
+                "{}// test.bf:1\nc0 = input()\nprint(\"\\u{{1}}\")\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn calls_out_a_multiply_loop() {
+        assert_eq!(
+            decompiled(",[->+++<]>."),
+            format!(
+                "{}// test.bf:1\nc0 = input()\nmultiply(c0 -> c1 * 3)\nprint(c1)\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn indents_nested_loops_with_their_lines() {
+        assert_eq!(
+            decompiled(",[\n>,[>+<-]<-\n]"),
+            format!(
+                "{}// test.bf:1\nc0 = input()\nwhile c0 != 0 {{\n    \
+                 // test.bf:2\n    c1 = input()\n    move(c1 -> c2)\n    c0 -= 1\n}}\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn loses_track_of_the_pointer_after_a_scan() {
+        assert_eq!(
+            decompiled(",[>,]<[<]>."),
+            format!(
+                "{}// test.bf:1\nc0 = input()\nwhile c0 != 0 {{\n    p[1] = input()\n    \
+                 p += 1\n}}\np -= 1\np = find_zero(p[0], step -1)\nprint(p[1])\n",
+                HEADER
+            )
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::Config;
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ops::{Program, OPS};
+use crate::rng::Rng;
+use std::mem::discriminant;
+
+/// Which inputs `compare` runs the programs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inputs {
+    /// Every input up to this many bytes long.
+    pub exhaustive: usize,
+    /// How many random inputs after those.
+    pub random: usize,
+    /// The longest random input.
+    pub random_length: usize,
+    pub seed: u64,
+}
+
+impl Default for Inputs {
+    fn default() -> Self {
+        Inputs {
+            exhaustive: 1,
+            random: 1000,
+            random_length: 64,
+            seed: 1,
+        }
+    }
+}
+
+/// What a program did with one input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub output: Vec<u8>,
+    pub termination: Termination,
+}
+
+impl Run {
+    /// Runs `program` on all of `input`, then end of input.
+    pub fn new(program: &Program, config: Config, limits: Limits, input: &[u8]) -> Self {
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.limits = limits;
+        interpreter.load(program.clone());
+        interpreter.push_input(input);
+        interpreter.close_input();
+        let termination = interpreter.run();
+        Run {
+            output: interpreter.result_text.chars().map(|c| c as u8).collect(),
+            termination,
+        }
+    }
+
+    /// Whether the two runs ended the same way with the same output. Two
+    /// runs stopped by the same limit only need one output to start the
+    /// other, as a faster program gets further before the limit.
+    pub fn agrees_with(&self, other: &Run) -> bool {
+        if discriminant(&self.termination) != discriminant(&other.termination) {
+            return false;
+        }
+        if self.termination.is_limit() {
+            let shorter = self.output.len().min(other.output.len());
+            self.output[..shorter] == other.output[..shorter]
+        } else {
+            self.output == other.output
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub input: Vec<u8>,
+    pub a: Run,
+    pub b: Run,
+}
+
+impl Difference {
+    /// The first byte where the outputs differ, or where the shorter one
+    /// ends, if they aren't the same.
+    pub fn output_differs_at(&self) -> Option<usize> {
+        let (a, b) = (&self.a.output, &self.b.output);
+        a.iter()
+            .zip(b)
+            .position(|(x, y)| x != y)
+            .or_else(|| (a.len() != b.len()).then_some(a.len().min(b.len())))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    /// How many inputs were tried.
+    pub tried: usize,
+    /// The first input the programs disagreed on, if any: the shortest one,
+    /// if it was among the exhaustive ones.
+    pub difference: Option<Difference>,
+}
+
+/// Whether `program` has a `,` anywhere, so its input can matter.
+pub fn reads_input(program: &Program) -> bool {
+    program.ops.contains(&OPS::Input)
+}
+
+/// Every input of exactly `length` bytes, in order.
+fn every_input(length: usize) -> impl Iterator<Item = Vec<u8>> {
+    let mut next = Some(vec![0u8; length]);
+    std::iter::from_fn(move || {
+        let current = next.take()?;
+        let mut following = current.clone();
+        // Count up like an odometer; wrapping every byte means we're done.
+        for byte in following.iter_mut().rev() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                next = Some(following);
+                break;
+            }
+        }
+        Some(current)
+    })
+}
+
+/// Runs `a` and `b` on the same inputs and machine and stops at the first
+/// input they disagree on. Programs that never read only need the one run.
+pub fn compare(
+    a: &Program,
+    b: &Program,
+    config: Config,
+    limits: Limits,
+    inputs: Inputs,
+) -> Comparison {
+    let mut tried = 0;
+    let mut check = |input: Vec<u8>| {
+        tried += 1;
+        let (run_a, run_b) = (
+            Run::new(a, config, limits, &input),
+            Run::new(b, config, limits, &input),
+        );
+        (!run_a.agrees_with(&run_b)).then_some(Difference {
+            input,
+            a: run_a,
+            b: run_b,
+        })
+    };
+    if !reads_input(a) && !reads_input(b) {
+        let difference = check(Vec::new());
+        return Comparison { tried, difference };
+    }
+    let mut rng = Rng::new(inputs.seed);
+    let random = (0..inputs.random).map(|_| {
+        let length = inputs.exhaustive
+            + 1
+            + rng.below(
+                inputs
+                    .random_length
+                    .saturating_sub(inputs.exhaustive)
+                    .max(1),
+            );
+        (0..length).map(|_| rng.byte()).collect()
+    });
+    let difference = (0..=inputs.exhaustive)
+        .flat_map(every_input)
+        .chain(random)
+        .find_map(&mut check);
+    Comparison { tried, difference }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, every_input, reads_input, Comparison, Inputs};
+    use crate::config::Config;
+    use crate::interpreter::{Limits, Termination};
+    use crate::ops::{Dialect, Program};
+
+    fn program(source: &str) -> Program {
+        Program::parse(source, Dialect::Brainfuck).unwrap()
+    }
+
+    fn compared(a: &str, b: &str) -> Comparison {
+        let limits = Limits {
+            max_steps: Some(1000),
+            ..Limits::default()
+        };
+        let inputs = Inputs {
+            random: 10,
+            ..Inputs::default()
+        };
+        compare(&program(a), &program(b), Config::default(), limits, inputs)
+    }
+
+    #[test]
+    fn counts_through_every_input() {
+        assert_eq!(every_input(0).collect::<Vec<_>>(), vec![Vec::<u8>::new()]);
+        let two: Vec<_> = every_input(2).collect();
+        assert_eq!(two.len(), 65536);
+        assert_eq!(two[..2], [vec![0, 0], vec![0, 1]]);
+        assert_eq!(two[256], vec![1, 0]);
+        assert_eq!(two.last(), Some(&vec![255, 255]));
+    }
+
+    #[test]
+    fn equivalent_programs_agree_on_every_input() {
+        let comparison = compared(",[.,]", ",[.,]>+<");
+        assert_eq!(comparison.difference, None);
+        // The empty input, every byte and the random ones.
+        assert_eq!(comparison.tried, 1 + 256 + 10);
+    }
+
+    #[test]
+    fn finds_the_first_input_the_output_differs_on() {
+        let comparison = compared(",.", ".");
+        let difference = comparison.difference.unwrap();
+        // The end of input leaves the cell at 0, so "" and "\0" agree.
+        assert_eq!(difference.input, [1]);
+        assert_eq!(comparison.tried, 3);
+        assert_eq!(difference.a.output, [1]);
+        assert_eq!(difference.b.output, [0]);
+        assert_eq!(difference.output_differs_at(), Some(0));
+    }
+
+    #[test]
+    fn finds_programs_that_stop_differently() {
+        let comparison = compared("+", "+[]");
+        // Neither reads, so the one run is enough.
+        assert_eq!(comparison.tried, 1);
+        let difference = comparison.difference.unwrap();
+        assert_eq!(difference.a.termination, Termination::Finished);
+        assert!(difference.b.termination.is_limit());
+        assert_eq!(difference.output_differs_at(), None);
+    }
+
+    #[test]
+    fn runs_cut_short_agree_as_far_as_both_got() {
+        let comparison = compared("+[.]", "+[..]");
+        assert_eq!(comparison.difference, None);
+    }
+
+    #[test]
+    fn knows_which_programs_read() {
+        assert!(reads_input(&program("+[,.]")));
+        assert!(!reads_input(&program("+[-.]")));
+        // Reading without printing makes no difference to the output.
+        assert_eq!(compared(",,", "").difference, None);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::codegen::describe;
+use crate::config::{CellWidth, Config, ConfigError, EofPolicy};
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ir::optimise;
+use crate::ops::{Dialect, Program};
+use crate::reduce::reduce;
+use crate::rng::Rng;
+use crate::vm::Vm;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Loops the generator drops in whole, so the optimiser's idioms come up
+/// more often than chance would have them.
+const IDIOMS: [&str; 8] = [
+    "[-]",
+    "[+]",
+    "[->+<]",
+    "[-<+>]",
+    "[->++>+++<<]",
+    "[>]",
+    "[<]",
+    "[>>]",
+];
+
+/// The most a case may print before the interpreter stops it, which also
+/// keeps native runs from filling their pipe.
+const MAX_OUTPUT: usize = 4096;
+
+/// How long an engine other than the interpreter gets before it counts as
+/// hanging.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A program, the machine it runs on and its input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    /// Brainfuck source.
+    pub source: String,
+    pub config: Config,
+    pub input: Vec<u8>,
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} on input \"{}\" ({})",
+            self.source,
+            self.input.escape_ascii(),
+            describe(self.config)
+        )
+    }
+}
+
+/// A well-bracketed program of about `length` ops, made of single ops,
+/// loops nested up to four deep and the odd idiom loop.
+pub fn program(rng: &mut Rng, length: usize) -> String {
+    let mut source = String::new();
+    let mut open = 0;
+    while source.len() < length {
+        match rng.below(100) {
+            0..=19 => source.push('+'),
+            20..=34 => source.push('-'),
+            35..=49 => source.push('>'),
+            50..=61 => source.push('<'),
+            62..=69 => source.push('.'),
+            70..=74 => source.push(','),
+            75..=79 => source.push_str(IDIOMS[rng.below(IDIOMS.len())]),
+            80..=89 if open < 4 => {
+                // A loop that counts down has a chance of ending.
+                source.push_str(if rng.below(2) == 0 { "[-" } else { "[" });
+                open += 1;
+            }
+            _ if open > 0 => {
+                source.push(']');
+                open -= 1;
+            }
+            _ => {}
+        }
+    }
+    source.push_str(&"]".repeat(open));
+    source
+}
+
+/// A machine with a random cell width and EOF policy, and sometimes a tape
+/// small enough for programs to wrap around it.
+pub fn config(rng: &mut Rng) -> Config {
+    Config {
+        cell_width: [CellWidth::Bits8, CellWidth::Bits16, CellWidth::Bits32][rng.below(3)],
+        eof: [EofPolicy::Unchanged, EofPolicy::Zero, EofPolicy::Max][rng.below(3)],
+        tape_size: [Config::default().tape_size, 1, 7, 64][rng.below(4)],
+    }
+}
+
+pub fn case(rng: &mut Rng, length: usize) -> Case {
+    let source = program(rng, length);
+    let config = config(rng);
+    let input = (0..rng.below(8)).map(|_| rng.byte()).collect();
+    Case {
+        source,
+        config,
+        input,
+    }
+}
+
+/// The ways of running a program that get checked against the interpreter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    Vm,
+    Jit,
+    /// The `env.read`/`env.write` module in the embedded runtime.
+    Wasm,
+    /// The WASI module in the embedded runtime.
+    Wasi,
+    /// A `bf build` executable, run as a process.
+    Native,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Engine::Vm => "vm",
+            Engine::Jit => "jit",
+            Engine::Wasm => "wasm",
+            Engine::Wasi => "wasi",
+            Engine::Native => "native",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Engine {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vm" => Ok(Engine::Vm),
+            "jit" => Ok(Engine::Jit),
+            "wasm" => Ok(Engine::Wasm),
+            "wasi" => Ok(Engine::Wasi),
+            "native" => Ok(Engine::Native),
+            _ => Err(ConfigError::BadValue {
+                key: "engine".to_owned(),
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// The engines this build has.
+pub fn engines() -> Vec<Engine> {
+    let mut engines = vec![Engine::Vm];
+    if cfg!(all(feature = "jit", target_arch = "x86_64", unix)) {
+        engines.push(Engine::Jit);
+    }
+    if cfg!(feature = "wasm-runtime") {
+        engines.extend([Engine::Wasm, Engine::Wasi]);
+    }
+    if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        engines.push(Engine::Native);
+    }
+    engines
+}
+
+/// What the interpreter prints for `case`, or `None` if it doesn't finish
+/// within `max_steps` and `MAX_OUTPUT`.
+pub fn reference(program: &Program, case: &Case, max_steps: u64) -> Option<Vec<u8>> {
+    let mut interpreter = Interpreter::with_config(case.config);
+    interpreter.limits = Limits {
+        max_steps: Some(max_steps),
+        max_output_bytes: Some(MAX_OUTPUT),
+        ..Limits::default()
+    };
+    interpreter.load(program.clone());
+    interpreter.push_input(&case.input);
+    interpreter.close_input();
+    (interpreter.run() == Termination::Finished)
+        .then(|| interpreter.result_text.chars().map(|c| c as u8).collect())
+}
+
+/// What `engine` prints for `case`, or why it couldn't run it. Only for
+/// programs the interpreter finishes: apart from the VM's timeout, engines
+/// aren't stopped early.
+pub fn run(engine: Engine, program: &Program, case: &Case) -> Result<Vec<u8>, String> {
+    match engine {
+        Engine::Vm => {
+            let mut vm = Vm::new(program, case.config);
+            vm.unchecked = true;
+            vm.limits.timeout = Some(TIMEOUT);
+            vm.push_input(&case.input);
+            vm.close_input();
+            match vm.run() {
+                Termination::Finished => Ok(vm.output),
+                stopped => Err(stopped.to_string()),
+            }
+        }
+        Engine::Jit => run_jit(program, case),
+        Engine::Wasm => run_wasm(program, case, crate::codegen::wasm::Flavour::Imports),
+        Engine::Wasi => run_wasm(program, case, crate::codegen::wasm::Flavour::Wasi),
+        Engine::Native => run_native(program, case),
+    }
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+fn run_jit(program: &Program, case: &Case) -> Result<Vec<u8>, String> {
+    use crate::bench::BufferIo;
+    use crate::jit::Jit;
+    let bytecode = crate::vm::compile(&optimise(program), case.config.tape_size);
+    let jit = Jit::compile(&bytecode, case.config).ok_or("the JIT can't compile it")?;
+    let mut io = BufferIo {
+        input: case.input.iter(),
+        output: Vec::new(),
+    };
+    match jit.run(&mut vec![0; case.config.tape_size], 0, &mut io) {
+        (Termination::Finished, _) => Ok(io.output),
+        (stopped, _) => Err(stopped.to_string()),
+    }
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64", unix)))]
+fn run_jit(_: &Program, _: &Case) -> Result<Vec<u8>, String> {
+    Err("this build has no JIT".to_owned())
+}
+
+#[cfg(feature = "wasm-runtime")]
+fn run_wasm(
+    program: &Program,
+    case: &Case,
+    flavour: crate::codegen::wasm::Flavour,
+) -> Result<Vec<u8>, String> {
+    let module = crate::codegen::wasm::generate(&optimise(program), case.config, flavour)
+        .ok_or("the tape is too big for WebAssembly")?;
+    crate::wasm_runtime::run(&module, flavour, &case.input).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "wasm-runtime"))]
+fn run_wasm(_: &Program, _: &Case, _: crate::codegen::wasm::Flavour) -> Result<Vec<u8>, String> {
+    Err("this build has no WebAssembly runtime".to_owned())
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn run_native(program: &Program, case: &Case) -> Result<Vec<u8>, String> {
+    use std::io::{Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let bytecode = crate::vm::compile(&optimise(program), case.config.tape_size);
+    let binary = crate::codegen::elf::executable(&bytecode, case.config)
+        .ok_or("the tape is too big for an executable")?;
+    let path = std::env::temp_dir().join(format!(
+        "bf-fuzz-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| {
+        std::fs::write(&path, binary)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // The program may stop before reading it all, which is fine.
+        let _ = child.stdin.take().expect("piped").write_all(&case.input);
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() > TIMEOUT {
+                child.kill()?;
+                return Ok(Err("timed out".to_owned()));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let mut output = Vec::new();
+        child
+            .stdout
+            .take()
+            .expect("piped")
+            .read_to_end(&mut output)?;
+        Ok(if status.success() {
+            Ok(output)
+        } else {
+            Err(format!("exited with {}", status))
+        })
+    })()
+    .unwrap_or_else(|e: std::io::Error| Err(e.to_string()));
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+fn run_native(_: &Program, _: &Case) -> Result<Vec<u8>, String> {
+    Err("native executables only run on x86-64 Linux".to_owned())
+}
+
+/// An engine that didn't do what the interpreter did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Disagreement {
+    pub case: Case,
+    pub engine: Engine,
+    pub expected: Vec<u8>,
+    pub actual: Result<Vec<u8>, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Agreed,
+    /// The interpreter didn't finish it, so there is nothing to compare.
+    Skipped,
+    Disagreed(Box<Disagreement>),
+}
+
+/// Runs `case` on the interpreter and then on each of `engines`, stopping at
+/// the first one that disagrees.
+pub fn check(case: &Case, engines: &[Engine], max_steps: u64) -> Verdict {
+    let program = match Program::parse(&case.source, Dialect::Brainfuck) {
+        Ok(program) => program,
+        Err(_) => return Verdict::Skipped,
+    };
+    let Some(expected) = reference(&program, case, max_steps) else {
+        return Verdict::Skipped;
+    };
+    for &engine in engines {
+        let actual = run(engine, &program, case);
+        if actual.as_ref() != Ok(&expected) {
+            return Verdict::Disagreed(Box::new(Disagreement {
+                case: case.clone(),
+                engine,
+                expected,
+                actual,
+            }));
+        }
+    }
+    Verdict::Agreed
+}
+
+/// Shrinks the program and the input of a disagreement for as long as the
+/// same engine keeps disagreeing.
+pub fn minimise(disagreement: Disagreement, max_steps: u64) -> Disagreement {
+    let mut current = disagreement;
+    loop {
+        let before = current.clone();
+        let engine = current.engine;
+        if let Ok(program) = Program::parse(&current.case.source, Dialect::Brainfuck) {
+            let case = current.case.clone();
+            reduce(&program, &case.source, Dialect::Brainfuck, |source| {
+                let case = Case {
+                    source: source.to_owned(),
+                    ..case.clone()
+                };
+                match check(&case, &[engine], max_steps) {
+                    Verdict::Disagreed(disagreement) => {
+                        current = *disagreement;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+        }
+        let mut i = 0;
+        while i < current.case.input.len() {
+            let mut case = current.case.clone();
+            case.input.remove(i);
+            match check(&case, &[engine], max_steps) {
+                Verdict::Disagreed(disagreement) => current = *disagreement,
+                _ => i += 1,
+            }
+        }
+        if current == before {
+            return current;
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::{parse_headers, Headers};
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ops::{Dialect, Program};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// A program found by `discover`, with the `.in` and `.out` files next to it
+/// if there are any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GoldenTest {
+    pub name: String,
+    pub program: PathBuf,
+    pub input: Option<PathBuf>,
+    pub expected: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Failure {
+    Io(String),
+    Setup(String),
+    Stopped(Termination),
+    Output(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Io(e) => write!(f, "{}", e),
+            Failure::Setup(e) => write!(f, "{}", e),
+            Failure::Stopped(termination) => write!(f, "{}", termination),
+            Failure::Output(diff) => write!(f, "output differs\n{}", diff.trim_end()),
+        }
+    }
+}
+
+fn dialect_of(path: &Path) -> Option<Dialect> {
+    match path.extension()?.to_str()? {
+        "bf" => Some(Dialect::Brainfuck),
+        "bf2" => Some(Dialect::BrainFnord2),
+        _ => None,
+    }
+}
+
+/// Finds every `.bf` and `.bf2` program under `dir`, sorted by name.
+pub fn discover(dir: &Path) -> io::Result<Vec<GoldenTest>> {
+    let mut tests = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if dialect_of(&path).is_none() {
+                continue;
+            }
+            let existing = |extension: &str| {
+                let sibling = path.with_extension(extension);
+                sibling.is_file().then_some(sibling)
+            };
+            tests.push(GoldenTest {
+                name: path
+                    .strip_prefix(dir)
+                    .unwrap_or(&path)
+                    .display()
+                    .to_string(),
+                input: existing("in"),
+                expected: existing("out"),
+                program: path,
+            });
+        }
+    }
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tests)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Failure> {
+    fs::read(path).map_err(|e| Failure::Io(format!("{}: {}", path.display(), e)))
+}
+
+impl GoldenTest {
+    /// Reads and parses the program and reads its input.
+    pub fn load(&self) -> Result<(Program, Headers, Vec<u8>), Failure> {
+        let source = String::from_utf8_lossy(&read(&self.program)?).into_owned();
+        let headers = parse_headers(&source).map_err(|e| Failure::Setup(e.to_string()))?;
+        let dialect = dialect_of(&self.program).unwrap_or(Dialect::Brainfuck);
+        let program =
+            Program::parse(&headers.source, dialect).map_err(|e| Failure::Setup(e.to_string()))?;
+        let input = match &self.input {
+            Some(input) => read(input)?,
+            None => Vec::new(),
+        };
+        Ok((program, headers, input))
+    }
+
+    /// Compares `actual` with the `.out` file, if there is one.
+    pub fn check(&self, actual: &[u8]) -> Result<(), Failure> {
+        if let Some(expected) = &self.expected {
+            let expected = read(expected)?;
+            if expected != actual {
+                return Err(Failure::Output(diff(&expected, actual)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the program on its input, closing the input at the end, and checks
+    /// the output. Limits from the program's headers win over `limits`.
+    pub fn run(&self, limits: Limits) -> Result<(), Failure> {
+        let (program, headers, input) = self.load()?;
+        let mut interpreter = Interpreter::with_config(headers.config);
+        interpreter.limits = headers.limits.or(limits);
+        interpreter.load(program);
+        interpreter.push_input(&input);
+        interpreter.close_input();
+        let termination = interpreter.run();
+        if termination != Termination::Finished {
+            return Err(Failure::Stopped(termination));
+        }
+        let actual: Vec<u8> = interpreter.result_text.chars().map(|c| c as u8).collect();
+        self.check(&actual)
+    }
+}
+
+fn show(bytes: &[u8]) -> String {
+    let text: String = bytes.iter().map(|&b| b as char).collect();
+    format!("{:?}", text)
+}
+
+/// A short line-by-line description of where `actual` stops matching
+/// `expected`.
+pub fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected_lines: Vec<&[u8]> = expected.split_inclusive(|&b| b == b'\n').collect();
+    let actual_lines: Vec<&[u8]> = actual.split_inclusive(|&b| b == b'\n').collect();
+    let first = expected_lines
+        .iter()
+        .zip(&actual_lines)
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected_lines.len().min(actual_lines.len()));
+    let mut report = format!(
+        "  expected {} bytes, got {} bytes; first difference on line {}\n",
+        expected.len(),
+        actual.len(),
+        first + 1
+    );
+    for line in first..first + DIFF_CONTEXT_LINES {
+        let (e, a) = (expected_lines.get(line), actual_lines.get(line));
+        if e.is_none() && a.is_none() {
+            break;
+        }
+        if e == a {
+            continue;
+        }
+        if let Some(e) = e {
+            report += &format!("  - {}\n", show(e));
+        }
+        if let Some(a) = a {
+            report += &format!("  + {}\n", show(a));
+        }
+    }
+    report
+}
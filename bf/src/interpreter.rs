@@ -0,0 +1,405 @@
+use crate::config::{Config, EofPolicy};
+use crate::ops::{Program, OPS};
+use crate::trace::{TraceEvent, Tracer};
+use crate::transcript::{Transcript, TranscriptEntry};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const CSIZE: usize = 30000;
+
+const TIMEOUT_CHECK_INTERVAL: u64 = 4096;
+
+/// Resource limits enforced by `Interpreter::run`, counted from the last `load`
+/// or `resume`. `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+    pub max_cells_touched: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// Each limit that isn't set here is taken from `fallback`.
+    pub fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            max_steps: self.max_steps.or(fallback.max_steps),
+            max_output_bytes: self.max_output_bytes.or(fallback.max_output_bytes),
+            max_cells_touched: self.max_cells_touched.or(fallback.max_cells_touched),
+            timeout: self.timeout.or(fallback.timeout),
+        }
+    }
+}
+
+/// Why `Interpreter::run` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    Finished,
+    AwaitingInput,
+    StepLimit {
+        steps: u64,
+    },
+    OutputLimit {
+        bytes: usize,
+    },
+    TapeLimit {
+        cells: usize,
+    },
+    Timeout {
+        elapsed: Duration,
+    },
+    /// It got to one of the `breakpoints`.
+    Breakpoint,
+}
+
+impl Termination {
+    pub fn is_limit(&self) -> bool {
+        !matches!(self, Termination::Finished | Termination::AwaitingInput)
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::Finished => write!(f, "finished"),
+            Termination::AwaitingInput => write!(f, "waiting for input"),
+            Termination::StepLimit { steps } => {
+                write!(f, "stopped: step limit of {} reached", steps)
+            }
+            Termination::OutputLimit { bytes } => {
+                write!(f, "stopped: output limit of {} bytes reached", bytes)
+            }
+            Termination::TapeLimit { cells } => {
+                write!(f, "stopped: tape limit of {} cells reached", cells)
+            }
+            Termination::Timeout { elapsed } => {
+                write!(f, "stopped: timed out after {:.3}s", elapsed.as_secs_f64())
+            }
+            Termination::Breakpoint => write!(f, "stopped at a breakpoint"),
+        }
+    }
+}
+
+pub struct Interpreter {
+    pub cells: Vec<u32>,
+    pub config: Config,
+    pub current_instruction: usize,
+    pub index: usize,
+    pub input: Vec<u8>,
+    pub input_closed: bool,
+    pub input_position: usize,
+    pub limits: Limits,
+    pub loop_stack: Vec<usize>,
+    pub output_bytes: usize,
+    pub program: Program,
+    pub result_text: String,
+    pub steps: u64,
+    pub elapsed: Duration,
+    pub tracer: Option<Tracer>,
+    pub recording: Option<Transcript>,
+    /// Instructions to stop before.
+    pub breakpoints: Vec<usize>,
+    /// The breakpoint the last run stopped at, which the next one goes past
+    /// if it starts there.
+    pub stopped_at: Option<usize>,
+    steps_at_resume: u64,
+    touched: Vec<bool>,
+    touched_count: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let mut touched = vec![false; config.tape_size];
+        touched[0] = true;
+        Interpreter {
+            cells: vec![0; config.tape_size],
+            config,
+            current_instruction: 0,
+            index: 0,
+            input: Vec::new(),
+            input_closed: false,
+            input_position: 0,
+            limits: Limits::default(),
+            loop_stack: Vec::new(),
+            output_bytes: 0,
+            program: Program::default(),
+            result_text: String::new(),
+            steps: 0,
+            elapsed: Duration::ZERO,
+            tracer: None,
+            recording: None,
+            breakpoints: Vec::new(),
+            stopped_at: None,
+            steps_at_resume: 0,
+            touched,
+            touched_count: 1,
+        }
+    }
+
+    /// Starts `program` from its first instruction, keeping the tape as it is.
+    pub fn load(&mut self, program: Program) {
+        self.program = program;
+        self.current_instruction = 0;
+        self.loop_stack = Vec::new();
+        self.recording = None;
+        self.steps = 0;
+        self.stopped_at = None;
+        self.resume();
+    }
+
+    /// Gives the program a fresh set of limits from where it currently is, so a
+    /// run stopped by a limit can carry on.
+    pub fn resume(&mut self) {
+        self.output_bytes = 0;
+        self.elapsed = Duration::ZERO;
+        self.steps_at_resume = self.steps;
+        self.touched = vec![false; self.cells.len()];
+        self.touched[self.index] = true;
+        self.touched_count = 1;
+    }
+
+    /// Clears the tape, output, input and counters, keeping the configuration
+    /// and limits.
+    pub fn reset(&mut self) {
+        let limits = self.limits;
+        *self = Interpreter::with_config(self.config);
+        self.limits = limits;
+    }
+
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    /// Marks the input as exhausted, so `,` leaves the cell unchanged instead of
+    /// waiting for more.
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current_instruction >= self.program.len()
+    }
+
+    pub fn run(&mut self) -> Termination {
+        let started = Instant::now();
+        let result = self.run_until_stopped(started);
+        self.elapsed += started.elapsed();
+        result
+    }
+
+    fn run_until_stopped(&mut self, started: Instant) -> Termination {
+        loop {
+            if self.is_finished() {
+                return Termination::Finished;
+            }
+            if let Some(max_steps) = self.limits.max_steps {
+                if self.steps - self.steps_at_resume >= max_steps {
+                    return Termination::StepLimit { steps: max_steps };
+                }
+            }
+            if let Some(timeout) = self.limits.timeout {
+                if self.steps & (TIMEOUT_CHECK_INTERVAL - 1) == 0 {
+                    let elapsed = self.elapsed + started.elapsed();
+                    if elapsed >= timeout {
+                        return Termination::Timeout { elapsed };
+                    }
+                }
+            }
+            let instruction = self.current_instruction;
+            if self.stopped_at.take() != Some(instruction)
+                && self.breakpoints.contains(&instruction)
+            {
+                self.stopped_at = Some(instruction);
+                return Termination::Breakpoint;
+            }
+            if let Some(stopped) = self.step() {
+                return stopped;
+            }
+        }
+    }
+
+    fn step(&mut self) -> Option<Termination> {
+        let instruction = self.current_instruction;
+        let op = self.program.ops[instruction];
+        let traced = match &self.tracer {
+            Some(tracer) => tracer.wants(self.steps, op),
+            None => false,
+        };
+        if !traced {
+            return self.execute();
+        }
+        let step = self.steps;
+        let pointer = self.index;
+        let cell_before = self.cells[pointer];
+        let input_position = self.input_position;
+        let output_bytes = self.output_bytes;
+        if let Some(stopped) = self.execute() {
+            return Some(stopped);
+        }
+        let cell_after = self.cells[pointer];
+        let event = TraceEvent {
+            step,
+            instruction,
+            span: self.program.spans[instruction].clone(),
+            op,
+            pointer,
+            cell_before,
+            cell_after,
+            input: (self.input_position > input_position).then_some(cell_after as u8),
+            output: (self.output_bytes > output_bytes).then_some(cell_after as u8),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&event);
+        }
+        None
+    }
+
+    fn execute(&mut self) -> Option<Termination> {
+        match self.program.ops[self.current_instruction] {
+            OPS::ShiftRight => {
+                let mut index = self.index + 1;
+                if index == self.cells.len() {
+                    index = 0;
+                }
+                if let Some(stopped) = self.move_to(index) {
+                    return Some(stopped);
+                }
+            }
+            OPS::ShiftLeft => {
+                let index = if self.index == 0 {
+                    self.cells.len() - 1
+                } else {
+                    self.index - 1
+                };
+                if let Some(stopped) = self.move_to(index) {
+                    return Some(stopped);
+                }
+            }
+            OPS::Increment => {
+                self.cells[self.index] =
+                    self.cells[self.index].wrapping_add(1) & self.config.cell_width.mask();
+            }
+            OPS::Decrement => {
+                self.cells[self.index] =
+                    self.cells[self.index].wrapping_sub(1) & self.config.cell_width.mask();
+            }
+            OPS::Print => {
+                if let Some(max_output_bytes) = self.limits.max_output_bytes {
+                    if self.output_bytes >= max_output_bytes {
+                        return Some(Termination::OutputLimit {
+                            bytes: max_output_bytes,
+                        });
+                    }
+                }
+                self.result_text.push(self.cells[self.index] as u8 as char);
+                self.output_bytes += 1;
+            }
+            OPS::Input => {
+                if self.input_position < self.input.len() {
+                    let byte = self.input[self.input_position];
+                    self.cells[self.index] = byte as u32;
+                    self.input_position += 1;
+                    if let Some(recording) = &mut self.recording {
+                        recording.inputs.push(TranscriptEntry {
+                            step: self.steps,
+                            byte,
+                        });
+                    }
+                } else if !self.input_closed {
+                    return Some(Termination::AwaitingInput);
+                } else {
+                    match self.config.eof {
+                        EofPolicy::Unchanged => {}
+                        EofPolicy::Zero => self.cells[self.index] = 0,
+                        EofPolicy::Max => self.cells[self.index] = self.config.cell_width.mask(),
+                    }
+                }
+            }
+            OPS::LoopStart => {
+                if self.cells[self.index] == 0 {
+                    self.current_instruction = self.program.jumps[self.current_instruction];
+                } else {
+                    self.loop_stack.push(self.current_instruction);
+                }
+            }
+            OPS::LoopEnd => {
+                if self.cells[self.index] != 0 {
+                    self.current_instruction = *self.loop_stack.last().unwrap();
+                } else {
+                    self.loop_stack.pop();
+                }
+            }
+        }
+        self.current_instruction += 1;
+        self.steps += 1;
+        None
+    }
+
+    fn move_to(&mut self, index: usize) -> Option<Termination> {
+        if !self.touched[index] {
+            if let Some(max_cells_touched) = self.limits.max_cells_touched {
+                if self.touched_count >= max_cells_touched {
+                    return Some(Termination::TapeLimit {
+                        cells: max_cells_touched,
+                    });
+                }
+            }
+            self.touched[index] = true;
+            self.touched_count += 1;
+        }
+        self.index = index;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interpreter, Termination};
+    use crate::ops::{Dialect, Program};
+
+    fn stopping_at(source: &str, breakpoints: Vec<usize>) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.load(Program::parse(source, Dialect::Brainfuck).unwrap());
+        interpreter.breakpoints = breakpoints;
+        interpreter
+    }
+
+    #[test]
+    fn stops_before_the_first_command() {
+        let mut interpreter = stopping_at("++[-]", vec![0]);
+        assert_eq!(interpreter.run(), Termination::Breakpoint);
+        assert_eq!((interpreter.current_instruction, interpreter.steps), (0, 0));
+        assert_eq!(interpreter.run(), Termination::Finished);
+    }
+
+    #[test]
+    fn stops_at_a_breakpoint_each_time_round() {
+        let mut interpreter = stopping_at("++[-]", vec![3]);
+        for cell in [2, 1] {
+            assert_eq!(interpreter.run(), Termination::Breakpoint);
+            assert_eq!(interpreter.cells[0], cell);
+        }
+        assert_eq!(interpreter.run(), Termination::Finished);
+    }
+
+    #[test]
+    fn goes_past_the_breakpoint_from_a_snapshot() {
+        let mut interpreter = stopping_at("++[-]", vec![3]);
+        assert_eq!(interpreter.run(), Termination::Breakpoint);
+        let snapshot = interpreter.snapshot();
+        let mut restored = stopping_at("++[-]", vec![3]);
+        restored.restore(snapshot).unwrap();
+        assert_eq!(restored.run(), Termination::Breakpoint);
+        assert_eq!(restored.cells[0], 1);
+    }
+}
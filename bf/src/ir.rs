@@ -0,0 +1,260 @@
+use crate::ops::{Program, OPS};
+use std::ops::Range;
+
+/// One operation of the optimised program. Offsets are relative to the
+/// pointer, cell arithmetic wraps, and every node keeps the source span it was
+/// built from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// `cell[offset] += delta`
+    Add {
+        offset: isize,
+        delta: u32,
+    },
+    /// `pointer += distance`
+    Move(isize),
+    /// `cell[offset] = 0`
+    Clear {
+        offset: isize,
+    },
+    /// `cell[offset] += cell[src] * factor`
+    MulAdd {
+        src: isize,
+        offset: isize,
+        factor: u32,
+    },
+    /// `while cell[0] != 0 { pointer += step }`
+    Scan(isize),
+    Print {
+        offset: isize,
+    },
+    Input {
+        offset: isize,
+    },
+    Loop(Vec<Node>),
+}
+
+fn join(a: &Range<usize>, b: &Range<usize>) -> Range<usize> {
+    a.start.min(b.start)..a.end.max(b.end)
+}
+
+/// Builds the optimised IR: runs of `+-` and `<>` are merged, pointer moves
+/// are folded into the offsets of the nodes around them, and clear, multiply
+/// and scan loops become single nodes.
+pub fn optimise(program: &Program) -> Vec<Node> {
+    let mut position = 0;
+    let nodes = lower(program, &mut position);
+    optimise_block(nodes)
+}
+
+fn lower(program: &Program, position: &mut usize) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::new();
+    while *position < program.len() {
+        let span = program.spans[*position].clone();
+        let op = program.ops[*position];
+        *position += 1;
+        let kind = match op {
+            OPS::Increment | OPS::Decrement => {
+                let delta = if op == OPS::Increment { 1 } else { u32::MAX };
+                if let Some(Node {
+                    kind:
+                        NodeKind::Add {
+                            offset: 0,
+                            delta: previous,
+                        },
+                    span: previous_span,
+                }) = nodes.last_mut()
+                {
+                    *previous = previous.wrapping_add(delta);
+                    *previous_span = join(previous_span, &span);
+                    continue;
+                }
+                NodeKind::Add { offset: 0, delta }
+            }
+            OPS::ShiftLeft | OPS::ShiftRight => {
+                let distance = if op == OPS::ShiftRight { 1 } else { -1 };
+                if let Some(Node {
+                    kind: NodeKind::Move(previous),
+                    span: previous_span,
+                }) = nodes.last_mut()
+                {
+                    *previous += distance;
+                    *previous_span = join(previous_span, &span);
+                    continue;
+                }
+                NodeKind::Move(distance)
+            }
+            OPS::Print => NodeKind::Print { offset: 0 },
+            OPS::Input => NodeKind::Input { offset: 0 },
+            OPS::LoopStart => {
+                let body = lower(program, position);
+                let end = program.spans[*position - 1].clone();
+                nodes.push(Node {
+                    kind: NodeKind::Loop(body),
+                    span: join(&span, &end),
+                });
+                continue;
+            }
+            OPS::LoopEnd => return nodes,
+        };
+        nodes.push(Node { kind, span });
+    }
+    nodes
+}
+
+fn optimise_block(nodes: Vec<Node>) -> Vec<Node> {
+    let mut optimised = Vec::new();
+    for node in nodes {
+        match node.kind {
+            NodeKind::Loop(body) => {
+                let body = optimise_block(body);
+                match loop_idiom(&body) {
+                    Some(kinds) => optimised.extend(kinds.into_iter().map(|kind| Node {
+                        kind,
+                        span: node.span.clone(),
+                    })),
+                    None => optimised.push(Node {
+                        kind: NodeKind::Loop(body),
+                        span: node.span,
+                    }),
+                }
+            }
+            kind => optimised.push(Node {
+                kind,
+                span: node.span,
+            }),
+        }
+    }
+    fold_offsets(optimised)
+}
+
+/// Moves pointer movement to the end of the block (or just before a loop or
+/// scan, which need the real pointer), shifting the offsets of everything in
+/// between, then merges neighbouring adds to the same cell.
+fn fold_offsets(nodes: Vec<Node>) -> Vec<Node> {
+    let mut folded: Vec<Node> = Vec::new();
+    let mut offset = 0;
+    let mut move_span: Option<Range<usize>> = None;
+    let flush =
+        |folded: &mut Vec<Node>, offset: &mut isize, move_span: &mut Option<Range<usize>>| {
+            if let Some(span) = move_span.take() {
+                if *offset != 0 {
+                    folded.push(Node {
+                        kind: NodeKind::Move(*offset),
+                        span,
+                    });
+                }
+            }
+            *offset = 0;
+        };
+    for node in nodes {
+        let kind = match node.kind {
+            NodeKind::Move(distance) => {
+                offset += distance;
+                move_span = Some(match &move_span {
+                    Some(previous) => join(previous, &node.span),
+                    None => node.span,
+                });
+                continue;
+            }
+            NodeKind::Add { offset: at, delta } => {
+                let at = at + offset;
+                if let Some(Node {
+                    kind:
+                        NodeKind::Add {
+                            offset: previous_at,
+                            delta: previous,
+                        },
+                    span,
+                }) = folded.last_mut()
+                {
+                    if *previous_at == at {
+                        *previous = previous.wrapping_add(delta);
+                        *span = join(span, &node.span);
+                        if *previous == 0 {
+                            folded.pop();
+                        }
+                        continue;
+                    }
+                }
+                if delta == 0 {
+                    continue;
+                }
+                NodeKind::Add { offset: at, delta }
+            }
+            NodeKind::Clear { offset: at } => NodeKind::Clear {
+                offset: at + offset,
+            },
+            NodeKind::MulAdd {
+                src,
+                offset: at,
+                factor,
+            } => NodeKind::MulAdd {
+                src: src + offset,
+                offset: at + offset,
+                factor,
+            },
+            NodeKind::Print { offset: at } => NodeKind::Print {
+                offset: at + offset,
+            },
+            NodeKind::Input { offset: at } => NodeKind::Input {
+                offset: at + offset,
+            },
+            kind @ (NodeKind::Loop(_) | NodeKind::Scan(_)) => {
+                flush(&mut folded, &mut offset, &mut move_span);
+                kind
+            }
+        };
+        folded.push(Node {
+            kind,
+            span: node.span,
+        });
+    }
+    flush(&mut folded, &mut offset, &mut move_span);
+    folded
+}
+
+/// Recognises an optimised loop body that can run in one go: `[-]` clears,
+/// `[->+>++<<]` style loops multiply into their neighbours and then clear, and
+/// `[>]` scans.
+fn loop_idiom(body: &[Node]) -> Option<Vec<NodeKind>> {
+    if let [Node {
+        kind: NodeKind::Move(step),
+        ..
+    }] = body
+    {
+        return Some(vec![NodeKind::Scan(*step)]);
+    }
+    let mut counter = None;
+    let mut targets = Vec::new();
+    for node in body {
+        match node.kind {
+            NodeKind::Add { offset: 0, delta } if counter.is_none() => counter = Some(delta),
+            NodeKind::Add { offset, delta } if offset != 0 => targets.push((offset, delta)),
+            _ => return None,
+        }
+    }
+    // The loop runs `cell[0]` times when it counts down and `-cell[0]` times
+    // when it counts up, so an upward counter negates every factor.
+    let sign = match counter? {
+        u32::MAX => 1,
+        1 => u32::MAX,
+        _ => return None,
+    };
+    let mut kinds: Vec<NodeKind> = targets
+        .into_iter()
+        .map(|(offset, delta)| NodeKind::MulAdd {
+            src: 0,
+            offset,
+            factor: delta.wrapping_mul(sign),
+        })
+        .collect();
+    kinds.push(NodeKind::Clear { offset: 0 });
+    Some(kinds)
+}
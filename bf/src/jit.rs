@@ -0,0 +1,132 @@
+use crate::codegen::x86::{Assembler, Io};
+use crate::config::Config;
+use crate::interpreter::Termination;
+use crate::vm::Bytecode;
+use std::ptr;
+
+/// Where the JIT gets its input from and sends its output to.
+pub trait JitIo {
+    /// The next input byte, or `None` at the end of the input.
+    fn read(&mut self) -> Option<u8>;
+    /// Takes one output byte. Returning a termination stops the program.
+    fn write(&mut self, byte: u8) -> Option<Termination>;
+}
+
+/// Passed to the generated code in r14 and handed back to the callbacks.
+/// `pointer` has to stay the first field: the epilogue stores the final
+/// pointer there.
+#[repr(C)]
+struct Context<'a> {
+    pointer: usize,
+    io: &'a mut dyn JitIo,
+    stopped: Option<Termination>,
+}
+
+extern "sysv64" fn jit_write(context: *mut Context, byte: u32) -> u32 {
+    // SAFETY: the generated code passes back the context `Jit::run` gave it.
+    let context = unsafe { &mut *context };
+    context.stopped = context.io.write(byte as u8);
+    context.stopped.is_some() as u32
+}
+
+extern "sysv64" fn jit_read(context: *mut Context) -> i32 {
+    // SAFETY: as for `jit_write`.
+    let context = unsafe { &mut *context };
+    context.io.read().map_or(-1, i32::from)
+}
+
+type Entry = extern "sysv64" fn(*mut u32, usize, usize, *mut Context) -> u32;
+
+/// A page-aligned mapping that is writable while the code is copied in and
+/// executable after that.
+struct ExecutableMemory {
+    address: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    fn new(code: &[u8]) -> Option<Self> {
+        let len = code.len().max(1);
+        // SAFETY: a fresh anonymous mapping; nothing else refers to it.
+        unsafe {
+            let address = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if address == libc::MAP_FAILED {
+                return None;
+            }
+            let memory = ExecutableMemory { address, len };
+            ptr::copy_nonoverlapping(code.as_ptr(), address as *mut u8, code.len());
+            if libc::mprotect(address, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(memory)
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: the mapping came from `mmap` in `new` and is only unmapped here.
+        unsafe {
+            libc::munmap(self.address, self.len);
+        }
+    }
+}
+
+/// Native x86-64 code for one program and configuration.
+pub struct Jit {
+    memory: ExecutableMemory,
+    tape_size: usize,
+}
+
+impl Jit {
+    /// Compiles `bytecode` (built for `config.tape_size`). Returns `None` if
+    /// the tape is too big for 32-bit offsets or the code can't be mapped.
+    pub fn compile(bytecode: &Bytecode, config: Config) -> Option<Jit> {
+        if config.tape_size > i32::MAX as usize {
+            return None;
+        }
+        let io = Io::Callbacks {
+            read: jit_read as *const () as u64,
+            write: jit_write as *const () as u64,
+        };
+        let code = Assembler::new(config, io).assemble(bytecode);
+        Some(Jit {
+            memory: ExecutableMemory::new(&code)?,
+            tape_size: config.tape_size,
+        })
+    }
+
+    /// Runs the program from its start on `cells` with the pointer at
+    /// `pointer`. Returns how it stopped and where the pointer ended up.
+    /// A run can't be resumed, so input that isn't there yet has to be waited
+    /// for in `JitIo::read`.
+    pub fn run(
+        &self,
+        cells: &mut [u32],
+        pointer: usize,
+        io: &mut dyn JitIo,
+    ) -> (Termination, usize) {
+        assert!(cells.len() == self.tape_size && pointer < self.tape_size);
+        let mut context = Context {
+            pointer,
+            io,
+            stopped: None,
+        };
+        // SAFETY: the memory holds a function with the `Entry` ABI, made by
+        // `Assembler` for a tape of exactly `cells.len()` cells, and it only
+        // touches cells at indexes it has wrapped into range.
+        let entry: Entry = unsafe { std::mem::transmute(self.memory.address) };
+        entry(cells.as_mut_ptr(), pointer, cells.len(), &mut context);
+        (
+            context.stopped.unwrap_or(Termination::Finished),
+            context.pointer,
+        )
+    }
+}
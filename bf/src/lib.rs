@@ -0,0 +1,23 @@
+pub mod analysis;
+pub mod bench;
+pub mod codegen;
+pub mod config;
+pub mod decompile;
+pub mod equiv;
+pub mod fuzz;
+pub mod golden;
+pub mod interpreter;
+pub mod ir;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+pub mod jit;
+pub mod ops;
+pub mod reduce;
+pub mod rng;
+pub mod snapshot;
+pub mod sourcemap;
+pub mod symbolic;
+pub mod trace;
+pub mod transcript;
+pub mod vm;
+#[cfg(feature = "wasm-runtime")]
+pub mod wasm_runtime;
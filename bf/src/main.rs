@@ -1,21 +1,27 @@
 use crate::egui::Vec2;
+use bf::codegen::Source;
+use bf::config::{parse_headers, Config};
+use bf::decompile::decompile;
+use bf::interpreter::{Interpreter, Termination};
+use bf::ir::optimise;
+use bf::ops::{Dialect, Program};
+use bf::snapshot::Snapshot;
+use bf::trace::read_trace;
+use bf::transcript::Transcript;
 use eframe::egui;
+use std::path::Path;
+use std::time::Duration;
 
-const CSIZE: usize = 30000;
+mod cli;
+mod trace_viewer;
 
-#[derive(Clone, Copy)]
-enum OPS {
-    Decrement,
-    Increment,
-    Input,
-    LoopEnd,
-    LoopStart,
-    Print,
-    ShiftLeft,
-    ShiftRight,
-}
+use trace_viewer::TraceViewer;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::main(&args));
+    }
     let options = eframe::NativeOptions {
         initial_window_size: Some(Vec2 { x: 930.0, y: 650.0 }),
         ..eframe::NativeOptions::default()
@@ -28,106 +34,138 @@ fn main() {
 }
 
 struct BFInterpreter {
-    cells: [u8; CSIZE],
-    current_instruction: usize,
-    eof: bool,
+    /// The decompiled program and the mode and text it was made from, while
+    /// the side panel is open.
+    decompiled: Option<(bool, String, String)>,
     fnord: bool,
-    index: usize,
     input: bool,
     input_text: String,
-    loop_stack: Vec<usize>,
+    interpreter: Interpreter,
     offset_cells: usize,
-    program_ops: Vec<OPS>,
     program_text: String,
-    result_text: String,
+    status: String,
+    timeout_ms: Option<u64>,
+    trace_viewer: Option<TraceViewer>,
+}
+
+fn limit_editor<N: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    label: &str,
+    limit: &mut Option<N>,
+    default: N,
+) {
+    let mut enabled = limit.is_some();
+    ui.checkbox(&mut enabled, label);
+    match (enabled, limit.as_mut()) {
+        (true, Some(value)) => {
+            ui.add(egui::DragValue::new(value));
+        }
+        (true, None) => *limit = Some(default),
+        (false, _) => *limit = None,
+    }
 }
 
 impl BFInterpreter {
     fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         BFInterpreter {
-            cells: [0; CSIZE],
-            current_instruction: 0,
-            eof: false,
+            decompiled: None,
             fnord: false,
-            index: 0,
             input: false,
             input_text: String::new(),
-            loop_stack: Vec::new(),
+            interpreter: Interpreter::new(),
             offset_cells: 0,
-            program_ops: Vec::new(),
             program_text: String::from("-[------->+<]>-.-[->+++++<]>++.+++++++..+++.[->+++++<]>+.------------.---[->+++<]>.-[--->+<]>---.+++.------.--------.-[--->+<]>..."),
-            result_text: String::new(),
+            status: String::new(),
+            timeout_ms: Some(5000),
+            trace_viewer: None,
         }
     }
-    fn run(&mut self) {
-        if self.input && !self.eof {
-            self.cells[self.index] = self.input_text.chars().next().unwrap() as u8;
-            self.input = false;
+    fn parse_program(&self) -> Result<(Program, Config), Box<dyn std::error::Error>> {
+        let dialect = if self.fnord {
+            Dialect::BrainFnord2
+        } else {
+            Dialect::Brainfuck
+        };
+        let headers = parse_headers(&self.program_text)?;
+        Ok((Program::parse(&headers.source, dialect)?, headers.config))
+    }
+    /// Switches to `config`, which means a fresh tape if it differs from the
+    /// current one.
+    fn configure(&mut self, config: Config) {
+        if config != self.interpreter.config {
+            let limits = self.interpreter.limits;
+            self.interpreter = Interpreter::with_config(config);
+            self.interpreter.limits = limits;
+            self.offset_cells = 0;
         }
-        if !self.input && !self.eof {
-            loop {
-                if self.program_ops.is_empty() {
-                    break;
-                }
-                let op = self.program_ops[self.current_instruction];
-                match op {
-                    OPS::ShiftRight => {
-                        self.index += 1;
-                        if self.index == CSIZE {
-                            self.index = 0;
-                        }
-                    }
-                    OPS::ShiftLeft => {
-                        if (self.index as isize) - 1 == -1 {
-                            self.index = CSIZE - 1;
-                        } else {
-                            self.index -= 1;
-                        }
-                    }
-                    OPS::Increment => {
-                        self.cells[self.index] = self.cells[self.index].wrapping_add(1);
-                    }
-                    OPS::Decrement => {
-                        self.cells[self.index] = self.cells[self.index].wrapping_sub(1);
-                    }
-                    OPS::Print => {
-                        let mut tmp = [0, 4];
-                        self.result_text
-                            .push_str(char::encode_utf8(self.cells[self.index] as char, &mut tmp));
-                    }
-                    OPS::Input => {
-                        self.input = true;
-                        self.current_instruction += 1;
-                        if self.current_instruction == self.program_ops.len() {
-                            self.current_instruction = 0;
-                            self.eof = true;
-                        }
-                        break;
-                    }
-                    OPS::LoopStart => {
-                        self.loop_stack.push(self.current_instruction);
-                    }
-                    OPS::LoopEnd => {
-                        if self.cells[self.index] != 0 {
-                            self.current_instruction = *self.loop_stack.last().unwrap();
-                        } else {
-                            self.loop_stack.pop();
-                        }
-                    }
-                }
-                self.current_instruction += 1;
-                if self.current_instruction == self.program_ops.len() {
-                    self.current_instruction = 0;
-                    self.eof = true;
-                    break;
-                }
-            }
+    }
+    fn load_state(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let snapshot = Snapshot::load(path)?;
+        self.interpreter.load(self.parse_program()?.0);
+        self.interpreter.restore(snapshot)?;
+        Ok(())
+    }
+    fn replay(&mut self, path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+        let transcript = Transcript::load(path)?;
+        let mut limits = self.interpreter.limits;
+        limits.timeout = self.timeout_ms.map(Duration::from_millis);
+        transcript.replay(self.parse_program()?.0, limits)?;
+        Ok(format!(
+            "Replayed {} input bytes, output matches",
+            transcript.inputs.len()
+        ))
+    }
+    fn decompile_toggle(&mut self, ui: &mut egui::Ui) {
+        let mut open = self.decompiled.is_some();
+        if ui.toggle_value(&mut open, "Decompile").changed() {
+            self.decompiled =
+                open.then(|| (self.fnord, self.program_text.clone(), self.decompile()));
+        }
+    }
+    fn decompile(&self) -> String {
+        match self.parse_program() {
+            Ok((program, config)) => decompile(
+                &optimise(&program),
+                config,
+                &Source::new("program", &self.program_text),
+            ),
+            Err(e) => e.to_string(),
         }
     }
+    fn run(&mut self) {
+        self.interpreter.limits.timeout = self.timeout_ms.map(Duration::from_millis);
+        let termination = self.interpreter.run();
+        self.input = termination == Termination::AwaitingInput;
+        self.status = if termination.is_limit() {
+            termination.to_string()
+        } else {
+            String::new()
+        };
+    }
 }
 
 impl eframe::App for BFInterpreter {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Some((fnord, text, _)) = &self.decompiled {
+            if *fnord != self.fnord || *text != self.program_text {
+                let decompiled = self.decompile();
+                self.decompiled = Some((self.fnord, self.program_text.clone(), decompiled));
+            }
+        }
+        if let Some((_, _, decompiled)) = &self.decompiled {
+            egui::SidePanel::right("decompiled")
+                .resizable(true)
+                .default_width(320.0)
+                .show(ctx, |ui| {
+                    ui.heading("Decompiled");
+                    egui::ScrollArea::both().show(ui, |ui| {
+                        ui.add(
+                            egui::Label::new(egui::RichText::new(decompiled).monospace())
+                                .wrap(false),
+                        );
+                    });
+                });
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
                 if self.fnord {
@@ -137,6 +175,7 @@ impl eframe::App for BFInterpreter {
                         if ui.button("Convert to Brainfuck mode").clicked() {
                             self.fnord = false;
                         }
+                        self.decompile_toggle(ui);
                     });
                 } else {
                     ui.heading("Brainfuck Interpreter");
@@ -145,6 +184,7 @@ impl eframe::App for BFInterpreter {
                         if ui.button("Convert to BrainFNORD mode").clicked() {
                             self.fnord = true;
                         }
+                        self.decompile_toggle(ui);
                     });
                 }
                 egui::ScrollArea::vertical().show(ui, |ui| {
@@ -158,173 +198,147 @@ impl eframe::App for BFInterpreter {
                             .desired_rows(29),
                     );
                 });
+                let mask = self.interpreter.config.cell_width.mask();
+                let shown = self.interpreter.cells.len().min(19);
+                self.offset_cells = self.offset_cells.min(self.interpreter.cells.len() - shown);
                 ui.horizontal(|ui| {
-                    for i in 0..19 {
+                    for i in 0..shown {
                         ui.vertical(|ui| {
                             ui.add(
                                 egui::Label::new(format!("{}", i + self.offset_cells)).wrap(false),
                             );
-                            ui.add(egui::DragValue::new(&mut self.cells[i + self.offset_cells]));
+                            ui.add(
+                                egui::DragValue::new(
+                                    &mut self.interpreter.cells[i + self.offset_cells],
+                                )
+                                .clamp_range(0..=mask),
+                            );
                         });
                     }
                 });
                 ui.add(
-                    egui::Slider::new(&mut self.offset_cells, 0..=CSIZE - 19).text("Scroll Cells"),
+                    egui::Slider::new(
+                        &mut self.offset_cells,
+                        0..=self.interpreter.cells.len() - shown,
+                    )
+                    .text("Scroll Cells"),
                 );
+                ui.horizontal(|ui| {
+                    let limits = &mut self.interpreter.limits;
+                    limit_editor(ui, "Max steps", &mut limits.max_steps, 10_000_000);
+                    limit_editor(ui, "Max output bytes", &mut limits.max_output_bytes, 65536);
+                    limit_editor(ui, "Max cells", &mut limits.max_cells_touched, 30000);
+                    limit_editor(ui, "Timeout (ms)", &mut self.timeout_ms, 5000);
+                });
                 ui.heading("Results Panel");
                 ui.horizontal(|ui| {
                     if self.input {
                         ui.label("Input a character please");
                         ui.add(egui::TextEdit::singleline(&mut self.input_text));
                         if ui.button("Enter your input").clicked() {
-                            self.run();
-                        }
-                    } else if ui.button("Run").clicked() {
-                        if self.fnord {
-                            let mut token_index = 0;
-                            loop {
-                                if token_index == self.program_text.as_bytes().len() {
-                                    break;
-                                }
-                                let token_start: char =
-                                    self.program_text.as_bytes()[token_index] as char;
-                                match token_start {
-                                    'f' => {
-                                        let token = "fnord";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::ShiftLeft);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    'k' => {
-                                        let token = "kallisti";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::ShiftRight);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    'p' => {
-                                        let token = "pineal";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::Print);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    'c' => {
-                                        let token = "chaos";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::Input);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    '2' => {
-                                        let token = "23";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::LoopStart);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    '5' => {
-                                        let token = "5";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::Increment);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    'h' => {
-                                        let token = "hail";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::Decrement);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    'e' => {
-                                        let token = "eris";
-                                        if &self.program_text
-                                            [token_index..token_index + token.len()]
-                                            == token
-                                        {
-                                            self.program_ops.push(OPS::LoopEnd);
-                                            token_index += token.len();
-                                        }
-                                    }
-                                    _ => {
-                                        token_index += 1;
-                                    }
-                                }
+                            if let Some(c) = self.input_text.chars().next() {
+                                self.interpreter.push_input(&[c as u8]);
+                                self.run();
                             }
-                        } else {
-                            for c in self.program_text.chars() {
-                                match c {
-                                    '>' => {
-                                        self.program_ops.push(OPS::ShiftRight);
-                                    }
-                                    '<' => {
-                                        self.program_ops.push(OPS::ShiftLeft);
-                                    }
-                                    '+' => {
-                                        self.program_ops.push(OPS::Increment);
-                                    }
-                                    '-' => {
-                                        self.program_ops.push(OPS::Decrement);
-                                    }
-                                    '.' => {
-                                        self.program_ops.push(OPS::Print);
-                                    }
-                                    ',' => {
-                                        self.program_ops.push(OPS::Input);
-                                    }
-                                    '[' => {
-                                        self.program_ops.push(OPS::LoopStart);
-                                    }
-                                    ']' => {
-                                        self.program_ops.push(OPS::LoopEnd);
-                                    }
-                                    _ => {}
+                        }
+                    } else {
+                        if ui.button("Run").clicked() {
+                            match self.parse_program() {
+                                Ok((program, config)) => {
+                                    self.configure(config);
+                                    self.interpreter.load(program);
+                                    self.interpreter.start_recording();
+                                    self.run();
                                 }
+                                Err(e) => self.status = e.to_string(),
                             }
                         }
-                        self.run();
+                        if !self.interpreter.is_finished() && ui.button("Continue").clicked() {
+                            self.interpreter.resume();
+                            self.run();
+                        }
                     }
                     if ui.button("Reset").clicked() {
-                        self.cells = [0; CSIZE];
-                        self.index = 0;
-                        self.result_text = "".to_owned();
-                    }
-                    if self.eof {
-                        self.program_ops = Vec::new();
-                        self.loop_stack = Vec::new();
+                        self.interpreter.reset();
                         self.input = false;
-                        self.current_instruction = 0;
-                        self.eof = false;
+                        self.status = String::new();
+                    }
+                    if ui.button("Save state").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("BF state", &["bfstate"])
+                            .save_file()
+                        {
+                            if let Err(e) = self.interpreter.snapshot().save(&path) {
+                                self.status = e.to_string();
+                            }
+                        }
+                    }
+                    if ui.button("Load state").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("BF state", &["bfstate"])
+                            .pick_file()
+                        {
+                            self.input = false;
+                            self.status = match self.load_state(&path) {
+                                Ok(()) => String::new(),
+                                Err(e) => e.to_string(),
+                            };
+                        }
+                    }
+                    if self.interpreter.recording.is_some()
+                        && ui.button("Save transcript").clicked()
+                    {
+                        if let (Some(path), Some(transcript)) = (
+                            rfd::FileDialog::new()
+                                .add_filter("Transcript", &["json"])
+                                .save_file(),
+                            self.interpreter.transcript(),
+                        ) {
+                            if let Err(e) = transcript.save(&path) {
+                                self.status = e.to_string();
+                            }
+                        }
+                    }
+                    if ui.button("Replay transcript").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Transcript", &["json"])
+                            .pick_file()
+                        {
+                            self.status = match self.replay(&path) {
+                                Ok(message) => message,
+                                Err(e) => e.to_string(),
+                            };
+                        }
+                    }
+                    if ui.button("Load trace").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Trace", &["jsonl"])
+                            .pick_file()
+                        {
+                            match read_trace(&path) {
+                                Ok(events) => {
+                                    let name = path.display().to_string();
+                                    self.trace_viewer = Some(TraceViewer::new(name, events));
+                                }
+                                Err(e) => self.status = e.to_string(),
+                            }
+                        }
+                    }
+                    if !self.status.is_empty() {
+                        ui.colored_label(egui::Color32::RED, &self.status);
                     }
                 });
                 ui.add_sized(
                     ui.available_size(),
-                    egui::TextEdit::multiline(&mut self.result_text),
+                    egui::TextEdit::multiline(&mut self.interpreter.result_text),
                 );
             });
         });
+        if let Some(viewer) = &mut self.trace_viewer {
+            viewer.show(ctx, &self.program_text);
+            if !viewer.open {
+                self.trace_viewer = None;
+            }
+        }
     }
 }
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OPS {
+    Decrement,
+    Increment,
+    Input,
+    LoopEnd,
+    LoopStart,
+    Print,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Brainfuck,
+    BrainFnord2,
+}
+
+const FNORD_TOKENS: [(&str, OPS); 8] = [
+    ("fnord", OPS::ShiftLeft),
+    ("kallisti", OPS::ShiftRight),
+    ("pineal", OPS::Print),
+    ("chaos", OPS::Input),
+    ("23", OPS::LoopStart),
+    ("5", OPS::Increment),
+    ("hail", OPS::Decrement),
+    ("eris", OPS::LoopEnd),
+];
+
+impl OPS {
+    /// How the op is written in `dialect`.
+    pub fn token(self, dialect: Dialect) -> &'static str {
+        match dialect {
+            Dialect::Brainfuck => match self {
+                OPS::Decrement => "-",
+                OPS::Increment => "+",
+                OPS::Input => ",",
+                OPS::LoopEnd => "]",
+                OPS::LoopStart => "[",
+                OPS::Print => ".",
+                OPS::ShiftLeft => "<",
+                OPS::ShiftRight => ">",
+            },
+            Dialect::BrainFnord2 => {
+                FNORD_TOKENS
+                    .iter()
+                    .find(|(_, op)| *op == self)
+                    .expect("every op has a token")
+                    .0
+            }
+        }
+    }
+}
+
+/// Every op in `source` and the byte span of its token, without checking
+/// that the brackets match. Anything that isn't a token is a comment.
+pub fn tokenize(source: &str, dialect: Dialect) -> (Vec<OPS>, Vec<Range<usize>>) {
+    let mut ops = Vec::new();
+    let mut spans = Vec::new();
+    match dialect {
+        Dialect::Brainfuck => {
+            for (position, c) in source.char_indices() {
+                let op = match c {
+                    '>' => OPS::ShiftRight,
+                    '<' => OPS::ShiftLeft,
+                    '+' => OPS::Increment,
+                    '-' => OPS::Decrement,
+                    '.' => OPS::Print,
+                    ',' => OPS::Input,
+                    '[' => OPS::LoopStart,
+                    ']' => OPS::LoopEnd,
+                    _ => continue,
+                };
+                ops.push(op);
+                spans.push(position..position + 1);
+            }
+        }
+        Dialect::BrainFnord2 => {
+            let mut token_index = 0;
+            while token_index < source.len() {
+                let rest = &source.as_bytes()[token_index..];
+                match FNORD_TOKENS
+                    .iter()
+                    .find(|(token, _)| rest.starts_with(token.as_bytes()))
+                {
+                    Some((token, op)) => {
+                        ops.push(*op);
+                        spans.push(token_index..token_index + token.len());
+                        token_index += token.len();
+                    }
+                    None => {
+                        token_index += 1;
+                    }
+                }
+            }
+        }
+    }
+    (ops, spans)
+}
+
+/// A parsed program: the ops, the byte span of the source token each op came
+/// from, and the matching bracket for every loop op.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub ops: Vec<OPS>,
+    pub spans: Vec<Range<usize>>,
+    pub jumps: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnmatchedLoopEnd { position: usize },
+    UnclosedLoopStart { position: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnmatchedLoopEnd { position } => {
+                write!(
+                    f,
+                    "loop end at byte {} has no matching loop start",
+                    position
+                )
+            }
+            ParseError::UnclosedLoopStart { position } => {
+                write!(f, "loop start at byte {} is never closed", position)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl ParseError {
+    /// The byte of the bracket it is about.
+    pub fn position(&self) -> usize {
+        match self {
+            ParseError::UnmatchedLoopEnd { position }
+            | ParseError::UnclosedLoopStart { position } => *position,
+        }
+    }
+}
+
+impl Program {
+    pub fn parse(source: &str, dialect: Dialect) -> Result<Program, ParseError> {
+        let (ops, spans) = tokenize(source, dialect);
+        let jumps = match_loops(&ops, &spans)?;
+        Ok(Program { ops, spans, jumps })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// FNV-1a hash of the ops, so comments and layout (and the dialect) don't
+    /// change it.
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for op in &self.ops {
+            hash ^= *op as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+fn match_loops(ops: &[OPS], spans: &[Range<usize>]) -> Result<Vec<usize>, ParseError> {
+    let mut jumps = vec![0; ops.len()];
+    let mut open = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            OPS::LoopStart => open.push(i),
+            OPS::LoopEnd => {
+                let start = open.pop().ok_or(ParseError::UnmatchedLoopEnd {
+                    position: spans[i].start,
+                })?;
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = open.pop() {
+        return Err(ParseError::UnclosedLoopStart {
+            position: spans[start].start,
+        });
+    }
+    Ok(jumps)
+}
@@ -0,0 +1,389 @@
+use crate::config::{parse_headers, Config};
+use crate::fuzz::{self, Case, Engine};
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ops::{Dialect, Program, OPS};
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{Command, Stdio};
+
+/// What makes a candidate worth keeping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Interesting {
+    /// The engine prints something other than the interpreter does, fails or
+    /// panics, on a program the interpreter finishes.
+    Differs(Engine),
+    /// Running it panics.
+    Panics,
+    /// It stops early, fails or panics with a message containing this text.
+    Fails(String),
+    /// Its output contains these bytes.
+    Prints(Vec<u8>),
+    /// This shell command exits with 0 when given the candidate's file as
+    /// its last argument.
+    Command(String),
+}
+
+/// A test a program keeps passing while it shrinks.
+#[derive(Clone, Debug)]
+pub struct Predicate {
+    pub interesting: Interesting,
+    /// What runs it for everything but `Differs`, the interpreter if `None`.
+    /// Other engines only get programs the interpreter finishes within the
+    /// limits, so a candidate that loops forever can't stall the reduction.
+    pub engine: Option<Engine>,
+    pub dialect: Dialect,
+    pub config: Config,
+    /// For the interpreter. Without a step limit a candidate that loops
+    /// forever never comes back.
+    pub limits: Limits,
+    pub input: Vec<u8>,
+}
+
+/// How one run went.
+struct Outcome {
+    output: Vec<u8>,
+    /// Why it didn't finish, if it didn't.
+    error: Option<String>,
+    panicked: bool,
+}
+
+impl Outcome {
+    /// Runs `run`, catching a panic without printing its message, then puts
+    /// the panic hook back as it was.
+    fn of(run: impl FnOnce() -> Result<Vec<u8>, (Vec<u8>, String)>) -> Outcome {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let result = panic::catch_unwind(AssertUnwindSafe(run));
+        panic::set_hook(hook);
+        match result {
+            Ok(Ok(output)) => Outcome {
+                output,
+                error: None,
+                panicked: false,
+            },
+            Ok(Err((output, error))) => Outcome {
+                output,
+                error: Some(error),
+                panicked: false,
+            },
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Outcome {
+                    output: Vec::new(),
+                    error: Some(format!("panicked: {}", message)),
+                    panicked: true,
+                }
+            }
+        }
+    }
+}
+
+impl Predicate {
+    /// Whether `text`, a whole source file with any headers, is interesting.
+    /// Panics are caught quietly.
+    pub fn holds(&self, text: &str) -> bool {
+        if let Interesting::Command(command) = &self.interesting {
+            return self.command_succeeds(command, text);
+        }
+        let Ok(headers) = parse_headers(text) else {
+            return false;
+        };
+        let Ok(program) = Program::parse(&headers.source, self.dialect) else {
+            return false;
+        };
+        let reference = Outcome::of(|| self.interpret(&program));
+        let engine = match &self.interesting {
+            Interesting::Differs(engine) => Some(*engine),
+            _ => self.engine,
+        };
+        let outcome = match engine {
+            None => reference,
+            Some(_) if reference.error.is_some() => return false,
+            Some(engine) => {
+                let case = Case {
+                    source: text.to_owned(),
+                    config: self.config,
+                    input: self.input.clone(),
+                };
+                let outcome =
+                    Outcome::of(|| fuzz::run(engine, &program, &case).map_err(|e| (Vec::new(), e)));
+                if let Interesting::Differs(_) = self.interesting {
+                    return outcome.error.is_some() || outcome.output != reference.output;
+                }
+                outcome
+            }
+        };
+        match &self.interesting {
+            Interesting::Panics => outcome.panicked,
+            Interesting::Fails(text) => outcome.error.is_some_and(|error| error.contains(text)),
+            Interesting::Prints(bytes) => {
+                bytes.is_empty()
+                    || outcome
+                        .output
+                        .windows(bytes.len())
+                        .any(|window| window == bytes)
+            }
+            Interesting::Differs(_) | Interesting::Command(_) => unreachable!(),
+        }
+    }
+
+    fn interpret(&self, program: &Program) -> Result<Vec<u8>, (Vec<u8>, String)> {
+        let mut interpreter = Interpreter::with_config(self.config);
+        interpreter.limits = self.limits;
+        interpreter.load(program.clone());
+        interpreter.push_input(&self.input);
+        interpreter.close_input();
+        let termination = interpreter.run();
+        let output = interpreter.result_text.chars().map(|c| c as u8).collect();
+        match termination {
+            Termination::Finished => Ok(output),
+            stopped => Err((output, stopped.to_string())),
+        }
+    }
+
+    fn command_succeeds(&self, command: &str, text: &str) -> bool {
+        let extension = match self.dialect {
+            Dialect::Brainfuck => "bf",
+            Dialect::BrainFnord2 => "bf2",
+        };
+        let path =
+            std::env::temp_dir().join(format!("bf-reduce-{}.{}", std::process::id(), extension));
+        if std::fs::write(&path, text).is_err() {
+            return false;
+        }
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", command))
+            .arg("sh")
+            .arg(&path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = std::fs::remove_file(&path);
+        status.is_ok_and(|status| status.success())
+    }
+}
+
+struct Reducer<'a, F> {
+    ops: &'a [OPS],
+    /// The header lines, which go in front of every candidate.
+    header: &'a str,
+    tokens: Vec<&'a str>,
+    separator: &'static str,
+    interesting: F,
+    /// Every candidate tried so far. The ones that were interesting became
+    /// the current program, so none of them is worth trying again either.
+    tried: HashSet<String>,
+}
+
+impl<F: FnMut(&str) -> bool> Reducer<'_, F> {
+    fn text(&self, kept: &[usize]) -> String {
+        let tokens: Vec<&str> = kept.iter().map(|&i| self.tokens[i]).collect();
+        self.header.to_owned() + &tokens.join(self.separator)
+    }
+
+    fn balanced(&self, kept: &[usize]) -> bool {
+        let mut depth = 0usize;
+        for &i in kept {
+            match self.ops[i] {
+                OPS::LoopStart => depth += 1,
+                OPS::LoopEnd if depth == 0 => return false,
+                OPS::LoopEnd => depth -= 1,
+                _ => {}
+            }
+        }
+        depth == 0
+    }
+
+    fn test(&mut self, kept: &[usize]) -> bool {
+        if !self.balanced(kept) {
+            return false;
+        }
+        let text = self.text(kept);
+        if !self.tried.insert(text.clone()) {
+            return false;
+        }
+        (self.interesting)(&text)
+    }
+
+    /// Delta debugging: tries leaving out ever smaller chunks until no
+    /// single op can go.
+    fn ddmin(&mut self, mut kept: Vec<usize>) -> Vec<usize> {
+        let mut chunks = 2;
+        while !kept.is_empty() {
+            let size = kept.len().div_ceil(chunks);
+            let smaller = (0..kept.len()).step_by(size).find_map(|start| {
+                let end = (start + size).min(kept.len());
+                let candidate = [&kept[..start], &kept[end..]].concat();
+                self.test(&candidate).then_some(candidate)
+            });
+            match smaller {
+                Some(smaller) => {
+                    kept = smaller;
+                    chunks = (chunks - 1).max(2);
+                }
+                None if size == 1 => break,
+                None => chunks = (chunks * 2).min(kept.len()),
+            }
+        }
+        kept
+    }
+
+    /// Tries leaving out each pair of neighbouring ops, for the `+-` and `<>`
+    /// that cancel out, where taking either one alone changes what it does.
+    fn pairs(&mut self, mut kept: Vec<usize>) -> Vec<usize> {
+        let mut start = 0;
+        while start + 1 < kept.len() {
+            let candidate = [&kept[..start], &kept[start + 2..]].concat();
+            if self.test(&candidate) {
+                kept = candidate;
+            } else {
+                start += 1;
+            }
+        }
+        kept
+    }
+
+    /// Tries leaving out each loop whole and then just its brackets, which
+    /// chunks that split a loop can never do.
+    fn loops(&mut self, mut kept: Vec<usize>) -> Vec<usize> {
+        let mut start = 0;
+        while start < kept.len() {
+            if self.ops[kept[start]] != OPS::LoopStart {
+                start += 1;
+                continue;
+            }
+            let mut depth = 0;
+            let end = (start..kept.len())
+                .find(|&i| {
+                    match self.ops[kept[i]] {
+                        OPS::LoopStart => depth += 1,
+                        OPS::LoopEnd => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .expect("kept is balanced");
+            let whole = [&kept[..start], &kept[end + 1..]].concat();
+            let body = [&kept[..start], &kept[start + 1..end], &kept[end + 1..]].concat();
+            if self.test(&whole) {
+                kept = whole;
+            } else if self.test(&body) {
+                kept = body;
+            } else {
+                start += 1;
+            }
+        }
+        kept
+    }
+}
+
+/// Shrinks `program`, parsed from `source`, to a smaller one that
+/// `interesting` still accepts, and returns its source: any header lines
+/// `source` starts with, then just the ops, one token each, with spaces
+/// between them in BrainFNORD2. Brackets stay balanced. Assumes the whole program is interesting; every candidate
+/// `interesting` accepts becomes the new program, so the last one it
+/// accepted is the result.
+pub fn reduce(
+    program: &Program,
+    source: &str,
+    dialect: Dialect,
+    interesting: impl FnMut(&str) -> bool,
+) -> String {
+    let header_len = parse_headers(source).map_or(0, |headers| headers.header_len);
+    let mut reducer = Reducer {
+        ops: &program.ops,
+        header: &source[..header_len],
+        tokens: program
+            .spans
+            .iter()
+            .map(|span| &source[span.clone()])
+            .collect(),
+        separator: match dialect {
+            Dialect::Brainfuck => "",
+            Dialect::BrainFnord2 => " ",
+        },
+        interesting,
+        tried: HashSet::new(),
+    };
+    let mut kept: Vec<usize> = (0..program.len()).collect();
+    loop {
+        let before = kept.len();
+        kept = reducer.ddmin(kept);
+        kept = reducer.pairs(kept);
+        kept = reducer.loops(kept);
+        if kept.len() == before {
+            return reducer.text(&kept);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reduce, Interesting, Predicate};
+    use crate::config::{parse_headers, Config};
+    use crate::interpreter::Limits;
+    use crate::ops::{Dialect, Program};
+
+    fn prints(bytes: &[u8], config: Config) -> Predicate {
+        Predicate {
+            interesting: Interesting::Prints(bytes.to_vec()),
+            engine: None,
+            dialect: Dialect::Brainfuck,
+            config,
+            limits: Limits {
+                max_steps: Some(10_000),
+                ..Limits::default()
+            },
+            input: Vec::new(),
+        }
+    }
+
+    fn reduced(source: &str, mut interesting: impl FnMut(&str) -> bool) -> String {
+        let headers = parse_headers(source).unwrap();
+        let program = Program::parse(&headers.source, Dialect::Brainfuck).unwrap();
+        assert!(interesting(source));
+        reduce(&program, source, Dialect::Brainfuck, interesting)
+    }
+
+    #[test]
+    fn shrinks_to_the_smallest_program_that_still_prints() {
+        let predicate = prints(&[2], Config::default());
+        let source = ",>>+++<<[-]>+<< comment >>[<+>-]<+.>>--<[.]";
+        assert_eq!(
+            reduced(source, |candidate| predicate.holds(candidate)),
+            "++."
+        );
+    }
+
+    #[test]
+    fn keeps_the_brackets_balanced() {
+        let mut tried = 0;
+        let result = reduced("+[->+<]>[.-]+[[-]]", |candidate| {
+            tried += 1;
+            assert!(Program::parse(candidate, Dialect::Brainfuck).is_ok());
+            candidate.contains('.')
+        });
+        assert_eq!(result, ".");
+        assert!(tried > 1);
+    }
+
+    #[test]
+    fn keeps_the_header_lines() {
+        let header = "# cell_width: 16\n# eof: zero\n";
+        let source = format!("{}>+++[-]<,+.", header);
+        let result = reduced(&source, |candidate| {
+            // Every candidate is a whole file, to run on the machine its
+            // headers ask for.
+            assert!(candidate.starts_with(header));
+            let headers = parse_headers(candidate).unwrap();
+            prints(&[1], headers.config).holds(candidate)
+        });
+        assert_eq!(result, format!("{}+.", header));
+    }
+}
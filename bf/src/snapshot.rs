@@ -0,0 +1,223 @@
+use crate::config::Config;
+use crate::interpreter::Interpreter;
+use crate::ops::OPS;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Everything needed to carry on running a program later. The tape is stored
+/// without its trailing zero cells. Version 1 snapshots predate `config` and
+/// load with the default one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub program_hash: String,
+    #[serde(default)]
+    pub config: Config,
+    pub cells: Vec<u32>,
+    pub index: usize,
+    pub current_instruction: usize,
+    pub loop_stack: Vec<usize>,
+    pub input: Vec<u8>,
+    pub input_position: usize,
+    pub input_closed: bool,
+    pub result_text: String,
+    pub steps: u64,
+    /// The breakpoint it stopped at, if it did.
+    #[serde(default)]
+    pub stopped_at: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    Version { found: u32 },
+    ProgramMismatch { expected: String, found: String },
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Format(e) => write!(f, "not a snapshot file: {}", e),
+            SnapshotError::Version { found } => write!(
+                f,
+                "snapshot version {} is not supported (expected {})",
+                found, SNAPSHOT_VERSION
+            ),
+            SnapshotError::ProgramMismatch { expected, found } => write!(
+                f,
+                "snapshot was taken from program {} but the loaded program is {}",
+                found, expected
+            ),
+            SnapshotError::Invalid(reason) => write!(f, "snapshot is corrupt: {}", reason),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Format(e)
+    }
+}
+
+pub fn hash_string(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let snapshot: Snapshot = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if snapshot.version == 0 || snapshot.version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version {
+                found: snapshot.version,
+            });
+        }
+        Ok(snapshot)
+    }
+}
+
+impl Interpreter {
+    pub fn snapshot(&self) -> Snapshot {
+        let used = self
+            .cells
+            .iter()
+            .rposition(|&c| c != 0)
+            .map_or(0, |last| last + 1);
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            program_hash: hash_string(self.program.hash()),
+            config: self.config,
+            cells: self.cells[..used].to_vec(),
+            index: self.index,
+            current_instruction: self.current_instruction,
+            loop_stack: self.loop_stack.clone(),
+            input: self.input.clone(),
+            input_position: self.input_position,
+            input_closed: self.input_closed,
+            result_text: self.result_text.clone(),
+            steps: self.steps,
+            stopped_at: self.stopped_at,
+        }
+    }
+
+    /// The `[`s of the loops around `instruction`, outermost first, which is
+    /// what the loop stack holds when the run gets there.
+    fn open_loops(&self, instruction: usize) -> Vec<usize> {
+        let mut open = Vec::new();
+        for (i, op) in self.program.ops[..instruction].iter().enumerate() {
+            match op {
+                OPS::LoopStart => open.push(i),
+                OPS::LoopEnd => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+        open
+    }
+
+    /// Puts the machine back into the state of `snapshot`. The program it was
+    /// taken from must already be loaded.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), SnapshotError> {
+        let expected = hash_string(self.program.hash());
+        if snapshot.program_hash != expected {
+            return Err(SnapshotError::ProgramMismatch {
+                expected,
+                found: snapshot.program_hash,
+            });
+        }
+        let tape_size = snapshot.config.tape_size;
+        if snapshot.cells.len() > tape_size || snapshot.index >= tape_size {
+            return Err(SnapshotError::Invalid("pointer or cells are off the tape"));
+        }
+        let mask = snapshot.config.cell_width.mask();
+        if snapshot.cells.iter().any(|&c| c & !mask != 0) {
+            return Err(SnapshotError::Invalid("cell is wider than the cell width"));
+        }
+        if snapshot.current_instruction > self.program.len()
+            || snapshot.input_position > snapshot.input.len()
+            || snapshot.loop_stack.iter().any(|&i| i >= self.program.len())
+        {
+            return Err(SnapshotError::Invalid(
+                "position is outside the program or input",
+            ));
+        }
+        if snapshot.loop_stack != self.open_loops(snapshot.current_instruction) {
+            return Err(SnapshotError::Invalid(
+                "loop stack doesn't match the loops around the position",
+            ));
+        }
+        self.config = snapshot.config;
+        self.cells = vec![0; tape_size];
+        self.cells[..snapshot.cells.len()].copy_from_slice(&snapshot.cells);
+        self.index = snapshot.index;
+        self.current_instruction = snapshot.current_instruction;
+        self.loop_stack = snapshot.loop_stack;
+        self.input = snapshot.input;
+        self.input_position = snapshot.input_position;
+        self.input_closed = snapshot.input_closed;
+        self.result_text = snapshot.result_text;
+        self.steps = snapshot.steps;
+        self.stopped_at = snapshot.stopped_at;
+        self.resume();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Interpreter, Limits, Termination};
+    use crate::ops::{Dialect, Program};
+
+    fn stopped_in_loop() -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.load(Program::parse("++[-]", Dialect::Brainfuck).unwrap());
+        interpreter.limits = Limits {
+            max_steps: Some(4),
+            ..Limits::default()
+        };
+        assert!(matches!(interpreter.run(), Termination::StepLimit { .. }));
+        interpreter.limits = Limits::default();
+        interpreter
+    }
+
+    #[test]
+    fn restores_inside_a_loop() {
+        let mut interpreter = stopped_in_loop();
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.loop_stack, vec![2]);
+        interpreter.restore(snapshot).unwrap();
+        assert_eq!(interpreter.run(), Termination::Finished);
+    }
+
+    #[test]
+    fn rejects_a_loop_stack_that_doesnt_match() {
+        let mut interpreter = stopped_in_loop();
+        for loop_stack in [vec![], vec![2, 2], vec![0]] {
+            let mut snapshot = interpreter.snapshot();
+            snapshot.loop_stack = loop_stack;
+            assert!(interpreter.restore(snapshot).is_err());
+        }
+    }
+}
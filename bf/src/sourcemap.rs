@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+/// Where one token went when a program was converted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenSpans {
+    pub source: Range<usize>,
+    pub destination: Range<usize>,
+}
+
+/// Which bytes of a converted program came from which bytes of the file it
+/// was converted from, token by token. bf2bf writes these next to what it
+/// converts, as `<destination>.map`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// The file that was converted, relative to the map's folder.
+    pub source: String,
+    /// The file it was converted to, relative to the map's folder.
+    pub destination: String,
+    /// In the order they are in both files.
+    pub tokens: Vec<TokenSpans>,
+}
+
+impl SourceMap {
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<SourceMap, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The span in the source of the token at byte `position` of the
+    /// destination, if there is a token there.
+    pub fn to_source(&self, position: usize) -> Option<Range<usize>> {
+        let i = self
+            .tokens
+            .partition_point(|token| token.destination.end <= position);
+        let token = self.tokens.get(i)?;
+        token
+            .destination
+            .contains(&position)
+            .then(|| token.source.clone())
+    }
+
+    /// The span in the destination of the token at byte `position` of the
+    /// source, if there is a token there.
+    pub fn to_destination(&self, position: usize) -> Option<Range<usize>> {
+        let i = self
+            .tokens
+            .partition_point(|token| token.source.end <= position);
+        let token = self.tokens.get(i)?;
+        token
+            .source
+            .contains(&position)
+            .then(|| token.destination.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceMap, TokenSpans};
+    use crate::interpreter::{Interpreter, Termination};
+    use crate::ops::{Dialect, Program};
+
+    /// `+x[-]` converted to `5 23 hail eris`, with its comment dropped.
+    fn map() -> SourceMap {
+        let spans = [(0..1, 0..1), (2..3, 2..4), (3..4, 5..9), (4..5, 10..14)];
+        SourceMap {
+            source: "prog.bf".to_owned(),
+            destination: "prog.bf2".to_owned(),
+            tokens: spans
+                .into_iter()
+                .map(|(source, destination)| TokenSpans {
+                    source,
+                    destination,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn maps_tokens_both_ways() {
+        let map = map();
+        assert_eq!(map.to_source(0), Some(0..1));
+        assert_eq!(map.to_source(3), Some(2..3));
+        assert_eq!(map.to_source(8), Some(3..4));
+        assert_eq!(map.to_destination(2), Some(2..4));
+        assert_eq!(map.to_destination(4), Some(10..14));
+    }
+
+    #[test]
+    fn has_nothing_between_tokens() {
+        let map = map();
+        assert_eq!(map.to_source(1), None);
+        assert_eq!(map.to_source(14), None);
+        assert_eq!(map.to_destination(1), None);
+        assert_eq!(map.to_destination(5), None);
+    }
+
+    /// A breakpoint on the `-` of the original stops before `hail`.
+    #[test]
+    fn maps_breakpoints() {
+        let program = Program::parse("5 23 hail eris", Dialect::BrainFnord2).unwrap();
+        let byte = map().to_destination(3).unwrap().start;
+        let instruction = program
+            .spans
+            .iter()
+            .position(|span| span.contains(&byte))
+            .unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.load(program);
+        interpreter.breakpoints = vec![instruction];
+        assert_eq!(interpreter.run(), Termination::Breakpoint);
+        assert_eq!(
+            (interpreter.current_instruction, interpreter.cells[0]),
+            (2, 1)
+        );
+        assert_eq!(interpreter.run(), Termination::Finished);
+    }
+}
@@ -0,0 +1,652 @@
+use crate::config::{Config, EofPolicy};
+use crate::ir::{Node, NodeKind};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+/// How many values the solver may try across all the input bytes before it
+/// gives up on a set of constraints.
+const SOLVER_BUDGET: usize = 1_000_000;
+
+/// A cell as `constant + sum(coefficient * input[byte])`, wrapping at the
+/// cell width. Every cell stays like this: the program can only add
+/// constants and multiples of other cells to a cell.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Value {
+    pub constant: u32,
+    /// `(input byte, coefficient)` pairs, by input byte, with no zero
+    /// coefficients.
+    pub terms: Vec<(usize, u32)>,
+}
+
+impl Value {
+    fn known(constant: u32) -> Self {
+        Value {
+            constant,
+            terms: Vec::new(),
+        }
+    }
+
+    fn input(byte: usize) -> Self {
+        Value {
+            constant: 0,
+            terms: vec![(byte, 1)],
+        }
+    }
+
+    /// The value, if it doesn't depend on the input.
+    pub fn as_known(&self) -> Option<u32> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+
+    /// `self += other * factor`
+    fn add_scaled(&mut self, other: &Value, factor: u32, mask: u32) {
+        self.constant = self
+            .constant
+            .wrapping_add(other.constant.wrapping_mul(factor))
+            & mask;
+        let mut terms = BTreeMap::new();
+        for &(byte, coefficient) in &self.terms {
+            terms.insert(byte, coefficient);
+        }
+        for &(byte, coefficient) in &other.terms {
+            let sum = terms.entry(byte).or_insert(0);
+            *sum = sum.wrapping_add(coefficient.wrapping_mul(factor)) & mask;
+        }
+        self.terms = terms
+            .into_iter()
+            .filter(|&(_, coefficient)| coefficient != 0)
+            .collect();
+    }
+
+    fn evaluate(&self, input: &[u8], mask: u32) -> u32 {
+        self.terms
+            .iter()
+            .fold(self.constant, |sum, &(byte, coefficient)| {
+                sum.wrapping_add((input[byte] as u32).wrapping_mul(coefficient))
+            })
+            & mask
+    }
+}
+
+/// `value == 0` or `value != 0`, with the value cut down to `mask`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub value: Value,
+    pub mask: u32,
+    pub zero: bool,
+}
+
+impl Constraint {
+    fn holds(&self, input: &[u8]) -> bool {
+        (self.value.evaluate(input, self.mask) == 0) == self.zero
+    }
+
+    /// The last input byte it depends on.
+    fn last_byte(&self) -> Option<usize> {
+        self.value.terms.last().map(|&(byte, _)| byte)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    Found(Vec<u8>),
+    Impossible,
+    /// Ran out of budget before finding out.
+    GaveUp,
+}
+
+/// Finds `bytes` input bytes that meet every constraint, trying bytes in
+/// order and checking each constraint as soon as its last byte is set.
+pub fn solve(constraints: &[Constraint], bytes: usize) -> Solution {
+    let mut by_last_byte = vec![Vec::new(); bytes];
+    for constraint in constraints {
+        match constraint.last_byte() {
+            Some(byte) => by_last_byte[byte].push(constraint),
+            None if !constraint.holds(&[]) => return Solution::Impossible,
+            None => {}
+        }
+    }
+    let mut input = vec![0; bytes];
+    let mut budget = SOLVER_BUDGET;
+    match assign(&by_last_byte, &mut input, 0, &mut budget) {
+        Some(true) => Solution::Found(input),
+        Some(false) => Solution::Impossible,
+        None => Solution::GaveUp,
+    }
+}
+
+/// Tries every value of `input[byte]` and the bytes after it. `None` when
+/// out of budget.
+fn assign(
+    by_last_byte: &[Vec<&Constraint>],
+    input: &mut [u8],
+    byte: usize,
+    budget: &mut usize,
+) -> Option<bool> {
+    if byte == input.len() {
+        return Some(true);
+    }
+    for value in 0..=255 {
+        *budget = budget.checked_sub(1)?;
+        input[byte] = value;
+        if by_last_byte[byte]
+            .iter()
+            .all(|constraint| constraint.holds(&input[..=byte]))
+            && assign(by_last_byte, input, byte + 1, budget)?
+        {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// What the search is looking for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// Running the op at this byte of the source.
+    Reach(usize),
+    /// Finishing after printing exactly this.
+    Output(Vec<u8>),
+}
+
+/// How far the search goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Search {
+    /// The longest input tried: past this many bytes the input always ends.
+    pub max_input: usize,
+    /// How many times a loop can go round (each time it's entered) before
+    /// the path is given up.
+    pub max_passes: usize,
+    /// How many nodes one path can run before it's given up.
+    pub max_steps: usize,
+    /// How many paths are followed before the whole search is.
+    pub max_paths: usize,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            max_input: 16,
+            max_passes: 1000,
+            max_steps: 1_000_000,
+            max_paths: 10_000,
+        }
+    }
+}
+
+/// An input that does what the goal asked for, and what the program prints
+/// on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Witness {
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub found: Option<Witness>,
+    /// How many paths through the program were followed.
+    pub paths: usize,
+    /// How many of them were given up on because of `Search`'s bounds or the
+    /// solver's budget. With none and nothing found, no input of up to
+    /// `max_input` bytes meets the goal.
+    pub cut: usize,
+}
+
+#[derive(Clone)]
+struct Frame<'a> {
+    nodes: &'a [Node],
+    next: usize,
+    /// Whether this is a loop body, which goes round again while the cell
+    /// is nonzero.
+    looped: bool,
+    passes: usize,
+}
+
+/// One way through the program, with everything that had to be true about
+/// the input to get here.
+#[derive(Clone)]
+struct Path<'a> {
+    frames: Vec<Frame<'a>>,
+    pointer: usize,
+    /// Cells that aren't zero.
+    cells: BTreeMap<usize, Value>,
+    /// How many input bytes have been read.
+    read: usize,
+    /// Whether the input has run out.
+    ended: bool,
+    output: Vec<Value>,
+    constraints: Vec<Constraint>,
+    steps: usize,
+}
+
+impl Path<'_> {
+    fn index(&self, offset: isize, tape_size: usize) -> usize {
+        (self.pointer as isize + offset).rem_euclid(tape_size as isize) as usize
+    }
+
+    fn cell(&self, index: usize) -> Value {
+        self.cells.get(&index).cloned().unwrap_or_default()
+    }
+
+    fn set(&mut self, index: usize, value: Value) {
+        if value == Value::default() {
+            self.cells.remove(&index);
+        } else {
+            self.cells.insert(index, value);
+        }
+    }
+
+    fn solve(&self) -> Solution {
+        solve(&self.constraints, self.read)
+    }
+
+    fn witness(&self, input: Vec<u8>) -> Witness {
+        let output = self
+            .output
+            .iter()
+            .map(|value| value.evaluate(&input, 0xff) as u8)
+            .collect();
+        Witness { input, output }
+    }
+}
+
+/// What a step left the path as.
+enum Step<'a> {
+    Running,
+    /// The path split in two, and this is the other half.
+    Forked(Path<'a>),
+    Finished,
+    /// Dropped, because it can't meet the goal or went past a bound.
+    Dropped,
+    Found(Witness),
+}
+
+struct Executor<'a> {
+    config: Config,
+    search: Search,
+    goal: &'a Goal,
+    /// The node `Goal::Reach` is after, and whether reaching it needs the
+    /// node's loop to go round (the byte is inside a loop the optimiser
+    /// turned into one node).
+    target: Option<(&'a Node, bool)>,
+    /// Paths dropped because of a bound or the solver's budget.
+    cut: Cell<usize>,
+}
+
+/// The innermost node whose source contains `position`, or the first one
+/// after it.
+fn find(nodes: &[Node], position: usize) -> Option<(&Node, bool)> {
+    let node = nodes.iter().find(|node| node.span.end > position)?;
+    let inside = position > node.span.start;
+    match &node.kind {
+        NodeKind::Loop(body) if inside => find(body, position).or(Some((node, true))),
+        NodeKind::MulAdd { .. } | NodeKind::Clear { .. } | NodeKind::Scan(_) => {
+            Some((node, inside))
+        }
+        _ => Some((node, false)),
+    }
+}
+
+impl<'a> Executor<'a> {
+    fn cut(&self) -> Step<'a> {
+        self.cut.set(self.cut.get() + 1);
+        Step::Dropped
+    }
+
+    /// What the solver says about `path`, as a step.
+    fn solved(&self, path: &Path<'a>) -> Step<'a> {
+        match path.solve() {
+            Solution::Found(input) => Step::Found(path.witness(input)),
+            Solution::Impossible => Step::Dropped,
+            Solution::GaveUp => self.cut(),
+        }
+    }
+
+    /// Splits `path` on whether the cell at `index` is zero. Halves the
+    /// solver shows can't happen come back as `None`.
+    fn branch(&self, path: &Path<'a>, index: usize) -> (Option<Path<'a>>, Option<Path<'a>>) {
+        let value = path.cell(index);
+        if let Some(known) = value.as_known() {
+            return if known == 0 {
+                (Some(path.clone()), None)
+            } else {
+                (None, Some(path.clone()))
+            };
+        }
+        let half = |zero| {
+            let mut half = path.clone();
+            half.constraints.push(Constraint {
+                value: value.clone(),
+                mask: self.config.cell_width.mask(),
+                zero,
+            });
+            (half.solve() != Solution::Impossible).then_some(half)
+        };
+        (half(true), half(false))
+    }
+
+    /// Carries on with whichever halves of a branch are possible, after
+    /// `on_zero` or `on_nonzero` has moved each one on.
+    fn split(
+        &self,
+        path: &mut Path<'a>,
+        (zero, nonzero): (Option<Path<'a>>, Option<Path<'a>>),
+        on_zero: impl FnOnce(&mut Path<'a>),
+        on_nonzero: impl FnOnce(&mut Path<'a>),
+    ) -> Step<'a> {
+        match (zero, nonzero) {
+            (Some(mut zero), Some(mut nonzero)) => {
+                on_zero(&mut zero);
+                on_nonzero(&mut nonzero);
+                *path = zero;
+                Step::Forked(nonzero)
+            }
+            (Some(mut zero), None) => {
+                on_zero(&mut zero);
+                *path = zero;
+                Step::Running
+            }
+            (None, Some(mut nonzero)) => {
+                on_nonzero(&mut nonzero);
+                *path = nonzero;
+                Step::Running
+            }
+            (None, None) => Step::Dropped,
+        }
+    }
+
+    /// Whether `path` is at the node `Goal::Reach` wants, and with what
+    /// input.
+    fn reached(&self, path: &Path<'a>, node: &Node) -> Option<Step<'a>> {
+        let (target, inside) = self.target?;
+        if !std::ptr::eq(target, node) {
+            return None;
+        }
+        if !inside {
+            return Some(self.solved(path));
+        }
+        let counter = match node.kind {
+            NodeKind::MulAdd { src, .. } => src,
+            NodeKind::Clear { offset } => offset,
+            _ => 0,
+        };
+        let index = path.index(counter, self.config.tape_size);
+        let (_, going_round) = self.branch(path, index);
+        Some(self.solved(&going_round?))
+    }
+
+    /// The end of a loop body: round again or out.
+    fn loop_end(&self, path: &mut Path<'a>) -> Step<'a> {
+        let (zero, mut nonzero) = self.branch(path, path.pointer);
+        let frame = path.frames.last().expect("a loop body has a frame");
+        if nonzero.is_some() && frame.passes + 1 >= self.search.max_passes {
+            nonzero = None;
+            if zero.is_none() {
+                return self.cut();
+            }
+            self.cut();
+        }
+        self.split(
+            path,
+            (zero, nonzero),
+            |path| {
+                path.frames.pop();
+            },
+            |path| {
+                let frame = path.frames.last_mut().expect("a loop body has a frame");
+                frame.passes += 1;
+                frame.next = 0;
+            },
+        )
+    }
+
+    fn step(&self, path: &mut Path<'a>) -> Step<'a> {
+        path.steps += 1;
+        if path.steps > self.search.max_steps {
+            return self.cut();
+        }
+        let tape_size = self.config.tape_size;
+        let mask = self.config.cell_width.mask();
+        let frame = path.frames.last().expect("a path always has a frame");
+        let Some(node) = frame.nodes.get(frame.next) else {
+            if frame.looped {
+                return self.loop_end(path);
+            }
+            return match self.goal {
+                Goal::Output(expected) if path.output.len() == expected.len() => self.solved(path),
+                Goal::Output(_) => Step::Dropped,
+                Goal::Reach(_) => Step::Finished,
+            };
+        };
+        if let Some(step) = self.reached(path, node) {
+            return step;
+        }
+        path.frames.last_mut().expect("checked above").next += 1;
+        match &node.kind {
+            NodeKind::Add { offset, delta } => {
+                let index = path.index(*offset, tape_size);
+                let mut value = path.cell(index);
+                value.add_scaled(&Value::known(1), *delta, mask);
+                path.set(index, value);
+            }
+            NodeKind::Move(distance) => path.pointer = path.index(*distance, tape_size),
+            NodeKind::Clear { offset } => {
+                let index = path.index(*offset, tape_size);
+                path.set(index, Value::default());
+            }
+            NodeKind::MulAdd {
+                src,
+                offset,
+                factor,
+            } => {
+                let source = path.cell(path.index(*src, tape_size));
+                let index = path.index(*offset, tape_size);
+                let mut value = path.cell(index);
+                value.add_scaled(&source, *factor, mask);
+                path.set(index, value);
+            }
+            NodeKind::Scan(step) => {
+                let branches = self.branch(path, path.pointer);
+                return self.split(
+                    path,
+                    branches,
+                    |_| {},
+                    |path| {
+                        // Stay on the scan until a zero turns up.
+                        path.frames.last_mut().expect("checked above").next -= 1;
+                        path.pointer = path.index(*step, tape_size);
+                    },
+                );
+            }
+            NodeKind::Print { offset } => {
+                let value = path.cell(path.index(*offset, tape_size));
+                if let Goal::Output(expected) = self.goal {
+                    let Some(&byte) = expected.get(path.output.len()) else {
+                        return Step::Dropped;
+                    };
+                    let mut difference = value.clone();
+                    difference.add_scaled(&Value::known(1), (byte as u32).wrapping_neg(), 0xff);
+                    path.constraints.push(Constraint {
+                        value: difference,
+                        mask: 0xff,
+                        zero: true,
+                    });
+                    if path.solve() == Solution::Impossible {
+                        return Step::Dropped;
+                    }
+                }
+                path.output.push(value);
+            }
+            NodeKind::Input { offset } => {
+                let index = path.index(*offset, tape_size);
+                let mut ended = path.clone();
+                ended.ended = true;
+                match self.config.eof {
+                    EofPolicy::Unchanged => {}
+                    EofPolicy::Zero => ended.set(index, Value::default()),
+                    EofPolicy::Max => ended.set(index, Value::known(mask)),
+                }
+                if path.ended || path.read == self.search.max_input {
+                    *path = ended;
+                    return Step::Running;
+                }
+                // Ending the input first finds the shortest inputs first.
+                let mut more = std::mem::replace(path, ended);
+                more.set(index, Value::input(more.read));
+                more.read += 1;
+                return Step::Forked(more);
+            }
+            NodeKind::Loop(body) => {
+                let branches = self.branch(path, path.pointer);
+                return self.split(
+                    path,
+                    branches,
+                    |_| {},
+                    |path| {
+                        path.frames.push(Frame {
+                            nodes: body,
+                            next: 0,
+                            looped: true,
+                            passes: 0,
+                        })
+                    },
+                );
+            }
+        }
+        Step::Running
+    }
+}
+
+/// Looks for an input that makes the program do what `goal` says, by running
+/// it with the input bytes as unknowns: `nodes` (from `ir::optimise`) are
+/// followed both ways wherever a cell that depends on the input is tested,
+/// and a path only goes on while the constraints it has picked up can still
+/// be met.
+pub fn search(nodes: &[Node], config: Config, goal: &Goal, search: Search) -> Report {
+    let executor = Executor {
+        config,
+        search,
+        goal,
+        target: match goal {
+            Goal::Reach(position) => find(nodes, *position),
+            Goal::Output(_) => None,
+        },
+        cut: Cell::new(0),
+    };
+    let mut report = Report {
+        found: None,
+        paths: 0,
+        cut: 0,
+    };
+    if matches!(goal, Goal::Reach(_)) && executor.target.is_none() {
+        return report;
+    }
+    let mut paths = vec![Path {
+        frames: vec![Frame {
+            nodes,
+            next: 0,
+            looped: false,
+            passes: 0,
+        }],
+        pointer: 0,
+        cells: BTreeMap::new(),
+        read: 0,
+        ended: false,
+        output: Vec::new(),
+        constraints: Vec::new(),
+        steps: 0,
+    }];
+    while let Some(mut path) = paths.pop() {
+        if report.paths == search.max_paths {
+            executor.cut.set(executor.cut.get() + paths.len() + 1);
+            break;
+        }
+        report.paths += 1;
+        loop {
+            match executor.step(&mut path) {
+                Step::Running => continue,
+                Step::Forked(other) => {
+                    paths.push(other);
+                    continue;
+                }
+                Step::Finished | Step::Dropped => {}
+                Step::Found(witness) => report.found = Some(witness),
+            }
+            break;
+        }
+        if report.found.is_some() {
+            break;
+        }
+    }
+    report.cut = executor.cut.get();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search, Goal, Report, Search, Witness};
+    use crate::config::Config;
+    use crate::ir::optimise;
+    use crate::ops::{Dialect, Program};
+
+    fn searched(source: &str, goal: Goal, bounds: Search) -> Report {
+        let program = Program::parse(source, Dialect::Brainfuck).unwrap();
+        search(&optimise(&program), Config::default(), &goal, bounds)
+    }
+
+    #[test]
+    fn finds_the_password_that_reaches_an_op() {
+        // Prints only if the first byte is 'A': cell 1 is a flag that gets
+        // cleared when the byte minus 65 isn't zero.
+        let source = format!(",{}>+<[[-]>-<]>[.[-]]", "-".repeat(65));
+        let print = source.find('.').unwrap();
+        let report = searched(&source, Goal::Reach(print), Search::default());
+        assert_eq!(
+            report.found,
+            Some(Witness {
+                input: b"A".to_vec(),
+                output: Vec::new(),
+            })
+        );
+        assert_eq!(report.cut, 0);
+    }
+
+    #[test]
+    fn finds_an_input_for_an_output() {
+        let report = searched(",+.,--.", Goal::Output(b"Hi".to_vec()), Search::default());
+        let witness = report.found.unwrap();
+        assert_eq!(witness.input, b"Gk");
+        assert_eq!(witness.output, b"Hi");
+    }
+
+    #[test]
+    fn says_when_nothing_reaches_the_goal() {
+        // The loop is never entered, so its `.` can't run.
+        let report = searched("[.]+.", Goal::Reach(1), Search::default());
+        assert_eq!(report.found, None);
+        assert_eq!(report.cut, 0);
+        let report = searched(",[-].", Goal::Output(b"x".to_vec()), Search::default());
+        assert_eq!(report.found, None);
+        assert_eq!(report.cut, 0);
+    }
+
+    #[test]
+    fn counts_the_paths_its_bounds_cut() {
+        let bounds = Search {
+            max_passes: 10,
+            ..Search::default()
+        };
+        // Never stops, so it only ever runs out of passes.
+        let report = searched("+[>+<]", Goal::Output(Vec::new()), bounds);
+        assert_eq!(report.found, None);
+        assert_eq!(report.cut, 1);
+        // Counting down from 11 takes one pass more than it's allowed.
+        let countdown: Vec<u8> = (1..=11).rev().collect();
+        let report = searched(",[.-]", Goal::Output(countdown.clone()), bounds);
+        assert_eq!(report.found, None);
+        assert_eq!(report.cut, 1);
+        let report = searched(",[.-]", Goal::Output(countdown), Search::default());
+        assert_eq!(report.found.unwrap().input, [11]);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::ops::OPS;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceMode {
+    Every,
+    EveryNth(u64),
+    LoopBoundaries,
+}
+
+/// One executed step. `pointer` is where the pointer was before the step and
+/// the cell values are for that cell.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceEvent {
+    pub step: u64,
+    pub instruction: usize,
+    pub span: Range<usize>,
+    pub op: OPS,
+    pub pointer: usize,
+    pub cell_before: u32,
+    pub cell_after: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<u8>,
+}
+
+/// Writes trace events as JSON Lines. Steps that read or print are always
+/// recorded, whatever the mode, so the I/O can be followed in any trace.
+pub struct Tracer {
+    pub mode: TraceMode,
+    out: Box<dyn Write>,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(mode: TraceMode, out: Box<dyn Write>) -> Self {
+        Tracer {
+            mode,
+            out,
+            error: None,
+        }
+    }
+
+    pub fn create(path: &Path, mode: TraceMode) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(mode, Box::new(BufWriter::new(file))))
+    }
+
+    pub fn wants(&self, step: u64, op: OPS) -> bool {
+        if matches!(op, OPS::Input | OPS::Print) {
+            return true;
+        }
+        match self.mode {
+            TraceMode::Every => true,
+            TraceMode::EveryNth(n) => step.is_multiple_of(n),
+            TraceMode::LoopBoundaries => matches!(op, OPS::LoopStart | OPS::LoopEnd),
+        }
+    }
+
+    /// Writes `event`. After the first write error the tracer stops writing
+    /// and `finish` reports it.
+    pub fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let written = serde_json::to_writer(&mut self.out, event)
+            .map_err(io::Error::from)
+            .and_then(|()| self.out.write_all(b"\n"));
+        if let Err(e) = written {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+}
+
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceEvent>> {
+    let mut events = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, e),
+            )
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
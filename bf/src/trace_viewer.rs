@@ -0,0 +1,139 @@
+use bf::trace::TraceEvent;
+use eframe::egui;
+use std::collections::BTreeMap;
+
+const SOURCE_CONTEXT: usize = 24;
+const TAPE_CONTEXT: usize = 5;
+
+/// Scrubs through a recorded trace. The tape and output shown are rebuilt
+/// from the events up to the current one, so a sampled trace only knows the
+/// cells its events touched.
+pub struct TraceViewer {
+    pub open: bool,
+    applied: usize,
+    cells: BTreeMap<usize, u32>,
+    events: Vec<TraceEvent>,
+    name: String,
+    output: String,
+    position: usize,
+}
+
+impl TraceViewer {
+    pub fn new(name: String, events: Vec<TraceEvent>) -> Self {
+        let mut viewer = TraceViewer {
+            open: true,
+            applied: 0,
+            cells: BTreeMap::new(),
+            events,
+            name,
+            output: String::new(),
+            position: 0,
+        };
+        viewer.seek(0);
+        viewer
+    }
+
+    fn seek(&mut self, position: usize) {
+        if self.events.is_empty() {
+            return;
+        }
+        self.position = position.min(self.events.len() - 1);
+        if self.position < self.applied {
+            self.applied = 0;
+            self.cells.clear();
+            self.output.clear();
+        }
+        for event in &self.events[self.applied..=self.position] {
+            self.cells.insert(event.pointer, event.cell_after);
+            if let Some(byte) = event.output {
+                self.output.push(byte as char);
+            }
+        }
+        self.applied = self.position + 1;
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, program_text: &str) {
+        let mut open = self.open;
+        egui::Window::new(format!("Trace: {}", self.name))
+            .open(&mut open)
+            .show(ctx, |ui| self.contents(ui, program_text));
+        self.open = open;
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui, program_text: &str) {
+        if self.events.is_empty() {
+            ui.label("The trace has no events.");
+            return;
+        }
+        let mut position = self.position;
+        ui.horizontal(|ui| {
+            if ui.button("<").clicked() {
+                position = position.saturating_sub(1);
+            }
+            ui.add(egui::Slider::new(&mut position, 0..=self.events.len() - 1).text("event"));
+            if ui.button(">").clicked() {
+                position += 1;
+            }
+        });
+        if position != self.position {
+            self.seek(position);
+        }
+        let event = &self.events[self.position];
+        ui.label(format!(
+            "step {}, instruction {}: {:?}",
+            event.step, event.instruction, event.op
+        ));
+        ui.label(format!(
+            "cell {}: {} -> {}",
+            event.pointer, event.cell_before, event.cell_after
+        ));
+        if let Some(byte) = event.input {
+            ui.label(format!("read {:?}", byte as char));
+        }
+        if let Some(byte) = event.output {
+            ui.label(format!("printed {:?}", byte as char));
+        }
+        match (
+            program_text.get(event.span.start.saturating_sub(SOURCE_CONTEXT)..event.span.start),
+            program_text.get(event.span.clone()),
+            program_text
+                .get(event.span.end..(event.span.end + SOURCE_CONTEXT).min(program_text.len())),
+        ) {
+            (Some(before), Some(token), Some(after)) => {
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    ui.monospace(before);
+                    ui.label(
+                        egui::RichText::new(token)
+                            .monospace()
+                            .background_color(egui::Color32::DARK_BLUE),
+                    );
+                    ui.monospace(after);
+                });
+            }
+            _ => {
+                ui.label("The trace doesn't match the program in the editor.");
+            }
+        }
+        ui.horizontal(|ui| {
+            for i in event.pointer.saturating_sub(TAPE_CONTEXT)..=event.pointer + TAPE_CONTEXT {
+                ui.vertical(|ui| {
+                    let label = egui::RichText::new(i.to_string());
+                    ui.label(if i == event.pointer {
+                        label.strong()
+                    } else {
+                        label
+                    });
+                    match self.cells.get(&i) {
+                        Some(value) => ui.monospace(value.to_string()),
+                        None => ui.monospace("?"),
+                    };
+                });
+            }
+        });
+        ui.label("Output so far");
+        egui::ScrollArea::vertical()
+            .max_height(120.0)
+            .show(ui, |ui| ui.monospace(&self.output));
+    }
+}
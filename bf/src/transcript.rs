@@ -0,0 +1,206 @@
+use crate::config::Config;
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ops::Program;
+use crate::snapshot::hash_string;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub step: u64,
+    pub byte: u8,
+}
+
+/// Every byte a program read during a session, with the step that read it,
+/// and the output the session produced.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub version: u32,
+    pub program_hash: String,
+    #[serde(default)]
+    pub config: Config,
+    pub inputs: Vec<TranscriptEntry>,
+    pub input_closed: bool,
+    pub output: String,
+    pub finished: bool,
+    #[serde(skip)]
+    output_start: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayMismatch {
+    Program {
+        expected: String,
+        found: String,
+    },
+    InputStep {
+        index: usize,
+        expected: u64,
+        found: u64,
+    },
+    Output {
+        position: usize,
+        expected: String,
+        found: String,
+    },
+    UnreadInput {
+        read: usize,
+        recorded: usize,
+    },
+    Stopped(Termination),
+}
+
+impl fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayMismatch::Program { expected, found } => write!(
+                f,
+                "transcript was recorded from program {} but the loaded program is {}",
+                found, expected
+            ),
+            ReplayMismatch::InputStep {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "input byte {} was read at step {} but the transcript has step {}",
+                index, found, expected
+            ),
+            ReplayMismatch::Output {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "output differs from character {}: expected {:?}, got {:?}",
+                position, expected, found
+            ),
+            ReplayMismatch::UnreadInput { read, recorded } => write!(
+                f,
+                "program read {} of the {} recorded input bytes",
+                read, recorded
+            ),
+            ReplayMismatch::Stopped(termination) => {
+                write!(f, "replay did not finish: {}", termination)
+            }
+        }
+    }
+}
+
+impl Error for ReplayMismatch {}
+
+impl Transcript {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Transcript> {
+        let transcript: Transcript = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if transcript.version != TRANSCRIPT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "transcript version {} is not supported (expected {})",
+                    transcript.version, TRANSCRIPT_VERSION
+                ),
+            ));
+        }
+        Ok(transcript)
+    }
+
+    /// Runs `program` with the recorded input and checks that every byte is
+    /// read at the same step and that the output comes out the same.
+    pub fn replay(&self, program: Program, limits: Limits) -> Result<(), ReplayMismatch> {
+        let expected = hash_string(program.hash());
+        if self.program_hash != expected {
+            return Err(ReplayMismatch::Program {
+                expected,
+                found: self.program_hash.clone(),
+            });
+        }
+        let mut interpreter = Interpreter::with_config(self.config);
+        interpreter.limits = limits;
+        interpreter.load(program);
+        interpreter.start_recording();
+        let bytes: Vec<u8> = self.inputs.iter().map(|entry| entry.byte).collect();
+        interpreter.push_input(&bytes);
+        if self.input_closed {
+            interpreter.close_input();
+        }
+        let termination = interpreter.run();
+        let replayed = interpreter.transcript().unwrap();
+        for (index, (recorded, replayed)) in self.inputs.iter().zip(&replayed.inputs).enumerate() {
+            if recorded.step != replayed.step {
+                return Err(ReplayMismatch::InputStep {
+                    index,
+                    expected: recorded.step,
+                    found: replayed.step,
+                });
+            }
+        }
+        if replayed.inputs.len() != self.inputs.len() {
+            return Err(ReplayMismatch::UnreadInput {
+                read: replayed.inputs.len(),
+                recorded: self.inputs.len(),
+            });
+        }
+        if let Some(position) = self
+            .output
+            .chars()
+            .zip(replayed.output.chars())
+            .position(|(a, b)| a != b)
+            .or_else(|| {
+                let (expected, found) =
+                    (self.output.chars().count(), replayed.output.chars().count());
+                (expected != found).then_some(expected.min(found))
+            })
+        {
+            return Err(ReplayMismatch::Output {
+                position,
+                expected: self.output.chars().skip(position).take(16).collect(),
+                found: replayed.output.chars().skip(position).take(16).collect(),
+            });
+        }
+        match termination {
+            Termination::Finished => Ok(()),
+            Termination::AwaitingInput if !self.finished => Ok(()),
+            stopped => Err(ReplayMismatch::Stopped(stopped)),
+        }
+    }
+}
+
+impl Interpreter {
+    /// Starts a transcript of the loaded program's input from here on.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Transcript {
+            version: TRANSCRIPT_VERSION,
+            program_hash: hash_string(self.program.hash()),
+            config: self.config,
+            inputs: Vec::new(),
+            input_closed: false,
+            output: String::new(),
+            finished: false,
+            output_start: self.result_text.len(),
+        });
+    }
+
+    /// The transcript recorded so far, if recording was started.
+    pub fn transcript(&self) -> Option<Transcript> {
+        let mut transcript = self.recording.clone()?;
+        transcript.input_closed = self.input_closed;
+        transcript.finished = self.is_finished();
+        transcript.output = self
+            .result_text
+            .get(transcript.output_start..)
+            .unwrap_or_default()
+            .to_owned();
+        Some(transcript)
+    }
+}
@@ -0,0 +1,433 @@
+use crate::analysis::analyse;
+use crate::config::{Config, EofPolicy};
+use crate::interpreter::{Limits, Termination};
+use crate::ir::{optimise, Node, NodeKind};
+use crate::ops::Program;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// How many taken backward jumps (or scan steps) the VM runs between looking
+/// at the clock.
+const TIMEOUT_CHECK_INTERVAL: u32 = 1 << 16;
+
+/// One VM instruction. Offsets are relative to the pointer and always smaller
+/// than the tape, jump targets are instruction indexes, and the code always
+/// ends with `Halt`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Insn {
+    Add {
+        offset: isize,
+        delta: u32,
+    },
+    Move(isize),
+    Clear {
+        offset: isize,
+    },
+    MulAdd {
+        src: isize,
+        offset: isize,
+        factor: u32,
+    },
+    Scan(isize),
+    Print {
+        offset: isize,
+    },
+    Input {
+        offset: isize,
+    },
+    /// Jumps past the matching `JumpIfNonZero` when the current cell is zero.
+    JumpIfZero(usize),
+    /// Jumps back to just after the matching `JumpIfZero` while the current
+    /// cell isn't zero.
+    JumpIfNonZero(usize),
+    Halt,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bytecode {
+    pub code: Vec<Insn>,
+    /// The source span each instruction came from.
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Flattens optimised IR into bytecode for a tape of `tape_size` cells.
+/// Offsets are reduced modulo the tape size, which doesn't change what they
+/// point at on a wrapping tape.
+pub fn compile(nodes: &[Node], tape_size: usize) -> Bytecode {
+    let mut bytecode = Bytecode::default();
+    emit(nodes, tape_size as isize, &mut bytecode);
+    let end = bytecode.spans.last().map_or(0, |span| span.end);
+    bytecode.code.push(Insn::Halt);
+    bytecode.spans.push(end..end);
+    bytecode
+}
+
+fn emit(nodes: &[Node], tape_size: isize, bytecode: &mut Bytecode) {
+    let wrap = |offset: isize| offset % tape_size;
+    for node in nodes {
+        let insn = match &node.kind {
+            NodeKind::Add { offset, delta } => Insn::Add {
+                offset: wrap(*offset),
+                delta: *delta,
+            },
+            NodeKind::Move(distance) => Insn::Move(wrap(*distance)),
+            NodeKind::Clear { offset } => Insn::Clear {
+                offset: wrap(*offset),
+            },
+            NodeKind::MulAdd {
+                src,
+                offset,
+                factor,
+            } => Insn::MulAdd {
+                src: wrap(*src),
+                offset: wrap(*offset),
+                factor: *factor,
+            },
+            NodeKind::Scan(step) => Insn::Scan(wrap(*step)),
+            NodeKind::Print { offset } => Insn::Print {
+                offset: wrap(*offset),
+            },
+            NodeKind::Input { offset } => Insn::Input {
+                offset: wrap(*offset),
+            },
+            NodeKind::Loop(body) => {
+                let start = bytecode.code.len();
+                bytecode.code.push(Insn::JumpIfZero(0));
+                bytecode.spans.push(node.span.start..node.span.start + 1);
+                emit(body, tape_size, bytecode);
+                let end = bytecode.code.len();
+                bytecode.code[start] = Insn::JumpIfZero(end + 1);
+                bytecode.code.push(Insn::JumpIfNonZero(start + 1));
+                bytecode.spans.push(node.span.end - 1..node.span.end);
+                continue;
+            }
+        };
+        bytecode.code.push(insn);
+        bytecode.spans.push(node.span.clone());
+    }
+}
+
+/// `pointer + offset` on a wrapping tape of `len` cells. Only right for
+/// `pointer < len` and `|offset| < len`, which `compile` makes sure of.
+#[inline(always)]
+fn wrap(pointer: usize, offset: isize, len: usize) -> usize {
+    let index = pointer as isize + offset;
+    if index < 0 {
+        (index + len as isize) as usize
+    } else if index as usize >= len {
+        index as usize - len
+    } else {
+        index as usize
+    }
+}
+
+/// Runs bytecode compiled from a program. Much faster than `Interpreter`, but
+/// it only counts output and time: step and tape limits, tracing and
+/// recording need the interpreter.
+pub struct Vm {
+    pub bytecode: Bytecode,
+    pub cells: Vec<u32>,
+    pub config: Config,
+    pub limits: Limits,
+    pub pointer: usize,
+    pub pc: usize,
+    pub input: Vec<u8>,
+    pub input_position: usize,
+    pub input_closed: bool,
+    pub output: Vec<u8>,
+    pub elapsed: Duration,
+    /// Skip bounds checks on the tape. Sound because every index the VM uses
+    /// goes through `wrap`, or is known to be on the tape when `bounded`.
+    pub unchecked: bool,
+    /// The pointer never wraps (`analysis::Analysis::stays_within`), so
+    /// indexes skip `wrap`. Only right when the program starts on the first
+    /// cell.
+    pub bounded: bool,
+    output_at_resume: usize,
+}
+
+impl Vm {
+    pub fn new(program: &Program, config: Config) -> Self {
+        let mut vm = Vm::with_bytecode(compile(&optimise(program), config.tape_size), config);
+        vm.bounded = analyse(program).stays_within(config.tape_size);
+        vm
+    }
+
+    /// A VM for bytecode that was compiled for `config.tape_size`.
+    pub fn with_bytecode(bytecode: Bytecode, config: Config) -> Self {
+        Vm {
+            bytecode,
+            cells: vec![0; config.tape_size],
+            config,
+            limits: Limits::default(),
+            pointer: 0,
+            pc: 0,
+            input: Vec::new(),
+            input_position: 0,
+            input_closed: false,
+            output: Vec::new(),
+            elapsed: Duration::ZERO,
+            unchecked: false,
+            bounded: false,
+            output_at_resume: 0,
+        }
+    }
+
+    /// Whether the VM can enforce `limits`.
+    pub fn supports(limits: &Limits) -> bool {
+        limits.max_steps.is_none() && limits.max_cells_touched.is_none()
+    }
+
+    /// Gives the program a fresh set of limits from where it currently is.
+    pub fn resume(&mut self) {
+        self.output_at_resume = self.output.len();
+        self.elapsed = Duration::ZERO;
+    }
+
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.bytecode.code[self.pc] == Insn::Halt
+    }
+
+    pub fn run(&mut self) -> Termination {
+        let started = Instant::now();
+        let result = match (self.unchecked, self.bounded) {
+            (true, true) => self.run_until_stopped::<true, true>(started),
+            (true, false) => self.run_until_stopped::<true, false>(started),
+            (false, true) => self.run_until_stopped::<false, true>(started),
+            (false, false) => self.run_until_stopped::<false, false>(started),
+        };
+        self.elapsed += started.elapsed();
+        result
+    }
+
+    fn run_until_stopped<const UNCHECKED: bool, const BOUNDED: bool>(
+        &mut self,
+        started: Instant,
+    ) -> Termination {
+        macro_rules! cell {
+            ($index:expr) => {
+                if UNCHECKED {
+                    // SAFETY: `wrap` keeps every index below the tape length,
+                    // and so does the analysis when `BOUNDED`.
+                    unsafe { self.cells.get_unchecked_mut($index) }
+                } else {
+                    &mut self.cells[$index]
+                }
+            };
+        }
+        let wrap = |pointer: usize, offset: isize, len: usize| {
+            if BOUNDED {
+                (pointer as isize + offset) as usize
+            } else {
+                wrap(pointer, offset, len)
+            }
+        };
+        let len = self.cells.len();
+        let mask = self.config.cell_width.mask();
+        let mut pc = self.pc;
+        let mut pointer = self.pointer;
+        let mut until_check = TIMEOUT_CHECK_INTERVAL;
+        let timed_out = |elapsed: Duration, limits: &Limits| match limits.timeout {
+            Some(timeout) if elapsed + started.elapsed() >= timeout => Some(Termination::Timeout {
+                elapsed: elapsed + started.elapsed(),
+            }),
+            _ => None,
+        };
+        let stopped = 'run: loop {
+            match self.bytecode.code[pc] {
+                Insn::Add { offset, delta } => {
+                    let cell = cell!(wrap(pointer, offset, len));
+                    *cell = cell.wrapping_add(delta) & mask;
+                }
+                Insn::Move(distance) => pointer = wrap(pointer, distance, len),
+                Insn::Clear { offset } => *cell!(wrap(pointer, offset, len)) = 0,
+                Insn::MulAdd {
+                    src,
+                    offset,
+                    factor,
+                } => {
+                    let value = *cell!(wrap(pointer, src, len));
+                    if value != 0 {
+                        let cell = cell!(wrap(pointer, offset, len));
+                        *cell = cell.wrapping_add(value.wrapping_mul(factor)) & mask;
+                    }
+                }
+                Insn::Scan(step) => {
+                    // Scans by one cell are the common case; searching the
+                    // tape as a slice lets them run many cells at a time.
+                    let found = match step {
+                        1 => {
+                            let (before, after) = self.cells.split_at(pointer);
+                            match after.iter().position(|&c| c == 0) {
+                                Some(distance) => Some(pointer + distance),
+                                None => before.iter().position(|&c| c == 0),
+                            }
+                        }
+                        -1 => {
+                            let (before, after) = self.cells.split_at(pointer + 1);
+                            match before.iter().rposition(|&c| c == 0) {
+                                Some(index) => Some(index),
+                                None => {
+                                    after.iter().rposition(|&c| c == 0).map(|i| pointer + 1 + i)
+                                }
+                            }
+                        }
+                        _ => None,
+                    };
+                    if let Some(index) = found {
+                        pointer = index;
+                    }
+                    while *cell!(pointer) != 0 {
+                        pointer = wrap(pointer, step, len);
+                        until_check -= 1;
+                        if until_check == 0 {
+                            until_check = TIMEOUT_CHECK_INTERVAL;
+                            if let Some(stopped) = timed_out(self.elapsed, &self.limits) {
+                                break 'run stopped;
+                            }
+                        }
+                    }
+                }
+                Insn::Print { offset } => {
+                    if let Some(max_output_bytes) = self.limits.max_output_bytes {
+                        if self.output.len() - self.output_at_resume >= max_output_bytes {
+                            break Termination::OutputLimit {
+                                bytes: max_output_bytes,
+                            };
+                        }
+                    }
+                    let byte = *cell!(wrap(pointer, offset, len)) as u8;
+                    self.output.push(byte);
+                }
+                Insn::Input { offset } => {
+                    let cell = cell!(wrap(pointer, offset, len));
+                    if self.input_position < self.input.len() {
+                        *cell = self.input[self.input_position] as u32;
+                        self.input_position += 1;
+                    } else if !self.input_closed {
+                        break Termination::AwaitingInput;
+                    } else {
+                        match self.config.eof {
+                            EofPolicy::Unchanged => {}
+                            EofPolicy::Zero => *cell = 0,
+                            EofPolicy::Max => *cell = mask,
+                        }
+                    }
+                }
+                Insn::JumpIfZero(target) => {
+                    if *cell!(pointer) == 0 {
+                        pc = target;
+                        continue;
+                    }
+                }
+                Insn::JumpIfNonZero(target) => {
+                    if *cell!(pointer) != 0 {
+                        pc = target;
+                        until_check -= 1;
+                        if until_check == 0 {
+                            until_check = TIMEOUT_CHECK_INTERVAL;
+                            if let Some(stopped) = timed_out(self.elapsed, &self.limits) {
+                                break stopped;
+                            }
+                        }
+                        continue;
+                    }
+                }
+                Insn::Halt => break Termination::Finished,
+            }
+            pc += 1;
+        };
+        self.pc = pc;
+        self.pointer = pointer;
+        stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vm;
+    use crate::analysis::analyse;
+    use crate::config::Config;
+    use crate::fuzz;
+    use crate::interpreter::{Interpreter, Limits, Termination};
+    use crate::ops::{Dialect, Program};
+    use crate::rng::Rng;
+
+    /// What the interpreter prints, if it finishes.
+    fn interpret(program: &Program, config: Config, input: &[u8]) -> Option<Vec<u8>> {
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.limits = Limits {
+            max_steps: Some(100_000),
+            ..Limits::default()
+        };
+        interpreter.load(program.clone());
+        interpreter.push_input(input);
+        interpreter.close_input();
+        match interpreter.run() {
+            Termination::Finished => {
+                Some(interpreter.result_text.chars().map(|c| c as u8).collect())
+            }
+            _ => None,
+        }
+    }
+
+    fn run(program: &Program, config: Config, input: &[u8], bounded: bool) -> Vec<u8> {
+        let mut vm = Vm::new(program, config);
+        vm.unchecked = true;
+        vm.bounded = bounded;
+        vm.push_input(input);
+        vm.close_input();
+        assert_eq!(vm.run(), Termination::Finished);
+        vm.output
+    }
+
+    /// The unchecked VM prints what the interpreter does on random programs
+    /// small enough to walk off an 8-cell tape, wrapping the pointer every
+    /// time and skipping the wrap wherever the analysis says it can.
+    #[test]
+    fn unchecked_vm_agrees_with_the_interpreter() {
+        let mut rng = Rng::new(31);
+        let mut bounded = 0;
+        for _ in 0..5000 {
+            let length = 1 + rng.below(40);
+            let source = fuzz::program(&mut rng, length);
+            let program = Program::parse(&source, Dialect::Brainfuck).unwrap();
+            let config = Config {
+                tape_size: 8,
+                ..fuzz::config(&mut rng)
+            };
+            let input: Vec<u8> = (0..rng.below(4)).map(|_| rng.byte()).collect();
+            let Some(expected) = interpret(&program, config, &input) else {
+                continue;
+            };
+            assert_eq!(
+                run(&program, config, &input, false),
+                expected,
+                "{:?}",
+                source
+            );
+            if analyse(&program).stays_within(config.tape_size) {
+                bounded += 1;
+                assert_eq!(
+                    run(&program, config, &input, true),
+                    expected,
+                    "{:?}",
+                    source
+                );
+            }
+        }
+        assert!(
+            bounded > 100,
+            "only {} programs stayed on the tape",
+            bounded
+        );
+    }
+}
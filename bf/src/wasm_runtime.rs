@@ -0,0 +1,170 @@
+use crate::codegen::wasm::{generate, Flavour};
+use crate::config::Config;
+use crate::golden::{diff, Failure, GoldenTest};
+use crate::interpreter::{Interpreter, Limits, Termination};
+use crate::ir::optimise;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+/// How much fuel one run gets, roughly one unit per instruction, so that a
+/// module stuck in a loop traps instead of hanging the tests.
+pub const FUEL: u64 = 20_000_000_000;
+
+const WASI: &str = "wasi_snapshot_preview1";
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+
+struct Host {
+    input: Vec<u8>,
+    position: usize,
+    output: Vec<u8>,
+}
+
+fn word(memory: &[u8], address: usize) -> Option<usize> {
+    let bytes = memory.get(address..address + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+}
+
+/// Copies between the host buffers and the iovecs at `iovs`, `read` telling
+/// which way, and stores the byte count at `done`. Returns a WASI errno.
+fn transfer(memory: &mut [u8], host: &mut Host, read: bool, iovs: i32, len: i32, done: i32) -> i32 {
+    let mut total = 0;
+    for i in 0..len as usize {
+        let iov = iovs as usize + 8 * i;
+        let (Some(buf), Some(buf_len)) = (word(memory, iov), word(memory, iov + 4)) else {
+            return ERRNO_FAULT;
+        };
+        let Some(buf) = memory.get_mut(buf..buf + buf_len) else {
+            return ERRNO_FAULT;
+        };
+        if read {
+            let available = &host.input[host.position..];
+            let n = buf.len().min(available.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            host.position += n;
+            total += n;
+        } else {
+            host.output.extend_from_slice(buf);
+            total += buf.len();
+        }
+    }
+    match memory.get_mut(done as usize..done as usize + 4) {
+        Some(done) => {
+            done.copy_from_slice(&(total as u32).to_le_bytes());
+            0
+        }
+        None => ERRNO_FAULT,
+    }
+}
+
+fn define(linker: &mut Linker<Host>, flavour: Flavour) -> Result<(), wasmi::Error> {
+    match flavour {
+        Flavour::Imports => {
+            linker.func_wrap("env", "read", |mut caller: Caller<'_, Host>| -> i32 {
+                let host = caller.data_mut();
+                match host.input.get(host.position) {
+                    Some(&byte) => {
+                        host.position += 1;
+                        byte.into()
+                    }
+                    None => -1,
+                }
+            })?;
+            linker.func_wrap("env", "write", |mut caller: Caller<'_, Host>, byte: i32| {
+                caller.data_mut().output.push(byte as u8);
+            })?;
+        }
+        Flavour::Wasi => {
+            for (name, fd, read) in [("fd_read", 0, true), ("fd_write", 1, false)] {
+                linker.func_wrap(
+                    WASI,
+                    name,
+                    move |mut caller: Caller<'_, Host>,
+                          file: i32,
+                          iovs: i32,
+                          len: i32,
+                          done: i32|
+                          -> i32 {
+                        let Some(memory) =
+                            caller.get_export("memory").and_then(Extern::into_memory)
+                        else {
+                            return ERRNO_FAULT;
+                        };
+                        if file != fd {
+                            return ERRNO_BADF;
+                        }
+                        let (memory, host) = memory.data_and_store_mut(&mut caller);
+                        transfer(memory, host, read, iovs, len, done)
+                    },
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a module made by `codegen::wasm::generate` on all of `input` in an
+/// embedded runtime and returns what it wrote.
+pub fn run(module: &[u8], flavour: Flavour, input: &[u8]) -> Result<Vec<u8>, wasmi::Error> {
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, module)?;
+    let mut store = Store::new(
+        &engine,
+        Host {
+            input: input.to_vec(),
+            position: 0,
+            output: Vec::new(),
+        },
+    );
+    store.set_fuel(FUEL)?;
+    let mut linker = Linker::new(&engine);
+    define(&mut linker, flavour)?;
+    let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+    let entry = match flavour {
+        Flavour::Imports => "run",
+        Flavour::Wasi => "_start",
+    };
+    instance
+        .get_typed_func::<(), ()>(&store, entry)?
+        .call(&mut store, ())?;
+    Ok(store.into_data().output)
+}
+
+fn interpret(
+    program: &crate::ops::Program,
+    config: Config,
+    input: &[u8],
+    limits: Limits,
+) -> Result<Vec<u8>, Failure> {
+    let mut interpreter = Interpreter::with_config(config);
+    interpreter.limits = limits;
+    interpreter.load(program.clone());
+    interpreter.push_input(input);
+    interpreter.close_input();
+    match interpreter.run() {
+        Termination::Finished => Ok(interpreter.result_text.chars().map(|c| c as u8).collect()),
+        stopped => Err(Failure::Stopped(stopped)),
+    }
+}
+
+/// Compiles `test` to both kinds of module, runs them and checks that they
+/// print what the interpreter does (and what the `.out` file says). Limits
+/// from the program's headers win over `limits`, which only bound the
+/// interpreter; the modules get `FUEL`.
+pub fn check(test: &GoldenTest, limits: Limits) -> Result<(), Failure> {
+    let (program, headers, input) = test.load()?;
+    let expected = interpret(&program, headers.config, &input, headers.limits.or(limits))?;
+    test.check(&expected)?;
+    let nodes = optimise(&program);
+    for flavour in [Flavour::Imports, Flavour::Wasi] {
+        let module = generate(&nodes, headers.config, flavour)
+            .ok_or_else(|| Failure::Setup("the tape is too big for WebAssembly".to_owned()))?;
+        let actual = run(&module, flavour, &input)
+            .map_err(|e| Failure::Setup(format!("{:?} module: {}", flavour, e)))?;
+        if actual != expected {
+            return Err(Failure::Output(diff(&expected, &actual)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use bf::fuzz::{self, Verdict};
+use bf::rng::Rng;
+
+/// Every engine in this build agrees with the interpreter on a fixed batch
+/// of random programs, so a failure here always comes back the same way.
+#[test]
+fn engines_agree_with_the_interpreter() {
+    let engines = fuzz::engines();
+    let mut rng = Rng::new(20221019);
+    for _ in 0..300 {
+        let case = fuzz::case(&mut rng, 40);
+        if let Verdict::Disagreed(disagreement) = fuzz::check(&case, &engines, 100_000) {
+            let disagreement = fuzz::minimise(*disagreement, 100_000);
+            panic!(
+                "{} disagrees with the interpreter on {}: expected \"{}\", got {:?}",
+                disagreement.engine,
+                disagreement.case,
+                disagreement.expected.escape_ascii(),
+                disagreement.actual
+            );
+        }
+    }
+}
@@ -0,0 +1,29 @@
+#![cfg(feature = "wasm-runtime")]
+
+use bf::golden::discover;
+use bf::interpreter::Limits;
+use bf::wasm_runtime::check;
+use std::path::Path;
+
+/// Compiles every program in `dir` to both kinds of module, runs them in the
+/// embedded runtime and compares what they print with the `.out` files.
+fn check_all(dir: &str) {
+    let tests = discover(&Path::new(env!("CARGO_MANIFEST_DIR")).join(dir)).unwrap();
+    assert!(!tests.is_empty(), "no programs in {}", dir);
+    for test in tests {
+        assert!(test.expected.is_some(), "{} has no .out file", test.name);
+        if let Err(failure) = check(&test, Limits::default()) {
+            panic!("{}: {}", test.name, failure);
+        }
+    }
+}
+
+#[test]
+fn benches_run_the_same_as_webassembly() {
+    check_all("benches");
+}
+
+#[test]
+fn golden_programs_run_the_same_as_webassembly() {
+    check_all("golden");
+}
@@ -0,0 +1,166 @@
+use crate::discover::{discover, Filter};
+use crate::files::{Batch, FileResult, IfExists, Settings, Status};
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const USAGE: &str = "usage:
+    bf2bf-cli [options] <file or folder>...
+                        convert each file to the other dialect, .bf2 to Brainfuck
+                        and anything else to BrainFNORD2, and the files the filter
+                        lets through in each folder and the folders in it
+options:
+    --include PATTERNS  convert the files in folders that match one of these
+                        comma-separated patterns, *.bf, *.b by default; without a /
+                        they match the file name, with one the path from the folder
+                        you gave, and ** matches across folders
+    --exclude PATTERNS  but not the ones that match these, or anything in a folder
+                        that does
+    --out DIR           write the outputs to DIR instead of next to the inputs, in
+                        the same folders under it as they are under the folder
+                        you gave
+    --name TEMPLATE     name the outputs after TEMPLATE, {stem}.{dialect_ext} by
+                        default, with {stem}, {ext} and {dialect_ext} (bf or bf2)
+                        filled in from the input
+    --if-exists ask|skip|overwrite|backup
+                        what to do when an output is already there (overwrite by
+                        default; backup moves the old one to <output>.bak first,
+                        or .bak2 and on if that's taken)
+    --comments          keep the comments
+    --keep-lines        keep the line breaks
+    --separator none|space|newline
+                        what goes between two commands
+    --wrap N            start a new line before a command would go past column N
+    --indent N          put loop brackets on lines of their own, and indent what's
+                        between them by N spaces a level
+    --source-maps       write <output>.map next to each output";
+
+pub fn main(args: &[String]) -> i32 {
+    match convert_all(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("bf2bf-cli: {}", e);
+            1
+        }
+    }
+}
+
+fn value<T>(args: &mut std::slice::Iter<String>, flag: &str) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let text = args
+        .next()
+        .ok_or_else(|| format!("{} needs a value", flag))?;
+    text.parse()
+        .map_err(|e| format!("bad value {:?} for {}: {}", text, flag, e).into())
+}
+
+fn convert_all(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    let mut filter = Filter::default();
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "help" | "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(0);
+            }
+            "--include" => filter.include = value(&mut args, arg)?,
+            "--exclude" => filter.exclude = value(&mut args, arg)?,
+            "--out" => settings.output_dir = Some(value::<String>(&mut args, arg)?.into()),
+            "--name" => settings.template = value(&mut args, arg)?,
+            "--if-exists" => settings.if_exists = value(&mut args, arg)?,
+            "--comments" => settings.options.comments = true,
+            "--keep-lines" => settings.options.keep_lines = true,
+            "--separator" => settings.options.separator = value(&mut args, arg)?,
+            "--wrap" => settings.options.max_width = Some(value(&mut args, arg)?),
+            "--indent" => settings.options.indent = Some(value(&mut args, arg)?),
+            "--source-maps" => settings.source_maps = true,
+            _ if arg.starts_with("--") => {
+                return Err(format!("unexpected argument {:?}\n{}", arg, USAGE).into())
+            }
+            _ => inputs.push(arg.clone()),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(USAGE.into());
+    }
+    let mut failed = false;
+    let mut batch = Batch::new(settings);
+    for input in inputs {
+        let input = PathBuf::from(input);
+        let jobs = if input.is_dir() {
+            let (files, problems) = discover(&input, &filter);
+            problems.iter().for_each(report);
+            if files.is_empty() {
+                report(&FileResult::skipped(&input, "nothing in it matches"));
+            }
+            files
+                .into_iter()
+                .map(|file| (file, input.clone()))
+                .collect()
+        } else {
+            let base = input.parent().unwrap_or(Path::new("")).to_owned();
+            vec![(input, base)]
+        };
+        for (input, base) in jobs {
+            let mut result = batch.convert(&input, &base);
+            if result.status == Status::Exists {
+                result = ask(&result, &batch.settings)?;
+            }
+            failed |= matches!(result.status, Status::Failed(_));
+            report(&result);
+        }
+    }
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// Asks what to do about an output that's already there, and does it.
+/// Without an answer it's left alone.
+fn ask(result: &FileResult, settings: &Settings) -> Result<FileResult, Box<dyn Error>> {
+    let output = result.output.as_deref().unwrap_or(Path::new(""));
+    loop {
+        eprint!(
+            "{} is already there: overwrite it, keep a backup or skip it? [o/b/S] ",
+            output.display()
+        );
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        let if_exists = match answer.trim() {
+            "o" | "overwrite" => IfExists::Overwrite,
+            "b" | "backup" => IfExists::Backup,
+            "" | "s" | "skip" => IfExists::Skip,
+            _ => continue,
+        };
+        return Ok(result.retry(&Settings {
+            if_exists,
+            ..settings.clone()
+        }));
+    }
+}
+
+fn report(result: &FileResult) {
+    let input = result.input.display();
+    match &result.status {
+        Status::Converted => {
+            let output = result.output.as_deref().unwrap_or(Path::new(""));
+            println!(
+                "{} -> {} ({} commands, {} -> {} bytes)",
+                input,
+                output.display(),
+                result.tokens,
+                result.input_size.unwrap_or(0),
+                result.output_size.unwrap_or(0)
+            );
+        }
+        Status::Exists => println!("{}: skipped: the output is already there", input),
+        Status::Skipped(reason) => println!("{}: skipped: {}", input, reason),
+        Status::Failed(reason) => eprintln!("bf2bf-cli: {}: {}", input, reason),
+    }
+}
@@ -0,0 +1,360 @@
+use bf::config::parse_headers;
+use bf::ops::{tokenize, Dialect, OPS};
+use bf::sourcemap::TokenSpans;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The characters that start a command in either dialect. In comments they
+/// get swapped for their fullwidth forms (`＋`, `ｈ`), which look much the
+/// same but aren't commands.
+const ESCAPED: &str = "+-<>.,[]fkpc25he";
+
+/// From an ASCII character to its fullwidth form.
+const FULLWIDTH_OFFSET: u32 = 0xfee0;
+
+/// What goes between two commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Separator {
+    #[default]
+    None,
+    Space,
+    Newline,
+}
+
+impl FromStr for Separator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Separator::None),
+            "space" => Ok(Separator::Space),
+            "newline" => Ok(Separator::Newline),
+            _ => Err("expected none, space or newline".to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Carry the text between commands across, escaped so it stays a comment.
+    pub comments: bool,
+    /// Between two commands with no comment between them.
+    pub separator: Separator,
+    /// Start a new line rather than let a command go past this column.
+    pub max_width: Option<usize>,
+    /// Put loop brackets on lines of their own and indent what's between
+    /// them by this many spaces a level.
+    pub indent: Option<usize>,
+    /// Start a new line wherever the source has one between two commands,
+    /// which the comments do anyway.
+    pub keep_lines: bool,
+}
+
+impl Options {
+    /// Whether the layout breaks lines of its own, in which case the file
+    /// ends with a newline too.
+    fn breaks_lines(&self) -> bool {
+        self.separator == Separator::Newline || self.max_width.is_some() || self.indent.is_some()
+    }
+}
+
+/// A converted program, and where each of its tokens came from.
+pub struct Conversion {
+    pub text: String,
+    pub tokens: Vec<TokenSpans>,
+}
+
+/// Lays out the converted commands and comments.
+struct Writer {
+    output: String,
+    tokens: Vec<TokenSpans>,
+    options: Options,
+    /// How many characters the current line has so far.
+    column: usize,
+    depth: usize,
+    after_bracket: bool,
+}
+
+impl Writer {
+    fn newline(&mut self) {
+        self.output.push('\n');
+        self.column = 0;
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.output += text;
+        match text.rfind('\n') {
+            Some(end) => self.column = text[end + 1..].chars().count(),
+            None => self.column += text.chars().count(),
+        }
+    }
+
+    /// Writes what the options keep of the comment between two commands,
+    /// and says whether any of it was text.
+    fn between(&mut self, comment: &str, to: Dialect) -> bool {
+        if self.options.comments {
+            self.comment(&escape(comment, to));
+            !comment.is_empty()
+        } else {
+            if self.options.keep_lines {
+                for _ in comment.matches('\n') {
+                    self.newline();
+                }
+            }
+            false
+        }
+    }
+
+    fn token(&mut self, op: OPS, token: &str, source: Range<usize>, after_comment: bool) {
+        let bracket = matches!(op, OPS::LoopStart | OPS::LoopEnd);
+        if op == OPS::LoopEnd {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        if self.column > 0 {
+            let separator = if self.options.indent.is_some() && (bracket || self.after_bracket) {
+                "\n"
+            } else if after_comment {
+                ""
+            } else {
+                match self.options.separator {
+                    Separator::None => "",
+                    Separator::Space => " ",
+                    Separator::Newline => "\n",
+                }
+            };
+            let too_wide = self
+                .options
+                .max_width
+                .is_some_and(|width| self.column + separator.len() + token.len() > width);
+            if separator == "\n" || too_wide {
+                self.newline();
+            } else {
+                self.comment(separator);
+            }
+        }
+        if self.column == 0 {
+            let indent = self.options.indent.unwrap_or(0) * self.depth;
+            self.comment(&" ".repeat(indent));
+        }
+        let start = self.output.len();
+        self.comment(token);
+        self.tokens.push(TokenSpans {
+            source,
+            destination: start..self.output.len(),
+        });
+        if op == OPS::LoopStart {
+            self.depth += 1;
+        }
+        self.after_bracket = bracket;
+    }
+}
+
+/// BrainFNORD2 for .bf2 files, Brainfuck for everything else.
+pub fn dialect_for(path: &Path) -> Dialect {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("bf2") => Dialect::BrainFnord2,
+        _ => Dialect::Brainfuck,
+    }
+}
+
+/// The dialect a file in `dialect` gets converted to.
+pub fn other(dialect: Dialect) -> Dialect {
+    match dialect {
+        Dialect::Brainfuck => Dialect::BrainFnord2,
+        Dialect::BrainFnord2 => Dialect::Brainfuck,
+    }
+}
+
+/// Rewrites `source` from `from` into the other dialect. Header lines are
+/// copied as they are, since they set up the machine the program runs on.
+pub fn convert(source: &str, from: Dialect, options: Options) -> Conversion {
+    let to = other(from);
+    let header_len = parse_headers(source).map_or(0, |headers| headers.header_len);
+    let (header, body) = source.split_at(header_len);
+    let (ops, spans) = tokenize(body, from);
+    let mut writer = Writer {
+        output: header.to_owned(),
+        tokens: Vec::new(),
+        options,
+        column: 0,
+        depth: 0,
+        after_bracket: false,
+    };
+    let mut comment_start = 0;
+    for (op, span) in ops.into_iter().zip(spans) {
+        let after_comment = writer.between(&body[comment_start..span.start], to);
+        let source_span = header_len + span.start..header_len + span.end;
+        writer.token(op, op.token(to), source_span, after_comment);
+        comment_start = span.end;
+    }
+    writer.between(&body[comment_start..], to);
+    if options.breaks_lines() && writer.column > 0 {
+        writer.newline();
+    }
+    Conversion {
+        text: writer.output,
+        tokens: writer.tokens,
+    }
+}
+
+/// Makes `comment` safe to put in a `dialect` program: undoes any escaping
+/// done for the other dialect, then breaks up every command in it by
+/// swapping its first character for the fullwidth form. No token contains
+/// the start of another, so that leaves none behind, and the fullwidth
+/// characters can't join with the commands around the comment to make one.
+/// A fullwidth character that was in the comment to begin with is taken
+/// for an escaped one too, so it only stays fullwidth if it starts a
+/// command.
+fn escape(comment: &str, dialect: Dialect) -> String {
+    let unescaped: String = comment
+        .chars()
+        .map(|c| {
+            match (c as u32)
+                .checked_sub(FULLWIDTH_OFFSET)
+                .and_then(char::from_u32)
+            {
+                Some(ascii) if ESCAPED.contains(ascii) => ascii,
+                _ => c,
+            }
+        })
+        .collect();
+    let (_, spans) = tokenize(&unescaped, dialect);
+    let mut escaped = String::with_capacity(unescaped.len());
+    let mut rest = 0;
+    for span in spans {
+        escaped += &unescaped[rest..span.start];
+        let first = unescaped[span.start..]
+            .chars()
+            .next()
+            .expect("tokens aren't empty");
+        escaped
+            .push(char::from_u32(first as u32 + FULLWIDTH_OFFSET).expect("fullwidth forms exist"));
+        rest = span.start + first.len_utf8();
+    }
+    escaped += &unescaped[rest..];
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, escape, other, Options, Separator};
+    use bf::ops::{tokenize, Dialect};
+
+    fn with_comments() -> Options {
+        Options {
+            comments: true,
+            ..Options::default()
+        }
+    }
+
+    /// Converts `source` to the other dialect and back, checking the ops
+    /// survive each way, and returns the converted text.
+    fn round_trip(source: &str, from: Dialect) -> String {
+        let converted = convert(source, from, with_comments()).text;
+        let (ops, _) = tokenize(source, from);
+        assert_eq!(tokenize(&converted, other(from)).0, ops);
+        let back = convert(&converted, other(from), with_comments()).text;
+        assert_eq!(back, source);
+        converted
+    }
+
+    #[test]
+    fn escapes_brainfnord2_commands_in_brainfuck_comments() {
+        let converted = round_trip("+ 5 23 hail chaos hhail\n[-]", Dialect::Brainfuck);
+        assert_eq!(converted, "5 ５ ２3 ｈail ｃhaos hｈail\n23haileris");
+    }
+
+    #[test]
+    fn escapes_brainfuck_commands_in_brainfnord2_comments() {
+        let converted = round_trip("5 +-<>.,[] hail", Dialect::BrainFnord2);
+        assert_eq!(converted, "+ ＋－＜＞．，［］ -");
+    }
+
+    #[test]
+    fn reads_fullwidth_characters_as_escaped() {
+        // Only what would be a command stays fullwidth.
+        assert_eq!(escape("ｈi ｈail", Dialect::BrainFnord2), "hi ｈail");
+        assert_eq!(escape("ｈi ＋", Dialect::Brainfuck), "hi ＋");
+        let converted = convert("+ ｈi", Dialect::Brainfuck, with_comments()).text;
+        assert_eq!(converted, "5 hi");
+    }
+
+    fn layout(source: &str, options: Options) -> String {
+        convert(source, Dialect::Brainfuck, options).text
+    }
+
+    #[test]
+    fn separates_commands() {
+        let cases = [
+            (Separator::None, "523hailkallisti5fnorderispineal"),
+            (Separator::Space, "5 23 hail kallisti 5 fnord eris pineal"),
+            (
+                Separator::Newline,
+                "5\n23\nhail\nkallisti\n5\nfnord\neris\npineal\n",
+            ),
+        ];
+        for (separator, expected) in cases {
+            let options = Options {
+                separator,
+                ..Options::default()
+            };
+            assert_eq!(layout("+[->+<].", options), expected);
+        }
+    }
+
+    #[test]
+    fn wraps() {
+        let options = Options {
+            separator: Separator::Space,
+            max_width: Some(12),
+            ..Options::default()
+        };
+        assert_eq!(
+            layout("+[->+<].", options),
+            "5 23 hail\nkallisti 5\nfnord eris\npineal\n"
+        );
+        // A command too long for a line of its own still gets just the one.
+        let options = Options {
+            max_width: Some(3),
+            ..Options::default()
+        };
+        assert_eq!(
+            layout("+[->+<].", options),
+            "523\nhail\nkallisti\n5\nfnord\neris\npineal\n"
+        );
+    }
+
+    #[test]
+    fn indents_loops() {
+        let options = Options {
+            indent: Some(2),
+            ..Options::default()
+        };
+        assert_eq!(
+            layout("+[->+<[-]].", options),
+            "5\n23\n  hailkallisti5fnord\n  23\n    hail\n  eris\neris\npineal\n"
+        );
+    }
+
+    #[test]
+    fn keeps_lines() {
+        let options = Options {
+            keep_lines: true,
+            ..Options::default()
+        };
+        assert_eq!(layout("+\n+ x\n\n-", options), "5\n5\n\nhail");
+    }
+
+    #[test]
+    fn indents_around_comments() {
+        let options = Options {
+            indent: Some(2),
+            ..with_comments()
+        };
+        assert_eq!(
+            layout("+[ loop\n- x]", options),
+            "5\n23 loop\n  hail x\neris\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::files::FileResult;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which files in a folder get converted, as comma-separated glob patterns.
+/// A pattern with no `/` in it is matched against file names, anything else
+/// against the path from the folder, where `**` can cross folders too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    pub include: String,
+    /// Folders that match aren't looked in at all.
+    pub exclude: String,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            include: "*.bf, *.b".to_owned(),
+            exclude: String::new(),
+        }
+    }
+}
+
+impl Filter {
+    fn matches(patterns: &str, relative: &Path) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or("");
+        patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .any(|pattern| {
+                let text = if pattern.contains('/') { &path } else { name };
+                let pattern: Vec<char> = pattern.chars().collect();
+                let text: Vec<char> = text.chars().collect();
+                glob(&pattern, &text)
+            })
+    }
+
+    fn excludes(&self, relative: &Path) -> bool {
+        Filter::matches(&self.exclude, relative)
+    }
+
+    fn includes(&self, relative: &Path) -> bool {
+        Filter::matches(&self.include, relative) && !self.excludes(relative)
+    }
+}
+
+/// Whether `text` matches `pattern`, where `?` is any character but `/`, `*`
+/// any run of them and `**` any run of anything.
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            let rest = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|skip| glob(rest, &text[skip..]))
+        }
+        ['*', rest @ ..] => {
+            let run = text.iter().take_while(|&&c| c != '/').count();
+            (0..=run).any(|skip| glob(rest, &text[skip..]))
+        }
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+/// Every file under `folder` that `filter` lets through, in order, and a
+/// skipped result for each folder that couldn't be looked in. Links to
+/// folders aren't followed, so a link back up can't go round forever.
+pub fn discover(folder: &Path, filter: &Filter) -> (Vec<PathBuf>, Vec<FileResult>) {
+    let mut files = Vec::new();
+    let mut problems = Vec::new();
+    let mut folders = vec![folder.to_owned()];
+    while let Some(current) = folders.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) => {
+                problems.push(FileResult::skipped(
+                    &current,
+                    &format!("can't look in it: {}", e),
+                ));
+                continue;
+            }
+        };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut subfolders = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            let relative = path.strip_prefix(folder).unwrap_or(&path);
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            if kind.is_dir() {
+                if !filter.excludes(relative) {
+                    subfolders.push(path);
+                }
+            } else if (kind.is_file() || path.is_file()) && filter.includes(relative) {
+                files.push(path);
+            }
+        }
+        folders.extend(subfolders.into_iter().rev());
+    }
+    (files, problems)
+}
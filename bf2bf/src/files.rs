@@ -0,0 +1,455 @@
+use crate::convert::{convert, dialect_for, other, Options};
+use bf::ops::Dialect;
+use bf::sourcemap::SourceMap;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Names outputs after their inputs, with the other dialect's extension.
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{dialect_ext}";
+
+/// What to do when an output is already there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IfExists {
+    /// Leave the file as `Status::Exists` until someone decides.
+    Ask,
+    Skip,
+    #[default]
+    Overwrite,
+    /// Move the old one to `<output>.bak`, or `.bak2` and on if that's
+    /// taken, once the new one is ready to write.
+    Backup,
+}
+
+impl FromStr for IfExists {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(IfExists::Ask),
+            "skip" => Ok(IfExists::Skip),
+            "overwrite" => Ok(IfExists::Overwrite),
+            "backup" => Ok(IfExists::Backup),
+            _ => Err("expected ask, skip, overwrite or backup".to_owned()),
+        }
+    }
+}
+
+/// Everything about how files get converted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub options: Options,
+    /// Write `<output>.map` next to each output.
+    pub source_maps: bool,
+    /// Where outputs go, in the same folders under it as their inputs are
+    /// under the folder they were found in. Next to the inputs if `None`.
+    pub output_dir: Option<PathBuf>,
+    /// The output's file name, with `{stem}`, `{ext}` and `{dialect_ext}`
+    /// (`bf` or `bf2`) filled in from the input.
+    pub template: String,
+    pub if_exists: IfExists,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            options: Options::default(),
+            source_maps: false,
+            output_dir: None,
+            template: DEFAULT_TEMPLATE.to_owned(),
+            if_exists: IfExists::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Converted,
+    /// The output is already there, and the settings say to ask first.
+    Exists,
+    /// Left alone, and why.
+    Skipped(String),
+    /// Why it went wrong.
+    Failed(String),
+}
+
+/// What happened to one file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileResult {
+    pub input: PathBuf,
+    /// The folder `input` was found in, which says where under the output
+    /// folder its output goes.
+    pub base: PathBuf,
+    pub output: Option<PathBuf>,
+    pub status: Status,
+    /// In bytes, once the file has been read.
+    pub input_size: Option<usize>,
+    /// In bytes, once the output has been written.
+    pub output_size: Option<usize>,
+    /// How many commands it has.
+    pub tokens: usize,
+}
+
+impl FileResult {
+    pub fn skipped(input: &Path, reason: &str) -> FileResult {
+        FileResult {
+            input: input.to_owned(),
+            base: input.parent().unwrap_or(Path::new("")).to_owned(),
+            output: None,
+            status: Status::Skipped(reason.to_owned()),
+            input_size: None,
+            output_size: None,
+            tokens: 0,
+        }
+    }
+
+    pub fn failed(input: &Path, reason: &str) -> FileResult {
+        FileResult {
+            status: Status::Failed(reason.to_owned()),
+            ..FileResult::skipped(input, "")
+        }
+    }
+
+    /// Converts the same file again, with different settings.
+    pub fn retry(&self, settings: &Settings) -> FileResult {
+        convert_file(&self.input, &self.base, settings)
+    }
+}
+
+/// Converts `input`, found in the folder `base`, into the other dialect,
+/// and says how that went rather than stopping at the first problem.
+pub fn convert_file(input: &Path, base: &Path, settings: &Settings) -> FileResult {
+    if input.is_dir() {
+        return FileResult::skipped(input, "it's a folder");
+    }
+    let mut result = FileResult {
+        base: base.to_owned(),
+        status: Status::Converted,
+        ..FileResult::skipped(input, "")
+    };
+    if let Err(e) = write_converted(input, settings, &mut result) {
+        result.status = Status::Failed(e.to_string());
+    }
+    result
+}
+
+/// Converts a list of files, one after another, where two inputs can end up
+/// with the same output: `prog.bf` and `prog.b` both make `prog.bf2`.
+#[derive(Clone, Default)]
+pub struct Batch {
+    pub settings: Settings,
+    /// Every output so far, and the input it came from, shared with the
+    /// batches made by `with_settings`.
+    outputs: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+}
+
+impl Batch {
+    pub fn new(settings: Settings) -> Batch {
+        Batch {
+            settings,
+            outputs: Arc::default(),
+        }
+    }
+
+    /// A batch with other settings that remembers the same outputs, so a
+    /// file converted again still can't write over another one's.
+    pub fn with_settings(&self, settings: Settings) -> Batch {
+        Batch {
+            settings,
+            outputs: self.outputs.clone(),
+        }
+    }
+
+    /// Converts `input`, found in `base`, unless another file in the batch
+    /// has the same output, in which case it fails rather than write over it.
+    pub fn convert(&mut self, input: &Path, base: &Path) -> FileResult {
+        if let Ok(output) = output_path(input, base, &self.settings) {
+            let mut outputs = self.outputs.lock().expect("no thread panics holding it");
+            match outputs.get(&output) {
+                Some(earlier) if earlier != input => {
+                    return FileResult {
+                        output: Some(output.clone()),
+                        ..FileResult::failed(
+                            input,
+                            &format!("{} has the same output", earlier.display()),
+                        )
+                    };
+                }
+                Some(_) => {}
+                None => {
+                    outputs.insert(output, input.to_owned());
+                }
+            }
+        }
+        convert_file(input, base, &self.settings)
+    }
+}
+
+/// Where the output for `input`, found in `base`, goes.
+fn output_path(input: &Path, base: &Path, settings: &Settings) -> Result<PathBuf, String> {
+    let name = file_name(&settings.template, input)?;
+    let folder = input.parent().unwrap_or(Path::new(""));
+    Ok(match &settings.output_dir {
+        Some(output_dir) => output_dir
+            .join(folder.strip_prefix(base).unwrap_or(Path::new("")))
+            .join(name),
+        None => folder.join(name),
+    })
+}
+
+/// Fills in `template` for `input`.
+fn file_name(template: &str, input: &Path) -> Result<String, String> {
+    let part = |part: Option<&std::ffi::OsStr>| {
+        part.map_or(String::new(), |part| part.to_string_lossy().into_owned())
+    };
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name += &rest[..start];
+        let end = rest[start..]
+            .find('}')
+            .ok_or("the naming template has a { without a }")?;
+        name += &match &rest[start + 1..start + end] {
+            "stem" => part(input.file_stem()),
+            "ext" => part(input.extension()),
+            "dialect_ext" => match other(dialect_for(input)) {
+                Dialect::Brainfuck => "bf".to_owned(),
+                Dialect::BrainFnord2 => "bf2".to_owned(),
+            },
+            key => return Err(format!("the naming template has no {{{}}}", key)),
+        };
+        rest = &rest[start + end + 1..];
+    }
+    name += rest;
+    if name.is_empty() {
+        return Err("the naming template makes an empty name".to_owned());
+    }
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(format!(
+            "the naming template makes {:?}, which isn't a file name",
+            name
+        ));
+    }
+    Ok(name)
+}
+
+/// `<output>.bak`, or `<output>.bak2` and so on if that's taken, so no
+/// backup is ever written over.
+fn backup_path(output: &Path) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut backup = output.to_owned().into_os_string();
+            backup.push(if n == 1 {
+                ".bak".to_owned()
+            } else {
+                format!(".bak{}", n)
+            });
+            PathBuf::from(backup)
+        })
+        .find(|backup| !backup.exists())
+        .expect("there's always a free name")
+}
+
+/// `path` as seen from `folder`, with as many `..` as it takes. Both have to
+/// exist; if either doesn't, `path` as it is.
+fn relative(path: &Path, folder: &Path) -> PathBuf {
+    let (Ok(path), Ok(folder)) = (path.canonicalize(), folder.canonicalize()) else {
+        return path.to_owned();
+    };
+    let path: Vec<Component> = path.components().collect();
+    let folder: Vec<Component> = folder.components().collect();
+    let common = path.iter().zip(&folder).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..folder.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+/// Fills in `result` as it goes, so a failure still shows how far it got.
+fn write_converted(
+    input: &Path,
+    settings: &Settings,
+    result: &mut FileResult,
+) -> Result<(), Box<dyn Error>> {
+    let from = dialect_for(input);
+    let output = output_path(input, &result.base, settings)?;
+    result.output = Some(output.clone());
+    let exists = output.exists();
+    if exists {
+        let same = |a: &Path, b: &Path| a.canonicalize().ok() == b.canonicalize().ok();
+        if same(&output, input) {
+            return Err("the output would be written over it".into());
+        }
+        match settings.if_exists {
+            IfExists::Ask => {
+                result.status = Status::Exists;
+                return Ok(());
+            }
+            IfExists::Skip => {
+                result.status = Status::Skipped("the output is already there".to_owned());
+                return Ok(());
+            }
+            IfExists::Overwrite | IfExists::Backup => {}
+        }
+    }
+    let bytes = fs::read(input).map_err(|e| format!("can't read it: {}", e))?;
+    result.input_size = Some(bytes.len());
+    let source = String::from_utf8(bytes).map_err(|e| {
+        format!(
+            "it isn't UTF-8 text (byte {} isn't)",
+            e.utf8_error().valid_up_to()
+        )
+    })?;
+    let conversion = convert(&source, from, settings.options);
+    result.tokens = conversion.tokens.len();
+    if let Some(folder) = output
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+    {
+        fs::create_dir_all(folder)
+            .map_err(|e| format!("can't make {}: {}", folder.display(), e))?;
+    }
+    if exists && settings.if_exists == IfExists::Backup {
+        fs::rename(&output, backup_path(&output))
+            .map_err(|e| format!("can't move the old output out of the way: {}", e))?;
+    }
+    fs::write(&output, &conversion.text).map_err(|e| format!("can't write it: {}", e))?;
+    result.output_size = Some(conversion.text.len());
+    if settings.source_maps {
+        let name = |path: &Path| {
+            path.file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+        };
+        let folder = output.parent().unwrap_or(Path::new(""));
+        let source = if input.parent() == output.parent() {
+            name(input)
+        } else {
+            relative(input, folder).display().to_string()
+        };
+        let map = SourceMap {
+            source,
+            destination: name(&output),
+            tokens: conversion.tokens,
+        };
+        let mut map_path = output.into_os_string();
+        map_path.push(".map");
+        map.save(Path::new(&map_path))
+            .map_err(|e| format!("can't write the source map: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_file, Batch, IfExists, Settings, Status};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// An empty folder of its own for each test.
+    fn folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("bf2bf-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn backing_up() -> Settings {
+        Settings {
+            if_exists: IfExists::Backup,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn keeps_outputs_in_their_folder() {
+        let folder = folder("template");
+        let cases = [
+            ("prog.bf", "../{stem}.bf2"),
+            ("prog.bf", "sub/{stem}"),
+            ("prog.bf", "..\\{stem}"),
+            ("prog.bf", ".."),
+            // Its stem is `..`.
+            ("...", "{stem}"),
+        ];
+        for (name, template) in cases {
+            let input = folder.join(name);
+            fs::write(&input, "+").unwrap();
+            let settings = Settings {
+                template: template.to_owned(),
+                ..Settings::default()
+            };
+            let result = convert_file(&input, &folder, &settings);
+            assert!(matches!(result.status, Status::Failed(_)), "{}", template);
+        }
+    }
+
+    #[test]
+    fn fails_the_second_file_with_the_same_output() {
+        let folder = folder("same-output");
+        fs::write(folder.join("prog.bf"), "+").unwrap();
+        fs::write(folder.join("prog.b"), "-").unwrap();
+        let mut batch = Batch::new(Settings::default());
+        let first = batch.convert(&folder.join("prog.b"), &folder);
+        let second = batch.convert(&folder.join("prog.bf"), &folder);
+        assert_eq!(first.status, Status::Converted);
+        assert!(matches!(second.status, Status::Failed(_)));
+        assert_eq!(fs::read_to_string(folder.join("prog.bf2")).unwrap(), "hail");
+    }
+
+    #[test]
+    fn keeps_failing_the_second_file_when_retried() {
+        let folder = folder("same-output-retry");
+        fs::write(folder.join("prog.bf"), "+").unwrap();
+        fs::write(folder.join("prog.b"), "-").unwrap();
+        let mut batch = Batch::new(Settings::default());
+        batch.convert(&folder.join("prog.b"), &folder);
+        batch.convert(&folder.join("prog.bf"), &folder);
+        let mut retry = batch.with_settings(Settings {
+            if_exists: IfExists::Overwrite,
+            ..Settings::default()
+        });
+        let second = retry.convert(&folder.join("prog.bf"), &folder);
+        assert!(matches!(second.status, Status::Failed(_)));
+        assert_eq!(fs::read_to_string(folder.join("prog.bf2")).unwrap(), "hail");
+        let first = retry.convert(&folder.join("prog.b"), &folder);
+        assert_eq!(first.status, Status::Converted);
+    }
+
+    #[test]
+    fn numbers_backups() {
+        let folder = folder("backups");
+        let input = folder.join("prog.bf");
+        fs::write(&input, "+").unwrap();
+        for old in ["first", "second"] {
+            fs::write(folder.join("prog.bf2"), old).unwrap();
+            let result = convert_file(&input, &folder, &backing_up());
+            assert_eq!(result.status, Status::Converted);
+        }
+        assert_eq!(
+            fs::read_to_string(folder.join("prog.bf2.bak")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs::read_to_string(folder.join("prog.bf2.bak2")).unwrap(),
+            "second"
+        );
+        assert_eq!(fs::read_to_string(folder.join("prog.bf2")).unwrap(), "5");
+    }
+
+    #[test]
+    fn keeps_the_old_output_when_converting_fails() {
+        let folder = folder("failed-backup");
+        let input = folder.join("prog.bf");
+        fs::write(&input, [0xff]).unwrap();
+        fs::write(folder.join("prog.bf2"), "old").unwrap();
+        let result = convert_file(&input, &folder, &backing_up());
+        assert!(matches!(result.status, Status::Failed(_)));
+        assert_eq!(fs::read_to_string(folder.join("prog.bf2")).unwrap(), "old");
+        assert!(!folder.join("prog.bf2.bak").exists());
+    }
+}
@@ -0,0 +1,8 @@
+//! The conversion itself, shared by the window (`bf2bf`) and the command
+//! line (`bf2bf-cli`). They're separate programs because release builds of
+//! the window on Windows have no console to print to or ask from.
+
+pub mod cli;
+pub mod convert;
+pub mod discover;
+pub mod files;
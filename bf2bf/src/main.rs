@@ -1,13 +1,37 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use bf2bf::convert::Separator;
+use bf2bf::discover::{discover, Filter};
+use bf2bf::files::{Batch, FileResult, IfExists, Settings, Status};
 use eframe::egui::*;
 use itertools::Itertools;
-use std::error::Error;
+use std::collections::HashSet;
 use std::fmt::Write;
-use std::fs;
-use std::fs::File;
-use std::io::Write as _;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+const SEPARATORS: [(Separator, &str); 3] = [
+    (Separator::None, "nothing"),
+    (Separator::Space, "a space"),
+    (Separator::Newline, "a new line"),
+];
+
+const IF_EXISTS: [(IfExists, &str); 4] = [
+    (IfExists::Ask, "ask"),
+    (IfExists::Skip, "skip it"),
+    (IfExists::Overwrite, "overwrite it"),
+    (IfExists::Backup, "keep a backup"),
+];
+
+/// What to do about an output that's already there, from the buttons.
+const ANSWERS: [(IfExists, &str); 3] = [
+    (IfExists::Overwrite, "Overwrite"),
+    (IfExists::Backup, "Keep backup"),
+    (IfExists::Skip, "Skip"),
+];
 
 #[derive(Clone)]
 struct MyDroppedFile {
@@ -20,11 +44,36 @@ impl PartialEq for MyDroppedFile {
     }
 }
 
+/// A file found in a dropped folder, waiting for the go-ahead.
+struct Found {
+    input: PathBuf,
+    /// The folder that was dropped.
+    base: PathBuf,
+    convert: bool,
+}
+
+/// Files being converted in the background.
+struct Run {
+    results: Receiver<FileResult>,
+    /// The files it hasn't finished with yet.
+    inputs: HashSet<PathBuf>,
+    total: usize,
+    done: usize,
+    stop: Arc<AtomicBool>,
+}
+
 #[derive(Default)]
 struct Bf2Bf {
     dropped_files: Vec<MyDroppedFile>,
     picked_path: Option<String>,
-    converted: bool,
+    settings: Settings,
+    filter: Filter,
+    /// The latest result for every file converted so far.
+    results: Vec<FileResult>,
+    found: Vec<Found>,
+    runs: Vec<Run>,
+    /// Which file each output belongs to, for as long as they're listed.
+    batch: Batch,
 }
 
 impl Bf2Bf {
@@ -36,62 +85,239 @@ impl Bf2Bf {
         }
     }
 
-    fn write_bf2_file(&mut self, file: String) -> Result<(), Box<dyn Error>> {
-        let mut output_path = PathBuf::from(file.clone());
-        output_path.set_extension("bf2");
-        let mut output_program = File::create(output_path)?;
-        let program_code: String = fs::read_to_string(file)?;
-        for c in program_code.chars() {
-            match c {
-                '>' => {
-                    write!(output_program, "kallisti")?;
-                }
-                '<' => {
-                    write!(output_program, "fnord")?;
+    /// Keeps `result`, in place of any earlier one for the same file.
+    fn record(&mut self, result: FileResult) {
+        match self
+            .results
+            .iter_mut()
+            .find(|old| old.input == result.input)
+        {
+            Some(old) => *old = result,
+            None => self.results.push(result),
+        }
+    }
+
+    /// Converts files straight away, and looks through folders for files to
+    /// ask about. A file on its own goes straight in the output folder.
+    fn take(&mut self, ctx: &Context, paths: Vec<PathBuf>) {
+        let mut jobs = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let (files, problems) = discover(&path, &self.filter);
+                for problem in problems {
+                    self.record(problem);
                 }
-                '+' => {
-                    write!(output_program, "5")?;
+                if files.is_empty() {
+                    self.record(FileResult::skipped(&path, "nothing in it matches"));
                 }
-                '-' => {
-                    write!(output_program, "hail")?;
+                self.found.extend(files.into_iter().map(|input| Found {
+                    input,
+                    base: path.clone(),
+                    convert: true,
+                }));
+            } else {
+                let base = path.parent().unwrap_or(Path::new("")).to_owned();
+                jobs.push((path, base));
+            }
+        }
+        self.start(ctx, jobs, self.settings.clone());
+    }
+
+    fn process(&mut self, ctx: &Context) {
+        let mut paths = Vec::new();
+        for mdf in std::mem::take(&mut self.dropped_files) {
+            match mdf.dropped_file.path {
+                Some(path) => paths.push(path),
+                None => self.record(FileResult::skipped(
+                    Path::new(&mdf.dropped_file.name),
+                    "it was dropped without a path",
+                )),
+            }
+        }
+        if let Some(picked) = self.picked_path.take() {
+            paths.push(PathBuf::from(picked));
+        }
+        self.take(ctx, paths);
+    }
+
+    /// Whether a run is still to convert `input`.
+    fn busy(&self, input: &Path) -> bool {
+        self.runs.iter().any(|run| run.inputs.contains(input))
+    }
+
+    /// Converts each input, found in its base folder, on another thread,
+    /// leaving out any a run is converting already.
+    fn start(&mut self, ctx: &Context, jobs: Vec<(PathBuf, PathBuf)>, settings: Settings) {
+        let jobs: Vec<_> = jobs
+            .into_iter()
+            .filter(|(input, _)| !self.busy(input))
+            .collect();
+        if jobs.is_empty() {
+            return;
+        }
+        let (sender, results) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.runs.push(Run {
+            results,
+            inputs: jobs.iter().map(|(input, _)| input.clone()).collect(),
+            total: jobs.len(),
+            done: 0,
+            stop: stop.clone(),
+        });
+        let mut batch = self.batch.with_settings(settings);
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            for (input, base) in jobs {
+                let result = if stop.load(Ordering::Relaxed) {
+                    FileResult::skipped(&input, "stopped before it got to it")
+                } else {
+                    batch.convert(&input, &base)
+                };
+                if sender.send(result).is_err() {
+                    break;
                 }
-                '.' => {
-                    write!(output_program, "pineal")?;
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Picks up what the runs have done since the last frame.
+    fn collect(&mut self) {
+        let mut results = Vec::new();
+        self.runs.retain_mut(|run| loop {
+            match run.results.try_recv() {
+                Ok(result) => {
+                    run.done += 1;
+                    run.inputs.remove(&result.input);
+                    results.push(result);
                 }
-                ',' => {
-                    write!(output_program, "chaos")?;
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => break false,
+            }
+        });
+        for result in results {
+            self.record(result);
+        }
+    }
+
+    /// The list of files found in folders, to say which to convert.
+    fn confirm(&mut self, ctx: &Context, ui: &mut Ui) {
+        let ticked = self.found.iter().filter(|found| found.convert).count();
+        ui.group(|ui| {
+            ui.label(format!(
+                "Found {} files, {} of them ticked:",
+                self.found.len(),
+                ticked
+            ));
+            ScrollArea::vertical()
+                .id_source("found")
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for found in &mut self.found {
+                        let name = found
+                            .input
+                            .strip_prefix(&found.base)
+                            .unwrap_or(&found.input);
+                        let label =
+                            Path::new(found.base.file_name().unwrap_or_default()).join(name);
+                        ui.checkbox(&mut found.convert, label.display().to_string());
+                    }
+                });
+            ui.horizontal(|ui| {
+                if ui.button(format!("Convert {}", ticked)).clicked() {
+                    let jobs = std::mem::take(&mut self.found)
+                        .into_iter()
+                        .filter(|found| found.convert)
+                        .map(|found| (found.input, found.base))
+                        .collect();
+                    self.start(ctx, jobs, self.settings.clone());
                 }
-                '[' => {
-                    write!(output_program, "23")?;
+                let all = ticked < self.found.len();
+                if ui
+                    .button(if all { "Tick all" } else { "Untick all" })
+                    .clicked()
+                {
+                    for found in &mut self.found {
+                        found.convert = all;
+                    }
                 }
-                ']' => {
-                    write!(output_program, "eris")?;
+                if ui.button("Cancel").clicked() {
+                    self.found.clear();
                 }
-                _ => {}
-            }
-        }
-        Ok(())
+            });
+        });
     }
 
-    fn process(&mut self) -> Result<(), Box<dyn Error>> {
-        for mdf in self.dropped_files.clone() {
-            self.write_bf2_file(
-                mdf.dropped_file
-                    .path
-                    .as_ref()
-                    .unwrap()
-                    .as_path()
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-            )?;
-        }
-        if let Some(picked) = &self.picked_path {
-            self.write_bf2_file(picked.clone())?;
+    /// Converts the files whose status is `wanted` again in the background,
+    /// doing what `if_exists` says if their output is already there and
+    /// that's given.
+    fn retry(
+        &mut self,
+        ctx: &Context,
+        wanted: impl Fn(usize, &Status) -> bool,
+        if_exists: Option<IfExists>,
+    ) {
+        let mut settings = self.settings.clone();
+        settings.if_exists = if_exists.unwrap_or(settings.if_exists);
+        let jobs = self
+            .results
+            .iter()
+            .enumerate()
+            .filter(|(i, result)| wanted(*i, &result.status))
+            .map(|(_, result)| (result.input.clone(), result.base.clone()))
+            .collect();
+        self.start(ctx, jobs, settings);
+    }
+
+    fn results_table(&mut self, ctx: &Context, ui: &mut Ui) {
+        let mut retry = Vec::new();
+        Grid::new("results").striped(true).show(ui, |ui| {
+            for heading in ["File", "Status", "Output", "Bytes", "Commands", ""] {
+                ui.strong(heading);
+            }
+            ui.end_row();
+            for (i, result) in self.results.iter().enumerate() {
+                ui.monospace(result.input.display().to_string());
+                match &result.status {
+                    Status::Converted => ui.label("converted"),
+                    Status::Exists => ui.label("the output is already there"),
+                    Status::Skipped(reason) => ui.label(format!("skipped: {}", reason)),
+                    Status::Failed(reason) => {
+                        ui.colored_label(Color32::RED, format!("failed: {}", reason))
+                    }
+                };
+                match &result.output {
+                    Some(output) => ui.monospace(output.display().to_string()),
+                    None => ui.label(""),
+                };
+                match (result.input_size, result.output_size) {
+                    (Some(input), Some(output)) => ui.label(format!("{} → {}", input, output)),
+                    (Some(input), None) => ui.label(input.to_string()),
+                    _ => ui.label(""),
+                };
+                ui.label(result.tokens.to_string());
+                match result.status {
+                    _ if self.busy(&result.input) => {
+                        ui.label("converting...");
+                    }
+                    Status::Failed(_) if ui.button("Retry").clicked() => retry.push((i, None)),
+                    Status::Exists => {
+                        ui.horizontal(|ui| {
+                            for (if_exists, name) in ANSWERS {
+                                if ui.button(name).clicked() {
+                                    retry.push((i, Some(if_exists)));
+                                }
+                            }
+                        });
+                    }
+                    _ => {}
+                }
+                ui.end_row();
+            }
+        });
+        for (row, if_exists) in retry {
+            self.retry(ctx, |i, _| i == row, if_exists);
         }
-        self.dropped_files = Vec::new();
-        self.picked_path = None;
-        Ok(())
     }
 }
 
@@ -99,39 +325,148 @@ impl eframe::App for Bf2Bf {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         CentralPanel::default().show(ctx, |ui| {
             ui.heading("Drag and drop a brainfuck program to convert to brainFNORD2");
-            if ui.button("Open file...").clicked() {
-                if let Some(path) = rfd::FileDialog::new().pick_file() {
-                    self.picked_path = Some(path.display().to_string());
+            ui.label("(or a .bf2 one to convert it back)");
+            ui.horizontal(|ui| {
+                let settings = &mut self.settings;
+                ui.checkbox(&mut settings.options.comments, "Keep comments");
+                ui.checkbox(&mut settings.options.keep_lines, "Keep line breaks");
+                ui.checkbox(&mut settings.source_maps, "Write source maps");
+            });
+            ui.horizontal(|ui| {
+                let options = &mut self.settings.options;
+                let separator = &mut options.separator;
+                let name = SEPARATORS
+                    .iter()
+                    .find(|(option, _)| option == separator)
+                    .map_or("", |(_, name)| name);
+                ComboBox::from_label("between commands")
+                    .selected_text(name)
+                    .show_ui(ui, |ui| {
+                        for (option, name) in SEPARATORS {
+                            ui.selectable_value(separator, option, name);
+                        }
+                    });
+                optional_number(ui, "Wrap at column", &mut options.max_width, 80);
+                optional_number(ui, "Indent loops by", &mut options.indent, 2);
+            });
+            ui.horizontal(|ui| {
+                let settings = &mut self.settings;
+                ui.label("Write to");
+                match &settings.output_dir {
+                    Some(output_dir) => {
+                        ui.monospace(output_dir.display().to_string());
+                        if ui.button("Next to the inputs").clicked() {
+                            settings.output_dir = None;
+                        }
+                    }
+                    None => {
+                        ui.label("the input's folder");
+                    }
                 }
+                if ui.button("Choose folder...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                        settings.output_dir = Some(path);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let settings = &mut self.settings;
+                ui.label("Name outputs");
+                ui.text_edit_singleline(&mut settings.template)
+                    .on_hover_text("{stem}, {ext} and {dialect_ext} get filled in");
+                let if_exists = &mut settings.if_exists;
+                let name = IF_EXISTS
+                    .iter()
+                    .find(|(option, _)| option == if_exists)
+                    .map_or("", |(_, name)| name);
+                ComboBox::from_label("if it's already there")
+                    .selected_text(name)
+                    .show_ui(ui, |ui| {
+                        for (option, name) in IF_EXISTS {
+                            ui.selectable_value(if_exists, option, name);
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                let filter = &mut self.filter;
+                ui.label("In folders, convert");
+                ui.text_edit_singleline(&mut filter.include)
+                    .on_hover_text("file name patterns like *.bf, or paths like lib/**/*.b");
+                ui.label("but not");
+                ui.text_edit_singleline(&mut filter.exclude);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Open file...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new().pick_file() {
+                        self.picked_path = Some(path.display().to_string());
+                    }
+                }
+                if ui.button("Open folder...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                        self.picked_path = Some(path.display().to_string());
+                    }
+                }
+            });
+            if self.picked_path.is_some() {
+                self.process(ctx);
             }
-            if let Some(picked_path) = &self.picked_path {
+            if !self.found.is_empty() {
+                self.confirm(ctx, ui);
+            }
+            self.collect();
+            if !self.runs.is_empty() {
+                let total: usize = self.runs.iter().map(|run| run.total).sum();
+                let done: usize = self.runs.iter().map(|run| run.done).sum();
                 ui.horizontal(|ui| {
-                    ui.label("Picked file:");
-                    ui.monospace(picked_path);
+                    ui.add(
+                        ProgressBar::new(done as f32 / total as f32)
+                            .text(format!("converted {} of {}", done, total)),
+                    );
+                    if ui.button("Stop").clicked() {
+                        for run in &self.runs {
+                            run.stop.store(true, Ordering::Relaxed);
+                        }
+                    }
                 });
-                self.process().ok();
-                self.converted = true;
             }
-            if !self.dropped_files.is_empty() {
-                ui.group(|ui| {
-                    ui.label("Converted files:");
-                    for file in &self.dropped_files {
-                        let mut info = if let Some(path) = &file.dropped_file.path {
-                            path.display().to_string()
-                        } else if !file.dropped_file.name.is_empty() {
-                            file.dropped_file.name.clone()
-                        } else {
-                            "???".to_owned()
-                        };
-                        if let Some(bytes) = &file.dropped_file.bytes {
-                            write!(info, " ({} bytes)", bytes.len()).ok();
+            if !self.results.is_empty() {
+                let count = |wanted: fn(&Status) -> bool| {
+                    self.results
+                        .iter()
+                        .filter(|result| wanted(&result.status))
+                        .count()
+                };
+                let failed = count(|status| matches!(status, Status::Failed(_)));
+                let exists = count(|status| *status == Status::Exists);
+                let summary = format!(
+                    "{} converted, {} skipped, {} failed, {} already there",
+                    count(|status| *status == Status::Converted),
+                    count(|status| matches!(status, Status::Skipped(_))),
+                    failed,
+                    exists
+                );
+                ui.horizontal(|ui| {
+                    ui.label(summary);
+                    if failed > 0 && ui.button("Retry failed").clicked() {
+                        self.retry(ctx, |_, status| matches!(status, Status::Failed(_)), None);
+                    }
+                    if exists > 0 {
+                        for (if_exists, name) in ANSWERS {
+                            if ui.button(format!("{} all", name)).clicked() {
+                                self.retry(
+                                    ctx,
+                                    |_, status| *status == Status::Exists,
+                                    Some(if_exists),
+                                );
+                            }
                         }
-                        ui.label(info);
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.results.clear();
+                        self.batch = Batch::default();
                     }
                 });
-            }
-            if self.converted {
-                ui.label("Converted!");
+                ScrollArea::both().show(ui, |ui| self.results_table(ctx, ui));
             }
         });
         preview_files_being_dropped(ctx);
@@ -142,11 +477,21 @@ impl eframe::App for Bf2Bf {
                 .clone()
                 .into_iter()
                 .unique_by(|f| f.clone().dropped_file.path)
-                .filter(|f| !f.clone().dropped_file.path.unwrap().is_dir())
                 .collect();
-            self.process().ok();
-            self.converted = true;
+            self.process(ctx);
+        }
+    }
+}
+
+fn optional_number(ui: &mut Ui, label: &str, number: &mut Option<usize>, default: usize) {
+    let mut enabled = number.is_some();
+    ui.checkbox(&mut enabled, label);
+    match (enabled, number.as_mut()) {
+        (true, Some(value)) => {
+            ui.add(DragValue::new(value).clamp_range(1..=usize::MAX));
         }
+        (true, None) => *number = Some(default),
+        (false, _) => *number = None,
     }
 }
 
@@ -0,0 +1,158 @@
+use bf::codegen::rust::function;
+use bf::config::{parse_headers, Config};
+use bf::ir::optimise;
+use bf::ops::{Dialect, ParseError, Program};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Compiles Brainfuck to a `fn(&[u8]) -> Vec<u8>` that runs it on its input
+/// and returns the output.
+///
+/// Takes the program either as tokens, `bf! { ++[>+<-]>. }`, or as one string
+/// literal, which may start with `# key: value` headers like a `.bf` file:
+/// `bf!("# cell_width: 16\n,[.,]")`.
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    expand(input, Dialect::Brainfuck)
+}
+
+/// `bf!` for BrainFNORD2: `bf2! { 5 5 23 kallisti 5 fnord hail eris kallisti pineal }`.
+#[proc_macro]
+pub fn bf2(input: TokenStream) -> TokenStream {
+    expand(input, Dialect::BrainFnord2)
+}
+
+/// The program text rebuilt from the tokens, and where each token starts in
+/// it, so parse errors can point at the token.
+#[derive(Default)]
+struct Text {
+    source: String,
+    spans: Vec<(usize, Span)>,
+}
+
+impl Text {
+    fn push(&mut self, piece: &str, span: Span) {
+        self.spans.push((self.source.len(), span));
+        self.source += piece;
+        self.source.push(' ');
+    }
+
+    fn flatten(&mut self, tokens: TokenStream) {
+        for token in tokens {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.flatten(group.stream());
+                    self.push(close, group.span_close());
+                }
+                TokenTree::Punct(punct) => self.push(&punct.as_char().to_string(), punct.span()),
+                token => self.push(&token.to_string(), token.span()),
+            }
+        }
+    }
+
+    fn span_at(&self, position: usize) -> Span {
+        let index = self.spans.partition_point(|(start, _)| *start <= position);
+        self.spans[index.saturating_sub(1)].1
+    }
+}
+
+/// Whether `literal` is a plain or raw string, with any number of `#`s.
+fn is_string(literal: &str) -> bool {
+    let raw = literal.strip_prefix('r').unwrap_or(literal);
+    raw.trim_start_matches('#').starts_with('"')
+}
+
+/// The value of a string literal as written in the source, or `None` if it
+/// isn't a plain or raw string or uses escapes this doesn't handle.
+fn string_value(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return raw
+            .get(hashes + 1..raw.len().checked_sub(hashes + 1)?)
+            .map(str::to_owned);
+    }
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            c @ ('\\' | '"' | '\'') => value.push(c),
+            '\n' => chars = chars.as_str().trim_start().chars(),
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn error(message: &str, span: Span) -> TokenStream {
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::from(literal).into());
+    arguments.set_span(span);
+    [
+        TokenTree::from(Ident::new("compile_error", span)),
+        bang.into(),
+        arguments.into(),
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn expand(input: TokenStream, dialect: Dialect) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.clone().into_iter().collect();
+    let (program, config) = match tokens.as_slice() {
+        [TokenTree::Literal(literal)] if is_string(&literal.to_string()) => {
+            let span = literal.span();
+            let Some(text) = string_value(&literal.to_string()) else {
+                return error("can't read this string literal, try a raw string", span);
+            };
+            let headers = match parse_headers(&text) {
+                Ok(headers) => headers,
+                Err(e) => return error(&e.to_string(), span),
+            };
+            match Program::parse(&headers.source, dialect) {
+                Ok(program) => (program, headers.config),
+                Err(e) => return error(&e.to_string(), span),
+            }
+        }
+        _ => {
+            // rustc won't tokenize unbalanced brackets, so only `bf2!` gets
+            // here with loops that don't match, but then the error can point
+            // at the `23` or `eris` itself.
+            let mut text = Text::default();
+            text.flatten(input);
+            match Program::parse(&text.source, dialect) {
+                Ok(program) => (program, Config::default()),
+                Err(ParseError::UnmatchedLoopEnd { position }) => {
+                    return error(
+                        "loop end has no matching loop start",
+                        text.span_at(position),
+                    )
+                }
+                Err(ParseError::UnclosedLoopStart { position }) => {
+                    return error("loop start is never closed", text.span_at(position))
+                }
+            }
+        }
+    };
+    let function = function(&optimise(&program), config, None);
+    format!("{{\n{}run as fn(&[u8]) -> Vec<u8>\n}}", function)
+        .parse()
+        .expect("generated code should be valid Rust")
+}
@@ -0,0 +1,9 @@
+#[test]
+fn compile() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/hello.rs");
+    cases.pass("tests/ui/raw.rs");
+    cases.compile_fail("tests/ui/unclosed.rs");
+    cases.compile_fail("tests/ui/unmatched.rs");
+    cases.compile_fail("tests/ui/unmatched_token.rs");
+}
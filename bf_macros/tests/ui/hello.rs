@@ -0,0 +1,6 @@
+fn main() {
+    let hello = bf_macros::bf! { ++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>. };
+    assert_eq!(hello(b""), b"H");
+    let echo = bf_macros::bf2!("# eof: zero\nchaos 23 pineal chaos eris");
+    assert_eq!(echo(b"hi"), b"hi");
+}
@@ -0,0 +1,9 @@
+fn main() {
+    // 256 is only nonzero in a 16-bit cell, so this prints Y there and
+    // nothing with the default 8-bit cells.
+    let y = bf_macros::bf!(
+        r#"# cell_width: 16
+++++++++++++++++[>++++++++++++++++<-]>[[-]>++++++++[>+++++++++++<-]>+.[-]]"#
+    );
+    assert_eq!(y(b""), b"Y");
+}
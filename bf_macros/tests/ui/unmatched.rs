@@ -0,0 +1,3 @@
+fn main() {
+    let _ = bf_macros::bf2!("eris");
+}